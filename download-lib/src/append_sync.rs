@@ -0,0 +1,150 @@
+use super::error::{DownloadError, Result};
+use super::options::DownloadOptions;
+use super::range_file::fetch_range;
+use super::transport::{transport_for_url, ITransport, IntoDownloadUrl, RemoteInfo};
+use super::{DownloadFile, DownloadInner, FileSave};
+use reqwest::Url;
+use std::io::SeekFrom;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio::time::sleep;
+
+impl DownloadFile {
+    /// start sync growing remote file now,
+    /// local file tail overlap size must same as remote,
+    /// only download new bytes and append to local file
+    #[inline]
+    pub async fn start_sync_append<U: IntoDownloadUrl>(
+        url: U,
+        save_path: PathBuf,
+        overlap: u64,
+        task_count: u64,
+        block: u64,
+    ) -> Result<Self> {
+        let (url, transport, info, save_file, local_len) =
+            Self::prepare_sync_append(url, save_path, overlap).await?;
+        let size = info.size;
+        let file = Self::new(
+            url,
+            transport,
+            size,
+            vec![Range {
+                start: local_len,
+                end: size,
+            }],
+            save_file,
+            task_count,
+            block,
+        );
+        // remote file is growing,not check it changed
+        file.inner_status
+            .check_change
+            .store(false, Ordering::Release);
+        file.start_now(info).await
+    }
+
+    /// check local file can append,return remote size and local file size
+    #[inline]
+    async fn prepare_sync_append<U: IntoDownloadUrl>(
+        url: U,
+        save_path: PathBuf,
+        overlap: u64,
+    ) -> Result<(Url, Arc<dyn ITransport>, RemoteInfo, FileSave, u64)> {
+        let url = url.into_download_url()?;
+        let transport = transport_for_url(&url);
+        // poll size without download whole file
+        let info = transport.probe_head(&url).await?;
+        let size = info.size;
+        let save_path = Self::get_save_path(&url, save_path, info.file_name.clone())?;
+        let local_len = match tokio::fs::metadata(&save_path).await {
+            Ok(metadata) => metadata.len(),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => 0,
+            Err(err) => return Err(err.into()),
+        };
+        log::trace!(
+            "sync append url:{} remote size:{} local file:{:?} size:{}",
+            url,
+            size,
+            save_path,
+            local_len
+        );
+        if local_len > size {
+            return Err(DownloadError::AppendMismatch(url));
+        }
+        let overlap = overlap.min(local_len);
+        if overlap > 0 {
+            let inner_status = Arc::new(DownloadInner::new(
+                url.clone(),
+                transport.clone(),
+                size,
+                overlap,
+            ));
+            inner_status.check_change.store(false, Ordering::Release);
+            inner_status.is_start.store(true, Ordering::Release);
+            let remote_tail = fetch_range(inner_status, local_len - overlap, local_len - 1).await?;
+            let mut local_tail = vec![0; overlap as usize];
+            let mut file = tokio::fs::File::open(&save_path).await?;
+            file.seek(SeekFrom::Start(local_len - overlap)).await?;
+            file.read_exact(&mut local_tail).await?;
+            if remote_tail[..] != local_tail[..] {
+                return Err(DownloadError::AppendMismatch(url));
+            }
+        }
+        let save_file = FileSave::open_append(save_path, local_len);
+        Ok((url, transport, info, save_file, local_len))
+    }
+}
+
+/// sync growing remote file once,
+/// only download new bytes and append to local file,return append size
+#[inline]
+pub async fn sync_append<U: IntoDownloadUrl>(
+    url: U,
+    save_path: PathBuf,
+    options: &DownloadOptions,
+) -> Result<u64> {
+    let (url, transport, info, save_file, local_len) =
+        DownloadFile::prepare_sync_append(url, save_path, options.append_overlap).await?;
+    let size = info.size;
+    let download = DownloadFile::new(
+        url,
+        transport,
+        size,
+        vec![Range {
+            start: local_len,
+            end: size,
+        }],
+        save_file,
+        options.task_count,
+        options.block,
+    );
+    download
+        .inner_status
+        .check_change
+        .store(false, Ordering::Release);
+    download.run_now(info).await?;
+    Ok(download.size())
+}
+
+/// keep sync growing remote file,poll remote file every interval,
+/// return when error
+#[inline]
+pub async fn follow_append<U: IntoDownloadUrl>(
+    url: U,
+    save_path: PathBuf,
+    options: &DownloadOptions,
+    interval: Duration,
+) -> Result<()> {
+    let url = url.into_download_url()?;
+    loop {
+        let len = sync_append(url.clone(), save_path.clone(), options).await?;
+        if len > 0 {
+            log::info!("url:{} append {} bytes to {:?}", url, len, save_path);
+        }
+        sleep(interval).await;
+    }
+}
@@ -0,0 +1,121 @@
+use super::error::{DownloadError, Result};
+use std::ops::{Range, RangeFrom};
+use std::str::FromStr;
+
+/// byte range of remote file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteRange {
+    /// start..end,end is excluded
+    Range(u64, u64),
+    /// start to end of file
+    From(u64),
+    /// last bytes of file
+    Last(u64),
+}
+
+impl ByteRange {
+    /// resolve to file offset range by file size,end is clip to file size
+    #[inline]
+    pub fn resolve(&self, size: u64) -> Result<Range<u64>> {
+        let range = match *self {
+            ByteRange::Range(start, end) => start..end.min(size),
+            ByteRange::From(start) => start..size,
+            ByteRange::Last(len) => size.saturating_sub(len)..size,
+        };
+        if range.start > range.end || range.start > size {
+            Err(DownloadError::InvalidRange(format!(
+                "{:?} not satisfiable of size:{}",
+                self, size
+            )))
+        } else {
+            Ok(range)
+        }
+    }
+
+    /// parse http style range list,like "0-99,200-,-50"
+    #[inline]
+    pub fn parse_list(value: &str) -> Result<Vec<ByteRange>> {
+        value.split(',').map(|x| x.trim().parse()).collect()
+    }
+}
+
+/// parse http style range,"a-b" end is included,"a-" is from a,"-n" is last n bytes
+impl FromStr for ByteRange {
+    type Err = DownloadError;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let err = || DownloadError::InvalidRange(value.to_string());
+        let (start, end) = value.trim().split_once('-').ok_or_else(err)?;
+        let parse = |x: &str| x.trim().parse::<u64>().map_err(|_| err());
+        match (start.trim().is_empty(), end.trim().is_empty()) {
+            (false, false) => {
+                let (start, end) = (parse(start)?, parse(end)?);
+                if start > end {
+                    return Err(err());
+                }
+                Ok(ByteRange::Range(start, end.checked_add(1).ok_or_else(err)?))
+            }
+            (false, true) => Ok(ByteRange::From(parse(start)?)),
+            (true, false) => Ok(ByteRange::Last(parse(end)?)),
+            (true, true) => Err(err()),
+        }
+    }
+}
+
+impl From<Range<u64>> for ByteRange {
+    #[inline]
+    fn from(value: Range<u64>) -> Self {
+        ByteRange::Range(value.start, value.end)
+    }
+}
+
+impl From<RangeFrom<u64>> for ByteRange {
+    #[inline]
+    fn from(value: RangeFrom<u64>) -> Self {
+        ByteRange::From(value.start)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ByteRange;
+
+    #[test]
+    fn parse_range() {
+        assert_eq!(
+            "0-99".parse::<ByteRange>().unwrap(),
+            ByteRange::Range(0, 100)
+        );
+        assert_eq!(" 200- ".parse::<ByteRange>().unwrap(), ByteRange::From(200));
+        assert_eq!("-50".parse::<ByteRange>().unwrap(), ByteRange::Last(50));
+        assert_eq!(
+            ByteRange::parse_list("0-99,200-,-50").unwrap(),
+            vec![
+                ByteRange::Range(0, 100),
+                ByteRange::From(200),
+                ByteRange::Last(50)
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_invalid_range() {
+        for value in ["", "-", "abc", "5-1", "1-x", "10", "0-18446744073709551615"] {
+            assert!(value.parse::<ByteRange>().is_err(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn resolve_range() {
+        assert_eq!(ByteRange::Range(0, 100).resolve(1000).unwrap(), 0..100);
+        assert_eq!(
+            ByteRange::Range(900, 2000).resolve(1000).unwrap(),
+            900..1000
+        );
+        assert_eq!(ByteRange::From(10).resolve(1000).unwrap(), 10..1000);
+        assert_eq!(ByteRange::Last(50).resolve(1000).unwrap(), 950..1000);
+        assert_eq!(ByteRange::Last(5000).resolve(1000).unwrap(), 0..1000);
+        assert!(ByteRange::From(1001).resolve(1000).is_err());
+        assert!(ByteRange::Range(2000, 3000).resolve(1000).is_err());
+    }
+}
@@ -0,0 +1,815 @@
+use super::error::{DownloadError, Result};
+use super::file_save::hash_file;
+use super::http_cache::CachePolicy;
+use super::piece_hash::HashType;
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// content addressed download cache,
+/// file data save to "<dir>/blobs/<sha256>",
+/// url entry save to "<dir>/entries/<sha256 of url>.json",
+/// entries,blobs and stats change under lock of "<dir>/lock"
+#[derive(Debug, Clone)]
+pub struct DownloadCache {
+    dir: PathBuf,
+    /// max total size of blobs,least recently used blobs evict after insert
+    max_size: Option<u64>,
+}
+
+/// cache entry of url
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheEntry {
+    pub url: String,
+    /// remote etag or last modified when cached
+    pub validator: Option<String>,
+    pub size: u64,
+    /// sha256 hex of file data
+    pub sha256: String,
+    /// last access unix time in milliseconds
+    pub last_access: u64,
+    /// http fresh until unix time in milliseconds,
+    /// none is stale,revalidate before use
+    pub expires: Option<u64>,
+}
+
+/// cache statistics
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub entries: u64,
+    pub blobs: u64,
+    /// total size of blobs
+    pub size: u64,
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+}
+
+#[inline]
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// write file by temp file and rename
+#[inline]
+fn write_atomic(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    std::fs::write(&temp, data)?;
+    std::fs::rename(&temp, path)
+}
+
+/// remove file,not exists is ok
+#[inline]
+fn remove_exists(path: &Path) -> std::io::Result<()> {
+    match std::fs::remove_file(path) {
+        Err(err) if err.kind() != ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+/// clone file by copy on write
+#[cfg(target_os = "linux")]
+#[inline]
+fn reflink(from: &Path, to: &Path) -> std::io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+    const FICLONE: libc::c_ulong = 0x40049409;
+    let source = std::fs::File::open(from)?;
+    let target = std::fs::File::create(to)?;
+    if unsafe { libc::ioctl(target.as_raw_fd(), FICLONE as _, source.as_raw_fd()) } != 0 {
+        let err = std::io::Error::last_os_error();
+        drop(target);
+        let _ = std::fs::remove_file(to);
+        return Err(err);
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+#[inline]
+fn reflink(_from: &Path, _to: &Path) -> std::io::Result<()> {
+    Err(ErrorKind::Unsupported.into())
+}
+
+/// place file by reflink or copy,to must not exist,
+/// not hardlink,edit of saved file must not change cache file
+#[inline]
+fn reflink_or_copy(from: &Path, to: &Path) -> std::io::Result<()> {
+    if reflink(from, to).is_ok() {
+        log::trace!("reflink file:{:?} to:{:?}", from, to);
+    } else {
+        std::fs::copy(from, to)?;
+        log::trace!("copy file:{:?} to:{:?}", from, to);
+    }
+    Ok(())
+}
+
+/// modified time of file in nanoseconds
+#[inline]
+fn modified_nanos(metadata: &std::fs::Metadata) -> Option<u128> {
+    metadata
+        .modified()
+        .ok()?
+        .duration_since(UNIX_EPOCH)
+        .ok()
+        .map(|x| x.as_nanos())
+}
+
+impl CacheEntry {
+    #[inline]
+    fn load(path: &Path) -> Result<Self> {
+        let invalid = || {
+            DownloadError::from(std::io::Error::new(
+                ErrorKind::InvalidData,
+                format!("invalid cache entry:{:?}", path),
+            ))
+        };
+        let json = serde_json::from_slice::<serde_json::Value>(&std::fs::read(path)?)
+            .map_err(std::io::Error::from)?;
+        Ok(Self {
+            url: json["url"].as_str().ok_or_else(invalid)?.to_string(),
+            validator: json["validator"].as_str().map(|x| x.to_string()),
+            size: json["size"].as_u64().ok_or_else(invalid)?,
+            sha256: json["sha256"]
+                .as_str()
+                .filter(|x| x.len() == 64 && x.chars().all(|c| c.is_ascii_hexdigit()))
+                .ok_or_else(invalid)?
+                .to_ascii_lowercase(),
+            last_access: json["last_access"].as_u64().unwrap_or_default(),
+            expires: json["expires"].as_u64(),
+        })
+    }
+
+    #[inline]
+    fn save(&self, path: &Path) -> Result<()> {
+        let json = serde_json::json!({
+            "url": self.url,
+            "validator": self.validator,
+            "size": self.size,
+            "sha256": self.sha256,
+            "last_access": self.last_access,
+            "expires": self.expires,
+        });
+        write_atomic(path, json.to_string().as_bytes())?;
+        Ok(())
+    }
+}
+
+impl DownloadCache {
+    /// cache in dir,create dir when insert
+    #[inline]
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            max_size: None,
+        }
+    }
+
+    /// cache of env DURL_CACHE_DIR,max size of env DURL_CACHE_MAX_SIZE,
+    /// none if dir not set
+    #[inline]
+    pub fn from_env() -> Option<Self> {
+        let dir = std::env::var_os("DURL_CACHE_DIR").filter(|dir| !dir.is_empty())?;
+        let cache = Self::new(PathBuf::from(dir));
+        match std::env::var("DURL_CACHE_MAX_SIZE")
+            .ok()
+            .and_then(|x| x.trim().parse::<u64>().ok())
+        {
+            Some(max_size) => Some(cache.max_size(max_size)),
+            None => Some(cache),
+        }
+    }
+
+    /// max total size of cache,evict least recently used file after insert
+    #[inline]
+    pub fn max_size(mut self, max_size: u64) -> Self {
+        self.max_size = Some(max_size);
+        self
+    }
+
+    /// cache dir
+    #[inline]
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// run cache file operation in blocking pool
+    #[inline]
+    pub(crate) async fn blocking<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&DownloadCache) -> Result<T> + Send + 'static,
+    ) -> Result<T> {
+        let cache = self.clone();
+        tokio::task::spawn_blocking(move || f(&cache))
+            .await
+            .map_err(DownloadError::JoinInError)?
+    }
+
+    #[inline]
+    fn blob_path(&self, sha256: &str) -> PathBuf {
+        self.dir.join("blobs").join(sha256)
+    }
+
+    #[inline]
+    fn entry_path(&self, url: &str) -> PathBuf {
+        self.dir
+            .join("entries")
+            .join(format!("{}.json", HashType::Sha256.digest(url.as_bytes())))
+    }
+
+    #[inline]
+    fn stats_path(&self) -> PathBuf {
+        self.dir.join("stats.json")
+    }
+
+    /// modified time of blob when last verified
+    #[inline]
+    fn verified_path(&self, sha256: &str) -> PathBuf {
+        self.blob_path(sha256).with_extension("verified")
+    }
+
+    /// lock cache dir,unlock when drop
+    #[inline]
+    fn lock(&self) -> Result<std::fs::File> {
+        std::fs::create_dir_all(&self.dir)?;
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(self.dir.join("lock"))?;
+        file.lock()?;
+        Ok(file)
+    }
+
+    /// find verified cache file,
+    /// by sha256 if known,or by url entry if remote validator not changed,
+    /// policy is http caching policy of remote response,update fresh time of entry
+    #[inline]
+    pub fn lookup(
+        &self,
+        url: &str,
+        validator: Option<&str>,
+        sha256: Option<&str>,
+        size: u64,
+        policy: &CachePolicy,
+    ) -> Result<Option<CacheEntry>> {
+        let entry_path = self.entry_path(url);
+        let sha256 = match sha256 {
+            Some(sha256) => Some(sha256.to_ascii_lowercase()),
+            None => CacheEntry::load(&entry_path)
+                .ok()
+                .filter(|entry| {
+                    validator.is_some()
+                        && entry.validator.as_deref() == validator
+                        && entry.size == size
+                })
+                .map(|entry| entry.sha256),
+        };
+        let entry = match sha256 {
+            Some(sha256) if !policy.no_store => {
+                self.verify_blob(&sha256, size)?.then(|| CacheEntry {
+                    url: url.to_string(),
+                    validator: validator.map(|x| x.to_string()),
+                    size,
+                    sha256,
+                    last_access: now_millis(),
+                    expires: policy.expires(),
+                })
+            }
+            _ => None,
+        };
+        self.hit(url, entry.as_ref())?;
+        Ok(entry)
+    }
+
+    /// find verified cache file of url still http fresh,use without request
+    #[inline]
+    pub fn lookup_fresh(&self, url: &str) -> Result<Option<CacheEntry>> {
+        let entry = match CacheEntry::load(&self.entry_path(url)) {
+            Ok(entry) if entry.expires.is_some_and(|expires| expires > now_millis()) => entry,
+            _ => return Ok(None),
+        };
+        if !self.verify_blob(&entry.sha256, entry.size)? {
+            return Ok(None);
+        }
+        let entry = CacheEntry {
+            last_access: now_millis(),
+            ..entry
+        };
+        log::trace!("cache of url:{} is fresh", url);
+        self.hit(url, Some(&entry))?;
+        Ok(Some(entry))
+    }
+
+    /// verified cache file of url not fresh but has validator,
+    /// revalidate by conditional request before use
+    #[inline]
+    pub fn lookup_stale(&self, url: &str) -> Result<Option<CacheEntry>> {
+        let entry = match CacheEntry::load(&self.entry_path(url)) {
+            Ok(entry) if entry.validator.is_some() => entry,
+            _ => return Ok(None),
+        };
+        Ok(self
+            .verify_blob(&entry.sha256, entry.size)?
+            .then_some(entry))
+    }
+
+    /// remote not modified,update fresh time of stale entry by policy
+    #[inline]
+    pub fn revalidated(&self, entry: CacheEntry, policy: &CachePolicy) -> Result<CacheEntry> {
+        let entry = CacheEntry {
+            last_access: now_millis(),
+            expires: policy.expires(),
+            ..entry
+        };
+        log::trace!("cache of url:{} is revalidated", entry.url);
+        self.hit(&entry.url, Some(&entry))?;
+        Ok(entry)
+    }
+
+    /// save hit entry and count stats
+    #[inline]
+    fn hit(&self, url: &str, entry: Option<&CacheEntry>) -> Result<()> {
+        let _lock = self.lock()?;
+        match entry {
+            Some(entry) => {
+                std::fs::create_dir_all(self.dir.join("entries"))?;
+                entry.save(&self.entry_path(url))?;
+                self.add_stats(1, 0, 0);
+                log::trace!("cache hit url:{} sha256:{}", url, entry.sha256);
+            }
+            None => {
+                self.add_stats(0, 1, 0);
+                log::trace!("cache miss url:{}", url);
+            }
+        }
+        Ok(())
+    }
+
+    /// read cache file data
+    #[inline]
+    pub fn read(&self, entry: &CacheEntry) -> Result<Vec<u8>> {
+        Ok(std::fs::read(self.blob_path(&entry.sha256))?)
+    }
+
+    /// check blob size,check sha256 only if modified time changed after last verify,
+    /// remove broken blob
+    #[inline]
+    fn verify_blob(&self, sha256: &str, size: u64) -> Result<bool> {
+        let _lock = self.lock()?;
+        let path = self.blob_path(sha256);
+        let modified = match std::fs::metadata(&path) {
+            Ok(metadata) if metadata.len() == size => modified_nanos(&metadata),
+            Ok(_) => return Ok(false),
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(false),
+            Err(err) => return Err(err.into()),
+        };
+        let verified = std::fs::read_to_string(self.verified_path(sha256))
+            .ok()
+            .and_then(|x| x.trim().parse::<u128>().ok());
+        if modified.is_some() && modified == verified {
+            return Ok(true);
+        }
+        self.check_blob(sha256, modified)
+    }
+
+    /// hash blob,record modified time if ok,otherwise remove it,caller hold lock
+    #[inline]
+    fn check_blob(&self, sha256: &str, modified: Option<u128>) -> Result<bool> {
+        let path = self.blob_path(sha256);
+        if hash_file(&path, HashType::Sha256, 0, u64::MAX)? == sha256 {
+            if let Some(modified) = modified {
+                write_atomic(&self.verified_path(sha256), modified.to_string().as_bytes())?;
+            }
+            return Ok(true);
+        }
+        log::warn!("cache file:{:?} is broken,remove it", path);
+        remove_exists(&path)?;
+        remove_exists(&self.verified_path(sha256))?;
+        Ok(false)
+    }
+
+    /// check sha256 of all blobs,remove broken blobs,return removed count
+    #[inline]
+    pub fn verify(&self) -> Result<u64> {
+        let _lock = self.lock()?;
+        let mut removed = 0;
+        for (sha256, _) in self.blobs()? {
+            let modified = std::fs::metadata(self.blob_path(&sha256))
+                .ok()
+                .and_then(|metadata| modified_nanos(&metadata));
+            if !self.check_blob(&sha256, modified)? {
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// place cache file to save path by reflink or copy
+    #[inline]
+    pub fn place(&self, entry: &CacheEntry, save_path: &Path) -> Result<()> {
+        let temp_path = save_path.with_extension("dd");
+        remove_exists(&temp_path)?;
+        // blob not evict or remove while copy
+        let _lock = self.lock()?;
+        reflink_or_copy(&self.blob_path(&entry.sha256), &temp_path)?;
+        std::fs::rename(&temp_path, save_path)?;
+        log::trace!("place cache:{} to:{:?}", entry.sha256, save_path);
+        Ok(())
+    }
+
+    /// add download file to cache,evict if over max size
+    #[inline]
+    pub fn insert(
+        &self,
+        url: &str,
+        validator: Option<&str>,
+        policy: &CachePolicy,
+        file: &Path,
+    ) -> Result<CacheEntry> {
+        let sha256 = hash_file(file, HashType::Sha256, 0, u64::MAX)?;
+        self.insert_blob(url, validator, policy, &sha256, |temp_path| {
+            reflink_or_copy(file, temp_path)
+        })
+    }
+
+    /// add download data to cache,evict if over max size
+    #[inline]
+    pub fn insert_bytes(
+        &self,
+        url: &str,
+        validator: Option<&str>,
+        policy: &CachePolicy,
+        data: &[u8],
+    ) -> Result<CacheEntry> {
+        let sha256 = HashType::Sha256.digest(data);
+        self.insert_blob(url, validator, policy, &sha256, |temp_path| {
+            std::fs::write(temp_path, data)
+        })
+    }
+
+    #[inline]
+    fn insert_blob(
+        &self,
+        url: &str,
+        validator: Option<&str>,
+        policy: &CachePolicy,
+        sha256: &str,
+        write: impl FnOnce(&Path) -> std::io::Result<()>,
+    ) -> Result<CacheEntry> {
+        let _lock = self.lock()?;
+        std::fs::create_dir_all(self.dir.join("blobs"))?;
+        std::fs::create_dir_all(self.dir.join("entries"))?;
+        let blob_path = self.blob_path(sha256);
+        if !blob_path.exists() {
+            let temp_path = blob_path.with_extension("tmp");
+            remove_exists(&temp_path)?;
+            write(&temp_path)?;
+            std::fs::rename(&temp_path, &blob_path)?;
+            // data is hashed before write
+            if let Some(modified) = modified_nanos(&std::fs::metadata(&blob_path)?) {
+                write_atomic(&self.verified_path(sha256), modified.to_string().as_bytes())?;
+            }
+        }
+        let entry = CacheEntry {
+            url: url.to_string(),
+            validator: validator.map(|x| x.to_string()),
+            size: std::fs::metadata(&blob_path)?.len(),
+            sha256: sha256.to_string(),
+            last_access: now_millis(),
+            expires: policy.expires(),
+        };
+        entry.save(&self.entry_path(url))?;
+        log::trace!("cache url:{} sha256:{}", url, entry.sha256);
+        if let Some(max_size) = self.max_size {
+            self.prune_locked(max_size)?;
+        }
+        Ok(entry)
+    }
+
+    /// all url entries
+    #[inline]
+    pub fn entries(&self) -> Result<Vec<CacheEntry>> {
+        let dir = match std::fs::read_dir(self.dir.join("entries")) {
+            Ok(dir) => dir,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(err) => return Err(err.into()),
+        };
+        let mut entries = vec![];
+        for item in dir {
+            let path = item?.path();
+            if path.extension().is_some_and(|x| x == "json") {
+                match CacheEntry::load(&path) {
+                    Ok(entry) => entries.push(entry),
+                    Err(err) => log::warn!("skip cache entry:{:?} error:{}", path, err),
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    /// blob sha256 and size
+    #[inline]
+    fn blobs(&self) -> Result<Vec<(String, u64)>> {
+        let dir = match std::fs::read_dir(self.dir.join("blobs")) {
+            Ok(dir) => dir,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(err) => return Err(err.into()),
+        };
+        let mut blobs = vec![];
+        for item in dir {
+            let item = item?;
+            let name = item.file_name().to_string_lossy().to_string();
+            if name.len() == 64 {
+                blobs.push((name, item.metadata()?.len()));
+            }
+        }
+        Ok(blobs)
+    }
+
+    /// hit,miss and eviction counters of stats file
+    #[inline]
+    fn counters(&self) -> serde_json::Value {
+        std::fs::read(self.stats_path())
+            .ok()
+            .and_then(|data| serde_json::from_slice::<serde_json::Value>(&data).ok())
+            .unwrap_or_default()
+    }
+
+    /// cache statistics,scan all entries and blobs
+    #[inline]
+    pub fn stats(&self) -> Result<CacheStats> {
+        let blobs = self.blobs()?;
+        let counters = self.counters();
+        Ok(CacheStats {
+            entries: self.entries()?.len() as u64,
+            blobs: blobs.len() as u64,
+            size: blobs.iter().map(|(_, size)| size).sum(),
+            hits: counters["hits"].as_u64().unwrap_or_default(),
+            misses: counters["misses"].as_u64().unwrap_or_default(),
+            evictions: counters["evictions"].as_u64().unwrap_or_default(),
+        })
+    }
+
+    /// add counters of stats file,caller hold lock,fail is ignored
+    #[inline]
+    fn add_stats(&self, hits: u64, misses: u64, evictions: u64) {
+        let counters = self.counters();
+        let count = |name: &str| counters[name].as_u64().unwrap_or_default();
+        let json = serde_json::json!({
+            "hits": count("hits") + hits,
+            "misses": count("misses") + misses,
+            "evictions": count("evictions") + evictions,
+        });
+        if let Err(err) = write_atomic(&self.stats_path(), json.to_string().as_bytes()) {
+            log::warn!("write cache stats error:{}", err);
+        }
+    }
+
+    /// evict least recently used files until total size not over max size,
+    /// return freed size
+    #[inline]
+    pub fn prune(&self, max_size: u64) -> Result<u64> {
+        let _lock = self.lock()?;
+        self.prune_locked(max_size)
+    }
+
+    #[inline]
+    fn prune_locked(&self, max_size: u64) -> Result<u64> {
+        let entries = self.entries()?;
+        let mut last_access: HashMap<&str, u64> = HashMap::new();
+        for entry in &entries {
+            let access = last_access.entry(entry.sha256.as_str()).or_default();
+            *access = (*access).max(entry.last_access);
+        }
+        let mut blobs = self.blobs()?;
+        blobs.sort_by_key(|(sha256, _)| {
+            last_access
+                .get(sha256.as_str())
+                .copied()
+                .unwrap_or_default()
+        });
+        let mut size = blobs.iter().map(|(_, size)| size).sum::<u64>();
+        let mut freed = 0;
+        let mut evictions = 0;
+        for (sha256, blob_size) in blobs {
+            if size <= max_size {
+                break;
+            }
+            remove_exists(&self.blob_path(&sha256))?;
+            remove_exists(&self.verified_path(&sha256))?;
+            for entry in entries.iter().filter(|entry| entry.sha256 == sha256) {
+                remove_exists(&self.entry_path(&entry.url))?;
+            }
+            log::trace!("evict cache:{} size:{}", sha256, blob_size);
+            size -= blob_size;
+            freed += blob_size;
+            evictions += 1;
+        }
+        if evictions > 0 {
+            self.add_stats(0, 0, evictions);
+        }
+        Ok(freed)
+    }
+
+    /// remove all cache files and stats
+    #[inline]
+    pub fn clear(&self) -> Result<()> {
+        let _lock = self.lock()?;
+        for dir in ["blobs", "entries"] {
+            match std::fs::remove_dir_all(self.dir.join(dir)) {
+                Err(err) if err.kind() != ErrorKind::NotFound => return Err(err.into()),
+                _ => {}
+            }
+        }
+        remove_exists(&self.stats_path())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DownloadCache;
+    use crate::http_cache::CachePolicy;
+    use crate::piece_hash::HashType;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    const URL: &str = "http://127.0.0.1/a.bin";
+    const VALIDATOR: Option<&str> = Some("\"v1\"");
+
+    /// empty temp dir of name
+    fn temp_dir(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("cache-test-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        path
+    }
+
+    fn fresh_for(secs: u64) -> CachePolicy {
+        CachePolicy {
+            fresh_for: Some(Duration::from_secs(secs)),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn lookup_by_validator_and_sha256() {
+        let dir = temp_dir("lookup");
+        let cache = DownloadCache::new(dir.join("cache"));
+        let policy = CachePolicy::default();
+        let entry = cache
+            .insert_bytes(URL, VALIDATOR, &policy, b"data")
+            .unwrap();
+        assert_eq!(entry.sha256, HashType::Sha256.digest(b"data"));
+        let hit = cache.lookup(URL, VALIDATOR, None, 4, &policy).unwrap();
+        assert_eq!(hit.unwrap().sha256, entry.sha256);
+        // remote changed
+        assert!(cache
+            .lookup(URL, Some("\"v2\""), None, 4, &policy)
+            .unwrap()
+            .is_none());
+        assert!(cache
+            .lookup(URL, VALIDATOR, None, 5, &policy)
+            .unwrap()
+            .is_none());
+        assert!(cache.lookup(URL, None, None, 4, &policy).unwrap().is_none());
+        // same data of other url is found by sha256
+        let other = "http://127.0.0.1/b.bin";
+        let hit = cache.lookup(
+            other,
+            None,
+            Some(&entry.sha256.to_ascii_uppercase()),
+            4,
+            &policy,
+        );
+        assert_eq!(hit.unwrap().unwrap().url, other);
+        let no_store = CachePolicy {
+            no_store: true,
+            ..Default::default()
+        };
+        let hit = cache.lookup(URL, VALIDATOR, Some(&entry.sha256), 4, &no_store);
+        assert!(hit.unwrap().is_none());
+        let stats = cache.stats().unwrap();
+        assert_eq!((stats.hits, stats.misses), (2, 4));
+        assert_eq!((stats.entries, stats.blobs, stats.size), (2, 1, 4));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn lookup_fresh_and_stale() {
+        let dir = temp_dir("fresh");
+        let cache = DownloadCache::new(dir.join("cache"));
+        cache
+            .insert_bytes(URL, VALIDATOR, &fresh_for(60), b"data")
+            .unwrap();
+        assert!(cache.lookup_fresh(URL).unwrap().is_some());
+        assert!(cache.lookup_stale(URL).unwrap().is_some());
+        cache
+            .insert_bytes(URL, VALIDATOR, &fresh_for(0), b"data")
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(5));
+        assert!(cache.lookup_fresh(URL).unwrap().is_none());
+        let stale = cache.lookup_stale(URL).unwrap().unwrap();
+        // not modified response make it fresh again
+        let entry = cache.revalidated(stale, &fresh_for(60)).unwrap();
+        assert!(entry.expires.is_some());
+        assert!(cache.lookup_fresh(URL).unwrap().is_some());
+        // stale entry without validator can not revalidate
+        cache
+            .insert_bytes(URL, None, &fresh_for(0), b"data")
+            .unwrap();
+        assert!(cache.lookup_stale(URL).unwrap().is_none());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn evict_least_recently_used() {
+        let dir = temp_dir("evict");
+        let cache = DownloadCache::new(dir.join("cache"));
+        let policy = CachePolicy::default();
+        let a = cache.insert_bytes("http://127.0.0.1/a", VALIDATOR, &policy, b"aaaaaaaaaa");
+        std::thread::sleep(Duration::from_millis(5));
+        cache
+            .insert_bytes("http://127.0.0.1/b", VALIDATOR, &policy, b"bbbbbbbbbb")
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(5));
+        // access a,b is least recently used
+        let hit = cache.lookup("http://127.0.0.1/a", VALIDATOR, None, 10, &policy);
+        assert_eq!(hit.unwrap().unwrap().sha256, a.unwrap().sha256);
+        std::thread::sleep(Duration::from_millis(5));
+        let cache = cache.max_size(25);
+        cache
+            .insert_bytes("http://127.0.0.1/c", VALIDATOR, &policy, b"cccccccccc")
+            .unwrap();
+        let stats = cache.stats().unwrap();
+        assert_eq!((stats.blobs, stats.size, stats.evictions), (2, 20, 1));
+        let urls = cache
+            .entries()
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.url)
+            .collect::<Vec<_>>();
+        assert!(!urls.contains(&"http://127.0.0.1/b".to_string()));
+        assert_eq!(cache.prune(10).unwrap(), 10);
+        assert_eq!(cache.stats().unwrap().size, 10);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn remove_broken_blob() {
+        let dir = temp_dir("broken");
+        let cache = DownloadCache::new(dir.join("cache"));
+        let policy = CachePolicy::default();
+        let entry = cache
+            .insert_bytes(URL, VALIDATOR, &policy, b"data")
+            .unwrap();
+        let blob = dir.join("cache").join("blobs").join(&entry.sha256);
+        std::thread::sleep(Duration::from_millis(5));
+        std::fs::write(&blob, b"dat!").unwrap();
+        assert!(cache
+            .lookup(URL, VALIDATOR, None, 4, &policy)
+            .unwrap()
+            .is_none());
+        assert!(!blob.exists());
+        cache
+            .insert_bytes(URL, VALIDATOR, &policy, b"data")
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(5));
+        std::fs::write(&blob, b"dat!").unwrap();
+        assert_eq!(cache.verify().unwrap(), 1);
+        assert_eq!(cache.stats().unwrap().blobs, 0);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn place_copy_not_hardlink() {
+        let dir = temp_dir("place");
+        let cache = DownloadCache::new(dir.join("cache"));
+        let file = dir.join("a.bin");
+        std::fs::write(&file, b"data").unwrap();
+        let entry = cache
+            .insert(URL, VALIDATOR, &CachePolicy::default(), &file)
+            .unwrap();
+        let blob = dir.join("cache").join("blobs").join(&entry.sha256);
+        let save_path = dir.join("b.bin");
+        cache.place(&entry, &save_path).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            let ino = |path: &PathBuf| std::fs::metadata(path).unwrap().ino();
+            assert_ne!(ino(&file), ino(&blob));
+            assert_ne!(ino(&save_path), ino(&blob));
+        }
+        // edit of placed file not change cache
+        std::fs::write(&save_path, b"edit").unwrap();
+        std::fs::write(&file, b"edit").unwrap();
+        assert_eq!(std::fs::read(&blob).unwrap(), b"data");
+        assert_eq!(cache.verify().unwrap(), 0);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,233 @@
+use super::error::{DownloadError, Result};
+use super::{download_to_bytes, DownloadOptions};
+use percent_encoding::percent_decode_str;
+use reqwest::Url;
+
+/// where to find sha256 of download file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChecksumSource {
+    /// checksum manifest url or local path,like "https://example.com/SHA256SUMS"
+    Manifest(String),
+    /// try "<url>.sha256",not found is skip verify
+    Discover,
+}
+
+/// sha256 checksum manifest,support formats:
+/// "<hex>  <name>","<hex> *<name>","SHA256 (<name>) = <hex>" and single "<hex>"
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChecksumManifest {
+    /// file name and sha256 hex,name is empty if manifest only has hash
+    pub entries: Vec<(String, String)>,
+}
+
+#[inline]
+fn is_sha256(hex: &str) -> bool {
+    hex.len() == 64 && hex.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// unescape gnu coreutils escaped name,line start with "\"
+#[inline]
+fn unescape(name: &str) -> String {
+    let mut result = String::with_capacity(name.len());
+    let mut chars = name.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('n')) => {
+                result.push('\n');
+                chars.next();
+            }
+            ('\\', Some('r')) => {
+                result.push('\r');
+                chars.next();
+            }
+            ('\\', Some('\\')) => {
+                result.push('\\');
+                chars.next();
+            }
+            _ => result.push(c),
+        }
+    }
+    result
+}
+
+/// last path component of name
+#[inline]
+fn base_name(name: &str) -> &str {
+    name.rsplit(['/', '\\']).next().unwrap_or(name)
+}
+
+impl ChecksumManifest {
+    /// parse manifest text,skip comment and unknown lines
+    #[inline]
+    pub fn parse(text: &str) -> Self {
+        let mut entries = vec![];
+        for line in text.lines() {
+            let line = line.trim_end_matches('\r');
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue;
+            }
+            // bsd tag format
+            if let Some(rest) = trimmed.strip_prefix("SHA256 (") {
+                if let Some((name, hex)) = rest.rsplit_once(") = ") {
+                    if is_sha256(hex.trim()) {
+                        entries.push((name.to_string(), hex.trim().to_ascii_lowercase()));
+                    }
+                }
+                continue;
+            }
+            let (escaped, line) = match line.strip_prefix('\\') {
+                Some(line) => (true, line),
+                None => (false, trimmed),
+            };
+            let (hex, name) = line.split_once([' ', '\t']).unwrap_or((line, ""));
+            if !is_sha256(hex) {
+                continue;
+            }
+            // text mode is two space,binary mode is space and "*"
+            let name = name.strip_prefix([' ', '*']).unwrap_or(name);
+            let name = if escaped {
+                unescape(name)
+            } else {
+                name.trim().to_string()
+            };
+            entries.push((name, hex.to_ascii_lowercase()));
+        }
+        Self { entries }
+    }
+
+    /// download or read manifest
+    #[inline]
+    pub async fn load(source: &str) -> Result<Self> {
+        Ok(Self::parse(&load_text(source).await?))
+    }
+
+    /// sha256 of file name,compare last path component of manifest name,
+    /// only one entry without name match any file
+    #[inline]
+    pub fn find(&self, file_name: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(name, _)| name == file_name)
+            .or_else(|| {
+                self.entries
+                    .iter()
+                    .find(|(name, _)| base_name(name) == file_name)
+            })
+            .or_else(|| match self.entries.as_slice() {
+                [(name, _)] if name.is_empty() => self.entries.first(),
+                _ => None,
+            })
+            .map(|(_, hex)| hex.as_str())
+    }
+}
+
+impl ChecksumSource {
+    /// find sha256 of url,file name is remote file name or url last path,
+    /// discover not found return none
+    #[inline]
+    pub async fn resolve(&self, url: &Url, file_name: Option<&str>) -> Result<Option<String>> {
+        let file_name = match file_name {
+            Some(file_name) => file_name.to_string(),
+            None => url
+                .path_segments()
+                .and_then(|mut segments| segments.next_back())
+                .map(|name| percent_decode_str(name).decode_utf8_lossy().to_string())
+                .ok_or_else(|| DownloadError::NotFileName(url.clone()))?,
+        };
+        match self {
+            ChecksumSource::Manifest(source) => {
+                let manifest = ChecksumManifest::load(source).await?;
+                let sha256 = manifest.find(&file_name).ok_or_else(|| {
+                    DownloadError::ChecksumNotFound(format!("{} in {}", file_name, source))
+                })?;
+                log::trace!(
+                    "checksum manifest:{} {} sha256:{}",
+                    source,
+                    file_name,
+                    sha256
+                );
+                Ok(Some(sha256.to_string()))
+            }
+            ChecksumSource::Discover => {
+                let mut source = url.clone();
+                source.set_path(&format!("{}.sha256", url.path()));
+                source.set_query(None);
+                source.set_fragment(None);
+                let manifest = match ChecksumManifest::load(source.as_str()).await {
+                    Ok(manifest) => manifest,
+                    Err(err) => {
+                        log::trace!("not found checksum:{} error:{}", source, err);
+                        return Ok(None);
+                    }
+                };
+                // manifest of one file,name is not need match
+                let sha256 = match manifest.entries.as_slice() {
+                    [(_, sha256)] => Some(sha256.as_str()),
+                    _ => manifest.find(&file_name),
+                };
+                log::trace!(
+                    "discover checksum:{} {} sha256:{:?}",
+                    source,
+                    file_name,
+                    sha256
+                );
+                Ok(sha256.map(|x| x.to_string()))
+            }
+        }
+    }
+}
+
+/// read text of url or local path
+#[inline]
+pub(crate) async fn load_text(source: &str) -> Result<String> {
+    if source.contains("://") || source.starts_with("data:") {
+        let data = download_to_bytes(source, &DownloadOptions::default()).await?;
+        Ok(String::from_utf8_lossy(&data).to_string())
+    } else {
+        Ok(tokio::fs::read_to_string(source).await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ChecksumManifest;
+
+    const A: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+    const B: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+    #[test]
+    fn parse_formats() {
+        let text = format!(
+            "# comment\r\n{A}  a.txt\r\n{B} *dir/b.bin\nSHA256 (c d.iso) = {}\n\\{A}  e\\nf\\\\g\nbad  x\n",
+            B.to_ascii_uppercase()
+        );
+        let manifest = ChecksumManifest::parse(&text);
+        let names = manifest
+            .entries
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["a.txt", "dir/b.bin", "c d.iso", "e\nf\\g"]);
+        assert_eq!(manifest.entries[2].1, B);
+    }
+
+    #[test]
+    fn find_by_name() {
+        let manifest =
+            ChecksumManifest::parse(&format!("{A}  a.bin\n{B}  dir/b.bin\n{A}  b.bin\n"));
+        assert_eq!(manifest.find("a.bin"), Some(A));
+        // exact name before last path component
+        assert_eq!(manifest.find("b.bin"), Some(A));
+        assert_eq!(manifest.find("dir/b.bin"), Some(B));
+        assert_eq!(manifest.find("c.bin"), None);
+    }
+
+    #[test]
+    fn find_single_hash() {
+        let manifest = ChecksumManifest::parse(&format!("{A}\n"));
+        assert_eq!(manifest.find("any.bin"), Some(A));
+        let manifest = ChecksumManifest::parse(&format!("{A}\n{B}\n"));
+        assert_eq!(manifest.find("any.bin"), None);
+    }
+}
@@ -0,0 +1,465 @@
+use super::download_stream::download_stream;
+use super::error::{DownloadError, Result};
+use super::transport::IntoDownloadUrl;
+use super::DownloadOptions;
+use bytes::{Buf, Bytes};
+use futures_util::StreamExt;
+use std::io::{ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, BufWriter};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+/// read buffer size of compressed file
+const READ_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// compression format of file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+    Xz,
+    Bzip2,
+}
+
+/// decompress progress
+#[derive(Debug, Default)]
+pub struct DecompressStatus {
+    /// compressed size,0 is unknown
+    size: AtomicU64,
+    read_size: AtomicU64,
+    written_size: AtomicU64,
+    is_finish: AtomicBool,
+}
+
+impl DecompressStatus {
+    /// compressed data size
+    #[inline]
+    pub fn get_size(&self) -> u64 {
+        self.size.load(Ordering::Acquire)
+    }
+
+    /// compressed data size already decompress
+    #[inline]
+    pub fn get_read_size(&self) -> u64 {
+        self.read_size.load(Ordering::Acquire)
+    }
+
+    /// decompressed data size
+    #[inline]
+    pub fn get_written_size(&self) -> u64 {
+        self.written_size.load(Ordering::Acquire)
+    }
+
+    #[inline]
+    pub fn get_percent_complete(&self) -> f64 {
+        let current = self.get_read_size() as f64 / self.get_size().max(1) as f64 * 100.0;
+        (current * 100.0).round() / 100.0
+    }
+
+    #[inline]
+    pub fn is_finish(&self) -> bool {
+        self.is_finish.load(Ordering::Acquire)
+    }
+}
+
+impl Compression {
+    /// detect by file extension,".tgz" is gzip of tar
+    #[inline]
+    pub fn from_extension(name: &str) -> Option<Self> {
+        let extension = name.rsplit_once('.')?.1.to_ascii_lowercase();
+        match extension.as_str() {
+            "gz" | "tgz" => Some(Compression::Gzip),
+            "zst" | "zstd" | "tzst" => Some(Compression::Zstd),
+            "xz" | "txz" => Some(Compression::Xz),
+            "bz2" | "tbz2" | "tbz" => Some(Compression::Bzip2),
+            _ => None,
+        }
+    }
+
+    /// detect by magic bytes of data head
+    #[inline]
+    pub fn from_magic(head: &[u8]) -> Option<Self> {
+        if head.starts_with(&[0x1f, 0x8b]) {
+            Some(Compression::Gzip)
+        } else if head.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Compression::Zstd)
+        } else if head.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Some(Compression::Xz)
+        } else if head.starts_with(b"BZh") {
+            Some(Compression::Bzip2)
+        } else {
+            None
+        }
+    }
+
+    /// detect by extension of name,then magic bytes of head
+    #[inline]
+    pub fn detect(name: &str, head: &[u8]) -> Option<Self> {
+        Self::from_extension(name).or_else(|| Self::from_magic(head))
+    }
+
+    /// file name after decompress,remove compression extension,
+    /// ".tgz" like extension change to ".tar",
+    /// unknown extension add ".out"
+    #[inline]
+    pub fn decompressed_name(name: &str) -> String {
+        match name.rsplit_once('.') {
+            Some((stem, extension)) => match extension.to_ascii_lowercase().as_str() {
+                "gz" | "zst" | "zstd" | "xz" | "bz2" if !stem.is_empty() => stem.to_string(),
+                "tgz" | "tzst" | "txz" | "tbz2" | "tbz" => format!("{}.tar", stem),
+                _ => format!("{}.out", name),
+            },
+            None => format!("{}.out", name),
+        }
+    }
+
+    /// reader of decompressed data,
+    /// concatenated members or frames are all decoded
+    #[inline]
+    pub(crate) fn reader<'a, R: Read + 'a>(self, reader: R) -> Result<Box<dyn Read + 'a>> {
+        Ok(match self {
+            Compression::Gzip => Box::new(flate2::read::MultiGzDecoder::new(reader)),
+            Compression::Zstd => Box::new(zstd::stream::read::Decoder::new(reader)?),
+            Compression::Xz => Box::new(liblzma::read::XzDecoder::new_multi_decoder(reader)),
+            Compression::Bzip2 => Box::new(bzip2::read::MultiBzDecoder::new(reader)),
+        })
+    }
+}
+
+/// read data sent by async task,end when sender drop
+struct ChannelReader {
+    receiver: mpsc::Receiver<Bytes>,
+    data: Bytes,
+}
+
+impl Read for ChannelReader {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.data.is_empty() {
+            match self.receiver.blocking_recv() {
+                Some(data) => self.data = data,
+                None => return Ok(0),
+            }
+        }
+        let len = buf.len().min(self.data.len());
+        buf[..len].copy_from_slice(&self.data[..len]);
+        self.data.advance(len);
+        Ok(len)
+    }
+}
+
+/// count written size of inner writer
+struct CountWriter<W> {
+    inner: W,
+    status: Arc<DecompressStatus>,
+}
+
+impl<W: Write> Write for CountWriter<W> {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let len = self.inner.write(buf)?;
+        self.status
+            .written_size
+            .fetch_add(len as u64, Ordering::Release);
+        Ok(len)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// decompress to temp file in blocking thread,rename to dest when finish,
+/// delete temp file when fail
+struct DecompressWriter {
+    sender: Option<mpsc::Sender<Bytes>>,
+    decoder: Option<JoinHandle<Result<()>>>,
+    temp_path: PathBuf,
+    dest: PathBuf,
+    status: Arc<DecompressStatus>,
+}
+
+impl DecompressWriter {
+    #[inline]
+    async fn create(
+        dest: PathBuf,
+        compression: Compression,
+        status: Arc<DecompressStatus>,
+    ) -> Result<Self> {
+        let temp_path = dest.with_extension("dd");
+        let file = tokio::fs::File::create(&temp_path).await?.into_std().await;
+        log::trace!("decompress {:?} to file:{:?}", compression, temp_path);
+        let (sender, receiver) = mpsc::channel(16);
+        let mut writer = CountWriter {
+            inner: std::io::BufWriter::with_capacity(READ_BUFFER_SIZE, file),
+            status: status.clone(),
+        };
+        let decoder = tokio::task::spawn_blocking(move || {
+            let mut reader = compression.reader(ChannelReader {
+                receiver,
+                data: Bytes::new(),
+            })?;
+            std::io::copy(&mut reader, &mut writer)?;
+            writer.flush()?;
+            Ok(())
+        });
+        Ok(Self {
+            sender: Some(sender),
+            decoder: Some(decoder),
+            temp_path,
+            dest,
+            status,
+        })
+    }
+
+    #[inline]
+    async fn write(&mut self, data: Bytes) -> Result<()> {
+        let len = data.len() as u64;
+        let is_sent = match &self.sender {
+            Some(sender) => sender.send(data).await.is_ok(),
+            None => false,
+        };
+        if !is_sent {
+            // decoder end before all data sent
+            self.join().await?;
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidData,
+                "decompressed data end before compressed data",
+            )
+            .into());
+        }
+        self.status.read_size.fetch_add(len, Ordering::Release);
+        Ok(())
+    }
+
+    /// close data channel,wait decoder thread end
+    #[inline]
+    async fn join(&mut self) -> Result<()> {
+        self.sender = None;
+        match self.decoder.take() {
+            Some(decoder) => decoder.await.map_err(DownloadError::JoinInError)?,
+            None => Ok(()),
+        }
+    }
+
+    /// finish decoder,rename temp file to dest
+    #[inline]
+    async fn finish(mut self) -> Result<u64> {
+        if let Err(err) = self.join().await {
+            self.abort().await;
+            return Err(err);
+        }
+        tokio::fs::rename(&self.temp_path, &self.dest).await?;
+        self.status.is_finish.store(true, Ordering::Release);
+        log::trace!(
+            "decompress finish file:{:?} size:{}",
+            self.dest,
+            self.status.get_written_size()
+        );
+        Ok(self.status.get_written_size())
+    }
+
+    #[inline]
+    async fn abort(mut self) {
+        let _ = self.join().await;
+        if let Err(err) = tokio::fs::remove_file(&self.temp_path).await {
+            log::warn!("delete file:{:?} error:{}", self.temp_path, err);
+        }
+        self.status.is_finish.store(true, Ordering::Release);
+    }
+}
+
+/// dest is dir,push decompressed name of file name
+#[inline]
+fn get_dest_path(mut dest: PathBuf, file_name: &str) -> PathBuf {
+    if dest.is_dir() {
+        dest.push(Compression::decompressed_name(file_name));
+    }
+    dest
+}
+
+#[inline]
+fn unknown_compression(name: &str) -> DownloadError {
+    std::io::Error::new(
+        ErrorKind::InvalidData,
+        format!("unknown compression of file:{}", name),
+    )
+    .into()
+}
+
+/// decompress local file to dest,compression none is detect by extension or magic bytes,
+/// dest is dir save to decompressed name,src is deleted if not keep,return dest path
+#[inline]
+pub async fn decompress_file(
+    src: &Path,
+    dest: PathBuf,
+    compression: Option<Compression>,
+    keep: bool,
+    status: Arc<DecompressStatus>,
+) -> Result<PathBuf> {
+    let mut file = tokio::fs::File::open(src).await?;
+    status
+        .size
+        .store(file.metadata().await?.len(), Ordering::Release);
+    let name = src
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let mut buf = vec![0; READ_BUFFER_SIZE];
+    let mut len = file.read(&mut buf).await?;
+    let compression = compression
+        .or_else(|| Compression::detect(&name, &buf[..len]))
+        .ok_or_else(|| unknown_compression(&name))?;
+    let dest = get_dest_path(dest, &name);
+    let mut writer = DecompressWriter::create(dest.clone(), compression, status).await?;
+    let result = async {
+        while len > 0 {
+            writer.write(Bytes::copy_from_slice(&buf[..len])).await?;
+            len = file.read(&mut buf).await?;
+        }
+        Ok::<_, DownloadError>(())
+    }
+    .await;
+    match result {
+        Ok(_) => {
+            writer.finish().await?;
+        }
+        Err(err) => {
+            writer.abort().await;
+            return Err(err);
+        }
+    }
+    if !keep {
+        tokio::fs::remove_file(src).await?;
+        log::trace!("delete compressed file:{:?}", src);
+    }
+    Ok(dest)
+}
+
+/// download url data in order and decompress while streaming,
+/// compression none is detect by url extension or magic bytes,
+/// dest is dir save to decompressed name,keep is path save compressed data too,
+/// return dest path
+#[inline]
+pub async fn download_decompress<U: IntoDownloadUrl>(
+    url: U,
+    dest: PathBuf,
+    compression: Option<Compression>,
+    keep: Option<PathBuf>,
+    options: &DownloadOptions,
+    status: Arc<DecompressStatus>,
+) -> Result<PathBuf> {
+    let url = url.into_download_url()?;
+    let name = url
+        .path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .filter(|name| !name.is_empty())
+        .ok_or_else(|| DownloadError::NotFileName(url.clone()))?
+        .to_string();
+    let mut stream = download_stream(url, options).await?;
+    status.size.store(stream.size(), Ordering::Release);
+    let head = match stream.next().await {
+        Some(data) => data?,
+        None => Default::default(),
+    };
+    let compression = compression
+        .or_else(|| Compression::detect(&name, &head))
+        .ok_or_else(|| unknown_compression(&name))?;
+    let dest = get_dest_path(dest, &name);
+    let mut writer = DecompressWriter::create(dest.clone(), compression, status).await?;
+    let keep = keep.map(|path| {
+        if path.is_dir() {
+            path.join(&name)
+        } else {
+            path
+        }
+    });
+    let mut keep_file = match &keep {
+        Some(path) => Some(BufWriter::new(tokio::fs::File::create(path).await?)),
+        None => None,
+    };
+    let result = async {
+        let mut data = head;
+        loop {
+            if let Some(keep_file) = keep_file.as_mut() {
+                keep_file.write_all(&data).await?;
+            }
+            writer.write(data).await?;
+            data = match stream.next().await {
+                Some(data) => data?,
+                None => break,
+            };
+        }
+        if let Some(keep_file) = keep_file.as_mut() {
+            keep_file.flush().await?;
+        }
+        Ok::<_, DownloadError>(())
+    }
+    .await;
+    match result {
+        Ok(_) => {
+            writer.finish().await?;
+            Ok(dest)
+        }
+        Err(err) => {
+            writer.abort().await;
+            drop(keep_file);
+            if let Some(path) = keep {
+                let _ = tokio::fs::remove_file(path).await;
+            }
+            Err(err)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decompress_file, Compression, DecompressStatus};
+    use std::io::{Read, Write};
+    use std::sync::Arc;
+
+    /// gzip data as one member
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn read_multi_member_gzip() {
+        let mut data = gzip(b"first member\n");
+        data.extend_from_slice(&gzip(b"second member\n"));
+        let mut reader = Compression::Gzip.reader(&data[..]).unwrap();
+        let mut output = String::new();
+        reader.read_to_string(&mut output).unwrap();
+        assert_eq!(output, "first member\nsecond member\n");
+    }
+
+    #[tokio::test]
+    async fn decompress_multi_member_gzip() {
+        let dir = std::env::temp_dir().join(format!("decompress-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let src = dir.join("data.txt.gz");
+        let mut data = gzip(b"first member\n");
+        data.extend_from_slice(&gzip(b"second member\n"));
+        std::fs::write(&src, &data).unwrap();
+        let status = Arc::new(DecompressStatus::default());
+        let dest = decompress_file(&src, dir.clone(), None, false, status.clone())
+            .await
+            .unwrap();
+        assert_eq!(dest, dir.join("data.txt"));
+        assert_eq!(
+            std::fs::read(&dest).unwrap(),
+            b"first member\nsecond member\n"
+        );
+        assert_eq!(status.get_read_size(), data.len() as u64);
+        assert_eq!(status.get_written_size(), 27);
+        assert!(status.is_finish());
+        assert!(!src.exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
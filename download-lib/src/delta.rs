@@ -0,0 +1,547 @@
+use super::error::{DownloadError, Result};
+use super::file_save::{FileSave, IFileSave};
+use super::transport::{transport_for_url, IntoDownloadUrl};
+use super::{download_to_bytes, DownloadFile, DownloadOptions};
+use bytes::Bytes;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{ErrorKind, Read, SeekFrom};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+/// control file magic line
+const CONTROL_MAGIC: &str = "dsync: 1";
+/// bytes of block strong checksum,sha256 prefix
+const CHECKSUM_SIZE: usize = 16;
+/// old file read buffer size
+const READ_BUFFER_SIZE: usize = 4 * 1024 * 1024;
+
+/// signature of block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeltaBlock {
+    /// rsync rolling checksum
+    pub rsum: u32,
+    /// sha256 prefix
+    pub checksum: [u8; CHECKSUM_SIZE],
+}
+
+/// zsync style control file,block signatures of remote file,
+/// last block is padded by zero
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeltaControl {
+    pub file_name: Option<String>,
+    /// file size
+    pub length: u64,
+    pub block_size: u64,
+    /// sha256 hex of whole file
+    pub sha256: String,
+    /// remote file url
+    pub url: Option<String>,
+    pub blocks: Vec<DeltaBlock>,
+}
+
+/// rsync rolling checksum of block,return a and b
+#[inline]
+fn rolling_checksum(block: &[u8]) -> (u32, u32) {
+    let len = block.len() as u32;
+    block
+        .iter()
+        .enumerate()
+        .fold((0u32, 0u32), |(a, b), (i, x)| {
+            (
+                a.wrapping_add(*x as u32),
+                b.wrapping_add((len - i as u32).wrapping_mul(*x as u32)),
+            )
+        })
+}
+
+#[inline]
+fn rsum(a: u32, b: u32) -> u32 {
+    ((b & 0xffff) << 16) | (a & 0xffff)
+}
+
+#[inline]
+fn strong_checksum(block: &[u8]) -> [u8; CHECKSUM_SIZE] {
+    let mut checksum = [0; CHECKSUM_SIZE];
+    checksum.copy_from_slice(&Sha256::digest(block)[..CHECKSUM_SIZE]);
+    checksum
+}
+
+impl DeltaControl {
+    /// generate control file of local file
+    #[inline]
+    pub fn generate(path: &Path, block_size: u64) -> Result<Self> {
+        let block_size = block_size.max(1);
+        let mut file = File::open(path)?;
+        let mut hasher = Sha256::new();
+        let mut blocks = vec![];
+        let mut length = 0;
+        let mut block = vec![0; block_size as usize];
+        loop {
+            let mut len = 0;
+            while len < block.len() {
+                match file.read(&mut block[len..])? {
+                    0 => break,
+                    n => len += n,
+                }
+            }
+            if len == 0 {
+                break;
+            }
+            hasher.update(&block[..len]);
+            length += len as u64;
+            block[len..].fill(0);
+            let (a, b) = rolling_checksum(&block);
+            blocks.push(DeltaBlock {
+                rsum: rsum(a, b),
+                checksum: strong_checksum(&block),
+            });
+            if len < block.len() {
+                break;
+            }
+        }
+        Ok(Self {
+            file_name: path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string()),
+            length,
+            block_size,
+            sha256: hex::encode(hasher.finalize()),
+            url: None,
+            blocks,
+        })
+    }
+
+    /// control file data,text header,blank line,then block signatures
+    #[inline]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = format!("{}\n", CONTROL_MAGIC);
+        if let Some(file_name) = &self.file_name {
+            data.push_str(&format!("Filename: {}\n", file_name));
+        }
+        data.push_str(&format!(
+            "Length: {}\nBlocksize: {}\nSHA-256: {}\n",
+            self.length, self.block_size, self.sha256
+        ));
+        if let Some(url) = &self.url {
+            data.push_str(&format!("URL: {}\n", url));
+        }
+        data.push('\n');
+        let mut data = data.into_bytes();
+        for block in &self.blocks {
+            data.extend_from_slice(&block.rsum.to_be_bytes());
+            data.extend_from_slice(&block.checksum);
+        }
+        data
+    }
+
+    /// parse control file data
+    #[inline]
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let invalid = |message: &str| {
+            DownloadError::from(std::io::Error::new(
+                ErrorKind::InvalidData,
+                format!("invalid delta control file,{}", message),
+            ))
+        };
+        let header_end = data
+            .windows(2)
+            .position(|x| x == b"\n\n")
+            .ok_or_else(|| invalid("no header end"))?;
+        let header = String::from_utf8_lossy(&data[..header_end]);
+        let mut lines = header.lines();
+        if lines.next() != Some(CONTROL_MAGIC) {
+            return Err(invalid("magic mismatch"));
+        }
+        let headers = lines
+            .filter_map(|line| line.split_once(": "))
+            .collect::<HashMap<_, _>>();
+        let length = headers
+            .get("Length")
+            .and_then(|x| x.parse::<u64>().ok())
+            .ok_or_else(|| invalid("no length"))?;
+        let block_size = headers
+            .get("Blocksize")
+            .and_then(|x| x.parse::<u64>().ok())
+            .filter(|x| *x > 0)
+            .ok_or_else(|| invalid("no block size"))?;
+        let sha256 = headers
+            .get("SHA-256")
+            .ok_or_else(|| invalid("no sha256"))?
+            .to_ascii_lowercase();
+        let records = &data[header_end + 2..];
+        let record_size = 4 + CHECKSUM_SIZE;
+        if records.len() as u64 != length.div_ceil(block_size) * record_size as u64 {
+            return Err(invalid("block count not match length"));
+        }
+        let blocks = records
+            .chunks(record_size)
+            .map(|record| {
+                let mut checksum = [0; CHECKSUM_SIZE];
+                checksum.copy_from_slice(&record[4..]);
+                DeltaBlock {
+                    rsum: u32::from_be_bytes([record[0], record[1], record[2], record[3]]),
+                    checksum,
+                }
+            })
+            .collect();
+        Ok(Self {
+            file_name: headers.get("Filename").map(|x| x.to_string()),
+            length,
+            block_size,
+            sha256,
+            url: headers.get("URL").map(|x| x.to_string()),
+            blocks,
+        })
+    }
+
+    /// save control file
+    #[inline]
+    pub fn save(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, self.to_bytes())?;
+        Ok(())
+    }
+
+    /// download and parse control file of url
+    #[inline]
+    pub async fn load<U: IntoDownloadUrl>(url: U) -> Result<Self> {
+        Self::parse(&download_to_bytes(url, &DownloadOptions::default()).await?)
+    }
+
+    /// find blocks in old file by rolling checksum,
+    /// return old file offset of every block,none is need download
+    #[inline]
+    pub fn match_blocks(&self, old_file: &Path) -> Result<Vec<Option<u64>>> {
+        let block_size = self.block_size as usize;
+        let mut table: HashMap<u32, Vec<usize>> = HashMap::new();
+        for (index, block) in self.blocks.iter().enumerate() {
+            table.entry(block.rsum).or_default().push(index);
+        }
+        let mut found = vec![None; self.blocks.len()];
+        let mut found_count = 0;
+        let mut file = File::open(old_file)?;
+        let mut buf: Vec<u8> = Vec::with_capacity(READ_BUFFER_SIZE + block_size);
+        // file offset of buf start
+        let mut base = 0u64;
+        let mut pos = 0usize;
+        let mut is_eof = false;
+        let mut rolling: Option<(u32, u32)> = None;
+        while found_count < found.len() {
+            if buf.len() < pos + block_size + 1 && !is_eof {
+                // drop scanned data,keep current window
+                buf.drain(..pos);
+                base += pos as u64;
+                pos = 0;
+                let len = buf.len();
+                buf.resize(len + READ_BUFFER_SIZE, 0);
+                let read = file.read(&mut buf[len..])?;
+                buf.truncate(len + read);
+                if read == 0 {
+                    // last block of control file is padded by zero
+                    is_eof = true;
+                    buf.resize(buf.len() + block_size, 0);
+                }
+                continue;
+            }
+            if buf.len() < pos + block_size {
+                break;
+            }
+            let window = &buf[pos..pos + block_size];
+            let (a, b) = rolling.unwrap_or_else(|| rolling_checksum(window));
+            if let Some(indexes) = table.get(&rsum(a, b)) {
+                let checksum = strong_checksum(window);
+                let mut is_match = false;
+                for &index in indexes {
+                    if self.blocks[index].checksum == checksum {
+                        is_match = true;
+                        if found[index].is_none() {
+                            found[index] = Some(base + pos as u64);
+                            found_count += 1;
+                        }
+                    }
+                }
+                if is_match {
+                    pos += block_size;
+                    rolling = None;
+                    continue;
+                }
+            }
+            if buf.len() < pos + block_size + 1 {
+                break;
+            }
+            let (out, input) = (buf[pos] as u32, buf[pos + block_size] as u32);
+            let a = a.wrapping_sub(out).wrapping_add(input);
+            let b = b
+                .wrapping_sub((block_size as u32).wrapping_mul(out))
+                .wrapping_add(a);
+            rolling = Some((a, b));
+            pos += 1;
+        }
+        log::trace!(
+            "delta old file:{:?} match blocks:{}/{}",
+            old_file,
+            found_count,
+            found.len()
+        );
+        Ok(found)
+    }
+}
+
+/// delta download save,copy matched blocks from old file on init,
+/// download ranges write to offset of new file,verify sha256 before finish
+pub struct DeltaSave {
+    save: FileSave,
+    old_file: PathBuf,
+    /// copy from old file,(new offset,old offset,len)
+    copies: Vec<(u64, u64, u64)>,
+    /// download ranges in new file,(save offset,new offset,len)
+    ranges: Vec<(u64, u64, u64)>,
+    length: u64,
+    sha256: String,
+    /// no block match old file,download whole file,write data by offset
+    is_whole: bool,
+}
+
+impl DeltaSave {
+    /// get save file real path
+    #[inline]
+    pub fn get_real_file_path(&self) -> String {
+        self.save.get_real_file_path()
+    }
+
+    /// bytes copy from old file
+    #[inline]
+    pub fn reuse_size(&self) -> u64 {
+        self.copies.iter().map(|(_, _, len)| len).sum()
+    }
+
+    /// copy matched blocks from old file
+    #[inline]
+    async fn copy_blocks(&self) -> Result<()> {
+        let mut file = tokio::fs::File::open(&self.old_file).await?;
+        for &(offset, old_offset, len) in &self.copies {
+            file.seek(SeekFrom::Start(old_offset)).await?;
+            let mut copied = 0;
+            while copied < len {
+                let mut buf = vec![0; (len - copied).min(READ_BUFFER_SIZE as u64) as usize];
+                let read = file.read(&mut buf).await?;
+                // zero padding of last block is already in new file
+                if read == 0 {
+                    break;
+                }
+                buf.truncate(read);
+                self.save
+                    .write_all_by_offset(Bytes::from(buf), offset + copied)
+                    .await?;
+                copied += read as u64;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl IFileSave for DeltaSave {
+    #[inline]
+    async fn init(&self, size: u64) -> Result<()> {
+        // whole file download may restart on remote change with new size
+        if self.is_whole {
+            return self.save.init(size).await;
+        }
+        self.save.init(self.length).await?;
+        self.copy_blocks().await
+    }
+
+    #[inline]
+    async fn write_all_by_offset(&self, mut data: Bytes, mut offset: u64) -> Result<()> {
+        if self.is_whole {
+            return self.save.write_all_by_offset(data, offset).await;
+        }
+        while !data.is_empty() {
+            let index = self
+                .ranges
+                .partition_point(|(save_offset, _, _)| *save_offset <= offset);
+            let Some(&(save_offset, new_offset, len)) = index
+                .checked_sub(1)
+                .and_then(|index| self.ranges.get(index))
+            else {
+                break;
+            };
+            let write_len = (data.len() as u64).min(save_offset + len - offset);
+            let buf = data.split_to(write_len as usize);
+            self.save
+                .write_all_by_offset(buf, new_offset + offset - save_offset)
+                .await?;
+            offset += write_len;
+        }
+        Ok(())
+    }
+
+    #[inline]
+    async fn finish(&self) -> Result<()> {
+        if let Some(actual) = self.save.sha256().await? {
+            if actual != self.sha256 {
+                self.save.abort().await?;
+                return Err(DownloadError::ChecksumMismatch {
+                    expected: self.sha256.clone(),
+                    actual,
+                });
+            }
+        }
+        self.save.finish().await
+    }
+
+    #[inline]
+    async fn abort(&self) -> Result<()> {
+        self.save.abort().await
+    }
+}
+
+impl DownloadFile<DeltaSave> {
+    /// start delta download,copy blocks same as control file from old file,
+    /// only download missing blocks by range request,verify sha256 before finish
+    #[inline]
+    pub async fn start_delta<U: IntoDownloadUrl>(
+        url: U,
+        control: &DeltaControl,
+        old_file: PathBuf,
+        save_path: PathBuf,
+        task_count: u64,
+        block: u64,
+    ) -> Result<Self> {
+        let url = url.into_download_url()?;
+        let transport = transport_for_url(&url);
+        let info = transport.probe(&url).await?;
+        if info.size != control.length {
+            return Err(DownloadError::SizeMismatch {
+                expected: control.length,
+                actual: info.size,
+            });
+        }
+        let save_path = DownloadFile::<FileSave>::get_save_path(
+            &url,
+            save_path,
+            info.file_name.clone().or_else(|| control.file_name.clone()),
+        )?;
+        let found = {
+            let control = control.clone();
+            let old_file = old_file.clone();
+            tokio::task::spawn_blocking(move || control.match_blocks(&old_file))
+                .await
+                .map_err(DownloadError::JoinInError)??
+        };
+
+        // merge continuous blocks
+        let mut copies: Vec<(u64, u64, u64)> = vec![];
+        let mut missing: Vec<Range<u64>> = vec![];
+        for (index, old_offset) in found.into_iter().enumerate() {
+            let start = index as u64 * control.block_size;
+            let len = control.block_size.min(control.length - start);
+            match old_offset {
+                Some(old_offset) => match copies.last_mut() {
+                    Some(last) if last.0 + last.2 == start && last.1 + last.2 == old_offset => {
+                        last.2 += len
+                    }
+                    _ => copies.push((start, old_offset, len)),
+                },
+                None => match missing.last_mut() {
+                    Some(last) if last.end == start => last.end += len,
+                    _ => missing.push(start..start + len),
+                },
+            }
+        }
+        let mut save_offset = 0;
+        let ranges = missing
+            .iter()
+            .map(|range| {
+                let item = (save_offset, range.start, range.end - range.start);
+                save_offset += range.end - range.start;
+                item
+            })
+            .collect();
+        log::trace!(
+            "delta url:{} reuse ranges:{} download ranges:{:?}",
+            url,
+            copies.len(),
+            missing
+        );
+        let save = DeltaSave {
+            save: FileSave::create(save_path)?,
+            old_file,
+            is_whole: copies.is_empty(),
+            copies,
+            ranges,
+            length: control.length,
+            sha256: control.sha256.clone(),
+        };
+        Self::new(url, transport, info.size, missing, save, task_count, block)
+            .start_now(info)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{rolling_checksum, rsum, DeltaControl};
+    use std::path::PathBuf;
+
+    /// write data to temp file of name
+    fn temp_file(name: &str, data: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("delta-test-{}-{}", std::process::id(), name));
+        std::fs::write(&path, data).unwrap();
+        path
+    }
+
+    #[test]
+    fn rolling_checksum_of_window() {
+        let data = b"abcdefgh";
+        let (a, b) = rolling_checksum(&data[..4]);
+        let (out, input) = (data[0] as u32, data[4] as u32);
+        let a = a.wrapping_sub(out).wrapping_add(input);
+        let b = b.wrapping_sub(4 * out).wrapping_add(a);
+        let (next_a, next_b) = rolling_checksum(&data[1..5]);
+        assert_eq!(rsum(a, b), rsum(next_a, next_b));
+    }
+
+    #[test]
+    fn control_round_trip() {
+        let data = (0..100u8).collect::<Vec<_>>();
+        let path = temp_file("round", &data);
+        let mut control = DeltaControl::generate(&path, 16).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        control.url = Some("http://example.com/a.bin".to_string());
+        assert_eq!(control.length, 100);
+        assert_eq!(control.blocks.len(), 7);
+        assert_eq!(DeltaControl::parse(&control.to_bytes()).unwrap(), control);
+    }
+
+    #[test]
+    fn parse_invalid_control() {
+        assert!(DeltaControl::parse(b"dsync: 1\nLength: 10\n").is_err());
+        assert!(DeltaControl::parse(b"zsync: 1\nLength: 1\nBlocksize: 1\nSHA-256: a\n\n").is_err());
+        // one block need 20 bytes record
+        assert!(
+            DeltaControl::parse(b"dsync: 1\nLength: 1\nBlocksize: 1\nSHA-256: a\n\nxx").is_err()
+        );
+        assert!(DeltaControl::parse(b"dsync: 1\nLength: 0\nBlocksize: 0\nSHA-256: a\n\n").is_err());
+    }
+
+    #[test]
+    fn match_shifted_blocks() {
+        let new = (0..64u8).collect::<Vec<_>>();
+        let path = temp_file("new", &new);
+        let control = DeltaControl::generate(&path, 16).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        // old file has 3 bytes inserted,block 1 changed and no last block
+        let mut old = vec![7, 7, 7];
+        old.extend_from_slice(&new[..16]);
+        old.extend_from_slice(&[0; 16]);
+        old.extend_from_slice(&new[32..48]);
+        let path = temp_file("old", &old);
+        let found = control.match_blocks(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(found, [Some(3), None, Some(35), None]);
+    }
+}
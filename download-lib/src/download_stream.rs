@@ -0,0 +1,380 @@
+use super::error::{DownloadError, Result};
+use super::options::DownloadOptions;
+use super::range_file::{fetch_head_from_body, fetch_range};
+use super::transport::{transport_for_url, ByteStream, IntoDownloadUrl};
+use super::DownloadInner;
+use bytes::{Buf, Bytes};
+use futures_util::Stream;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, ReadBuf};
+use tokio::sync::{mpsc, OwnedSemaphorePermit, Semaphore};
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, Sleep};
+
+/// cursor piece not arrive in time while later pieces buffered,request it again
+const HEDGE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// piece index,data and window permit,hedge request piece has no permit
+type Piece = (u64, Result<Bytes>, Option<OwnedSemaphorePermit>);
+
+/// in order download stream,
+/// pieces download concurrent and reorder by index,
+/// buffered pieces not more than twice of task count,
+/// piece at read cursor stalled is requested again out of window
+pub struct DownloadStream {
+    inner_status: Arc<DownloadInner>,
+    /// weak sender for hedge request,
+    /// channel close when all tasks exit
+    sender: mpsc::WeakUnboundedSender<Piece>,
+    receiver: mpsc::UnboundedReceiver<Piece>,
+    pieces: BTreeMap<u64, (Bytes, Option<OwnedSemaphorePermit>)>,
+    cursor: u64,
+    piece_count: u64,
+    block: u64,
+    chunk: Bytes,
+    tasks: Vec<JoinHandle<()>>,
+    /// stall timer of cursor piece,none timer is already hedged
+    stall: Option<(u64, Option<Pin<Box<Sleep>>>)>,
+    /// hedge request task of cursor piece
+    hedge: Option<JoinHandle<()>>,
+}
+
+/// start download url,return in order stream
+#[inline]
+pub async fn download_stream<U: IntoDownloadUrl>(
+    url: U,
+    options: &DownloadOptions,
+) -> Result<DownloadStream> {
+    let url = url.into_download_url()?;
+    let transport = transport_for_url(&url);
+    let info = transport.probe(&url).await?;
+    let size = info.size;
+    if let Some(limit) = options.max_size {
+        if size > limit {
+            return Err(DownloadError::SizeLimitExceeded { size, limit });
+        }
+    }
+    let inner_status = Arc::new(DownloadInner::new(url, transport, size, size));
+    inner_status.set_validator(info.validator);
+    Ok(DownloadStream::start(
+        inner_status,
+        info.body,
+        options.task_count.max(1),
+        options.block.max(1),
+    ))
+}
+
+impl DownloadStream {
+    /// body is probe opened whole file stream,read it as first piece
+    #[inline]
+    fn start(
+        inner_status: Arc<DownloadInner>,
+        body: Option<ByteStream>,
+        task_count: u64,
+        block: u64,
+    ) -> Self {
+        let size = inner_status.get_size();
+        let piece_count = size.div_ceil(block);
+        let task_count = task_count.min(piece_count).max(1);
+        let window = Arc::new(Semaphore::new(task_count as usize * 2));
+        let next_piece = Arc::new(AtomicU64::new(0));
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let body = Arc::new(Mutex::new(body));
+        inner_status.is_start.store(true, Ordering::Release);
+
+        let tasks = (0..task_count)
+            .map(|i| {
+                let inner_status = inner_status.clone();
+                let window = window.clone();
+                let next_piece = next_piece.clone();
+                let sender = sender.clone();
+                let body = body.clone();
+                tokio::spawn(async move {
+                    loop {
+                        let permit = match window.clone().acquire_owned().await {
+                            Ok(permit) => permit,
+                            Err(_) => break,
+                        };
+                        let index = next_piece.fetch_add(1, Ordering::AcqRel);
+                        if index >= piece_count {
+                            break;
+                        }
+                        let start = index * block;
+                        let end = (start + block).min(size) - 1;
+                        log::trace!(
+                            "stream task:{} piece:{} start:{} end:{}",
+                            i,
+                            index,
+                            start,
+                            end
+                        );
+                        let body = match index {
+                            0 => body.lock().unwrap().take(),
+                            _ => None,
+                        };
+                        let result = match body {
+                            Some(body) => {
+                                fetch_head_from_body(inner_status.clone(), end, body).await
+                            }
+                            None => fetch_range(inner_status.clone(), start, end).await,
+                        };
+                        let is_error = result.is_err();
+                        if sender.send((index, result, Some(permit))).is_err() || is_error {
+                            break;
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        Self {
+            inner_status,
+            sender: sender.downgrade(),
+            receiver,
+            pieces: BTreeMap::new(),
+            cursor: 0,
+            piece_count,
+            block,
+            chunk: Bytes::new(),
+            tasks,
+            stall: None,
+            hedge: None,
+        }
+    }
+
+    /// start stall timer of cursor piece if later pieces is buffered,
+    /// request cursor piece again when timer fire,
+    /// first arrived data is used and the other is drop
+    #[inline]
+    fn poll_hedge(&mut self, cx: &mut Context<'_>) {
+        if self.pieces.is_empty() {
+            self.stall = None;
+            return;
+        }
+        let cursor = self.cursor;
+        if self.stall.as_ref().map(|(index, _)| *index) != Some(cursor) {
+            self.stall = Some((cursor, Some(Box::pin(sleep(HEDGE_TIMEOUT)))));
+        }
+        let is_stalled = match self.stall.as_mut() {
+            Some((_, Some(timer))) => timer.as_mut().poll(cx).is_ready(),
+            _ => false,
+        };
+        if !is_stalled {
+            return;
+        }
+        self.stall = Some((cursor, None));
+        if let Some(hedge) = self.hedge.take() {
+            hedge.abort();
+        }
+        // all tasks exit,channel close report error
+        let Some(sender) = self.sender.upgrade() else {
+            return;
+        };
+        let size = self.size();
+        let start = cursor * self.block;
+        let end = (start + self.block).min(size) - 1;
+        log::debug!(
+            "stream piece:{} stalled,request again start:{} end:{}",
+            cursor,
+            start,
+            end
+        );
+        let inner_status = self.inner_status.clone();
+        self.hedge = Some(tokio::spawn(async move {
+            match fetch_range(inner_status, start, end).await {
+                Ok(data) => {
+                    let _ = sender.send((cursor, Ok(data), None));
+                }
+                Err(err) => log::warn!("stream hedge piece:{} error:{}", cursor, err),
+            }
+        }));
+    }
+
+    /// get status arc
+    #[inline]
+    pub fn get_status(&self) -> Arc<DownloadInner> {
+        self.inner_status.clone()
+    }
+
+    /// file size
+    #[inline]
+    pub fn size(&self) -> u64 {
+        self.inner_status.get_size()
+    }
+}
+
+impl Stream for DownloadStream {
+    type Item = Result<Bytes>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        loop {
+            if this.cursor >= this.piece_count {
+                if !this.inner_status.is_finish() {
+                    this.inner_status.set_finish();
+                }
+                return Poll::Ready(None);
+            }
+            if let Some((data, permit)) = this.pieces.remove(&this.cursor) {
+                this.cursor += 1;
+                drop(permit);
+                return Poll::Ready(Some(Ok(data)));
+            }
+            match this.receiver.poll_recv(cx) {
+                // duplicate piece of hedge request,data or error of it is drop
+                Poll::Ready(Some((index, result, _)))
+                    if index < this.cursor || this.pieces.contains_key(&index) =>
+                {
+                    if let Err(err) = result {
+                        log::debug!("stream piece:{} already served,drop error:{}", index, err);
+                    }
+                }
+                Poll::Ready(Some((index, Ok(data), permit))) => {
+                    this.pieces.insert(index, (data, permit));
+                }
+                Poll::Ready(Some((index, Err(err), _))) => {
+                    log::error!("download stream piece:{} error:{:?}", index, err);
+                    this.cursor = this.piece_count;
+                    this.pieces.clear();
+                    this.inner_status.set_finish();
+                    return Poll::Ready(Some(Err(err)));
+                }
+                // task panic or abort before send all pieces
+                Poll::Ready(None) => {
+                    log::error!("download stream task exit at piece:{}", this.cursor);
+                    this.cursor = this.piece_count;
+                    this.pieces.clear();
+                    this.inner_status.set_finish();
+                    return Poll::Ready(Some(Err(std::io::Error::other(
+                        "download stream task exit before all pieces",
+                    )
+                    .into())));
+                }
+                Poll::Pending => {
+                    this.poll_hedge(cx);
+                    return Poll::Pending;
+                }
+            }
+        }
+    }
+}
+
+impl AsyncRead for DownloadStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        while self.chunk.is_empty() {
+            match self.as_mut().poll_next(cx) {
+                Poll::Ready(Some(Ok(data))) => self.chunk = data,
+                Poll::Ready(Some(Err(err))) => return Poll::Ready(Err(std::io::Error::other(err))),
+                Poll::Ready(None) => return Poll::Ready(Ok(())),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        let len = self.chunk.len().min(buf.remaining());
+        buf.put_slice(&self.chunk[..len]);
+        self.chunk.advance(len);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Drop for DownloadStream {
+    #[inline]
+    fn drop(&mut self) {
+        for task in self.tasks.iter().chain(self.hedge.iter()) {
+            task.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DownloadStream;
+    use crate::error::DownloadError;
+    use crate::transport::tests::{MemoryTransport, Open};
+    use crate::DownloadInner;
+    use futures_util::StreamExt;
+    use reqwest::Url;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn data(size: usize) -> Vec<u8> {
+        (0..size).map(|i| (i % 251) as u8).collect()
+    }
+
+    fn start(transport: Arc<MemoryTransport>, task_count: u64, block: u64) -> DownloadStream {
+        let size = transport.data.len() as u64;
+        let url = Url::parse("http://127.0.0.1/file.bin").unwrap();
+        let inner_status = Arc::new(DownloadInner::new(url, transport, size, size));
+        DownloadStream::start(inner_status, None, task_count, block)
+    }
+
+    /// read stream to end,return data and errors
+    async fn read_all(mut stream: DownloadStream) -> (Vec<u8>, Vec<DownloadError>) {
+        let mut data = vec![];
+        let mut errors = vec![];
+        while let Some(result) = tokio::time::timeout(Duration::from_secs(60), stream.next())
+            .await
+            .expect("stream hang")
+        {
+            match result {
+                Ok(buf) => data.extend_from_slice(&buf),
+                Err(err) => errors.push(err),
+            }
+        }
+        (data, errors)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reorder_pieces() {
+        let transport = MemoryTransport::new(data(100))
+            .on_open(0, Open::Delay(Duration::from_secs(2)))
+            .on_open(14, Open::Delay(Duration::from_secs(1)))
+            .on_open(35, Open::Delay(Duration::from_secs(3)));
+        let (read, errors) = read_all(start(Arc::new(transport), 4, 7)).await;
+        assert!(errors.is_empty());
+        assert_eq!(read, data(100));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn hedge_piece_drop_late_error() {
+        // piece 0 stall and fail after hedge request served it,last piece keep stream open
+        let transport = Arc::new(
+            MemoryTransport::new(data(40))
+                .on_open(0, Open::Fail(Duration::from_secs(8)))
+                .on_open(30, Open::Delay(Duration::from_secs(12))),
+        );
+        let (read, errors) = read_all(start(transport.clone(), 2, 10)).await;
+        assert!(errors.is_empty());
+        assert_eq!(read, data(40));
+        let requests = transport.requests.lock().unwrap();
+        assert_eq!(requests.iter().filter(|range| **range == (0, 9)).count(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn piece_error_end_stream() {
+        let transport = MemoryTransport::new(data(40)).on_open(20, Open::Fail(Duration::ZERO));
+        let (read, errors) = read_all(start(Arc::new(transport), 1, 10)).await;
+        assert_eq!(read, data(20));
+        assert!(matches!(
+            errors.as_slice(),
+            [DownloadError::RangeNotSupported(_)]
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn task_panic_not_hang() {
+        let transport = MemoryTransport::new(data(40)).on_open(10, Open::Panic);
+        let (read, errors) = read_all(start(Arc::new(transport), 1, 10)).await;
+        assert_eq!(read, data(10));
+        assert_eq!(errors.len(), 1);
+    }
+}
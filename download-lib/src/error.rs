@@ -1,3 +1,5 @@
+use reqwest::header::HeaderMap;
+use reqwest::{StatusCode, Url};
 use thiserror::Error;
 use tokio::task::JoinError;
 
@@ -9,34 +11,191 @@ pub enum DownloadError {
         source: reqwest::Error,
     },
     #[error("io error->{source:?}")]
-    IoError {
-        #[from]
-        source: std::io::Error,
-    },
+    IoError { source: std::io::Error },
     #[error("not get file size ->{0:?}")]
-    NotGetFileSize(reqwest::Url),
-    #[error("save file is finish->{0:?}")]
-    SaveFileFinish(String),
-    #[error("not get file name ->{0:?}")]
-    NotFileName(reqwest::Url),
-    #[error("http error:{0}")]
-    HttpStatusError(String),
+    NotGetFileSize(Url),
+    #[error("save file is closed,can not write->{0:?}")]
+    SaveFileClosed(String),
+    #[error("url has no file name and server not send content-disposition ->{0:?}")]
+    NotFileName(Url),
+    #[error("http status:{status} url:{url} range:{range:?}")]
+    HttpStatus {
+        url: Url,
+        status: u16,
+        headers: Box<HeaderMap>,
+        range: Option<(u64, u64)>,
+    },
+    #[error("server not support range request ->{0:?}")]
+    RangeNotSupported(Url),
+    #[error("size mismatch expected:{expected} actual:{actual}")]
+    SizeMismatch { expected: u64, actual: u64 },
+    #[error("checksum mismatch expected:{expected} actual:{actual}")]
+    ChecksumMismatch { expected: String, actual: String },
+    #[error("disk full->{source:?}")]
+    DiskFull { source: std::io::Error },
+    #[error("download is cancelled")]
+    Cancelled,
+    #[error("connect time out ->{0:?}")]
+    ConnectTimeout(Url),
+    #[error("read time out ->{0:?}")]
+    ReadTimeout(Url),
+    #[error("size:{size} exceeds limit:{limit}")]
+    SizeLimitExceeded { size: u64, limit: u64 },
+    #[error("invalid byte range ->{0}")]
+    InvalidRange(String),
+    #[error("local file is not prefix of remote file,can not append ->{0:?}")]
+    AppendMismatch(Url),
+    #[error("remote file changed while downloading ->{0:?}")]
+    RemoteChanged(Url),
+    #[error("ftp reply:{code} {message} url:{url}")]
+    FtpStatus {
+        url: Url,
+        code: u16,
+        message: String,
+    },
+    #[error("invalid url ->{0}")]
+    InvalidUrl(String),
+    #[error("invalid signature ->{0}")]
+    InvalidSignature(String),
+    #[error("checksum not found in manifest ->{0}")]
+    ChecksumNotFound(String),
+    #[error("unsafe archive entry ->{0}")]
+    UnsafeArchiveEntry(String),
+    #[error("invalid argument ->{0}")]
+    InvalidArgument(String),
     #[error("async join error:{0}")]
     JoinInError(JoinError),
 }
 
-impl From<&DownloadError> for i32 {
-    fn from(v: &DownloadError) -> Self {
-        match v {
+impl DownloadError {
+    /// stable numeric error code,0 is reserved for no error
+    #[inline]
+    pub fn code(&self) -> i32 {
+        match self {
             DownloadError::ReqwestError { .. } => 1,
             DownloadError::IoError { .. } => 2,
             DownloadError::NotGetFileSize { .. } => 3,
-            DownloadError::SaveFileFinish { .. } => 4,
+            DownloadError::SaveFileClosed { .. } => 4,
             DownloadError::NotFileName { .. } => 5,
-            DownloadError::HttpStatusError { .. } => 6,
+            DownloadError::HttpStatus { .. } => 6,
             DownloadError::JoinInError { .. } => 7,
+            DownloadError::RangeNotSupported { .. } => 8,
+            DownloadError::SizeMismatch { .. } => 9,
+            DownloadError::ChecksumMismatch { .. } => 10,
+            DownloadError::DiskFull { .. } => 11,
+            DownloadError::Cancelled => 12,
+            DownloadError::ConnectTimeout { .. } => 13,
+            DownloadError::ReadTimeout { .. } => 14,
+            DownloadError::SizeLimitExceeded { .. } => 15,
+            DownloadError::InvalidRange { .. } => 16,
+            DownloadError::AppendMismatch { .. } => 17,
+            DownloadError::RemoteChanged { .. } => 18,
+            DownloadError::FtpStatus { .. } => 19,
+            DownloadError::InvalidUrl { .. } => 20,
+            DownloadError::InvalidSignature { .. } => 21,
+            DownloadError::ChecksumNotFound { .. } => 22,
+            DownloadError::UnsafeArchiveEntry { .. } => 23,
+            DownloadError::InvalidArgument { .. } => 24,
+        }
+    }
+
+    /// the error is transient,retry the request may succeed
+    #[inline]
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            // builder error like bad header value or url is not transient
+            DownloadError::ReqwestError { source } => {
+                source.is_timeout()
+                    || source.is_connect()
+                    || source.is_body()
+                    || source.status().is_some_and(is_retryable_status)
+            }
+            DownloadError::IoError { source } => matches!(
+                source.kind(),
+                std::io::ErrorKind::Interrupted
+                    | std::io::ErrorKind::ConnectionReset
+                    | std::io::ErrorKind::ConnectionAborted
+                    | std::io::ErrorKind::BrokenPipe
+                    | std::io::ErrorKind::TimedOut
+                    | std::io::ErrorKind::UnexpectedEof
+            ),
+            DownloadError::HttpStatus { status, .. } => {
+                StatusCode::from_u16(*status).is_ok_and(is_retryable_status)
+            }
+            // 4xx reply is transient negative completion
+            DownloadError::FtpStatus { code, .. } => (400..500).contains(code),
+            DownloadError::ConnectTimeout(_) | DownloadError::ReadTimeout(_) => true,
+            _ => false,
+        }
+    }
+
+    /// create http status error
+    #[inline]
+    pub(crate) fn http_status(
+        url: &Url,
+        status: StatusCode,
+        headers: &HeaderMap,
+        range: Option<(u64, u64)>,
+    ) -> Self {
+        DownloadError::HttpStatus {
+            url: url.clone(),
+            status: status.as_u16(),
+            headers: Box::new(headers.clone()),
+            range,
+        }
+    }
+}
+
+#[inline]
+fn is_retryable_status(status: StatusCode) -> bool {
+    status == StatusCode::REQUEST_TIMEOUT
+        || status == StatusCode::TOO_MANY_REQUESTS
+        || status.is_server_error()
+}
+
+impl From<std::io::Error> for DownloadError {
+    fn from(source: std::io::Error) -> Self {
+        if is_disk_full(&source) {
+            DownloadError::DiskFull { source }
+        } else {
+            DownloadError::IoError { source }
         }
     }
 }
 
+/// ssh error is io error of ssh session
+#[cfg(feature = "sftp")]
+impl From<ssh2::Error> for DownloadError {
+    #[inline]
+    fn from(err: ssh2::Error) -> Self {
+        std::io::Error::from(err).into()
+    }
+}
+
+#[cfg(unix)]
+#[inline]
+fn is_disk_full(err: &std::io::Error) -> bool {
+    // ENOSPC
+    err.raw_os_error() == Some(28)
+}
+
+#[cfg(windows)]
+#[inline]
+fn is_disk_full(err: &std::io::Error) -> bool {
+    // ERROR_HANDLE_DISK_FULL,ERROR_DISK_FULL
+    matches!(err.raw_os_error(), Some(39) | Some(112))
+}
+
+#[cfg(not(any(unix, windows)))]
+#[inline]
+fn is_disk_full(_err: &std::io::Error) -> bool {
+    false
+}
+
+impl From<&DownloadError> for i32 {
+    fn from(v: &DownloadError) -> Self {
+        v.code()
+    }
+}
+
 pub type Result<T> = std::result::Result<T, DownloadError>;
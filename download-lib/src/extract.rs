@@ -0,0 +1,750 @@
+use super::decompress::Compression;
+use super::error::{DownloadError, Result};
+use glob::Pattern;
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, ErrorKind, Read, Seek, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tar::EntryType;
+
+/// read buffer size of archive file
+const READ_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// archive format of extract
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    /// tar,maybe compressed
+    Tar(Option<Compression>),
+    Zip,
+}
+
+impl ArchiveFormat {
+    /// detect by file name,like ".tar",".tar.gz",".tgz" and ".zip"
+    #[inline]
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.to_ascii_lowercase();
+        let (stem, extension) = name.rsplit_once('.')?;
+        match extension {
+            "zip" => Some(ArchiveFormat::Zip),
+            "tar" => Some(ArchiveFormat::Tar(None)),
+            "tgz" | "tzst" | "txz" | "tbz2" | "tbz" => {
+                Some(ArchiveFormat::Tar(Compression::from_extension(&name)))
+            }
+            _ if stem.ends_with(".tar") => {
+                Compression::from_extension(&name).map(|x| ArchiveFormat::Tar(Some(x)))
+            }
+            _ => None,
+        }
+    }
+
+    /// detect by magic bytes of file head,compressed data is treat as tar
+    #[inline]
+    pub fn from_magic(head: &[u8]) -> Option<Self> {
+        if head.starts_with(b"PK\x03\x04") || head.starts_with(b"PK\x05\x06") {
+            Some(ArchiveFormat::Zip)
+        } else if head.get(257..262) == Some(b"ustar") {
+            Some(ArchiveFormat::Tar(None))
+        } else {
+            Compression::from_magic(head).map(|x| ArchiveFormat::Tar(Some(x)))
+        }
+    }
+
+    /// detect by file name,then magic bytes of head
+    #[inline]
+    pub fn detect(name: &str, head: &[u8]) -> Option<Self> {
+        Self::from_name(name).or_else(|| Self::from_magic(head))
+    }
+}
+
+/// extract archive options
+#[derive(Debug, Clone, Default)]
+pub struct ExtractOptions {
+    /// target dir,create if not exists,
+    /// every top level entry of archive replace same name entry of dir by rename
+    pub dir: PathBuf,
+    /// remove number of leading path components,entry has no more components is skip
+    pub strip_components: usize,
+    /// only extract entry path or parent dir match any glob,empty is all,
+    /// match path after strip components
+    pub include: Vec<String>,
+    /// skip entry path or parent dir match any glob
+    pub exclude: Vec<String>,
+    /// archive format,none is detect by file name or magic bytes
+    pub format: Option<ArchiveFormat>,
+}
+
+impl ExtractOptions {
+    #[inline]
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            ..Default::default()
+        }
+    }
+}
+
+#[inline]
+fn unsafe_entry(name: &str) -> DownloadError {
+    DownloadError::UnsafeArchiveEntry(name.to_string())
+}
+
+#[inline]
+fn parse_patterns(patterns: &[String]) -> Result<Vec<Pattern>> {
+    patterns
+        .iter()
+        .map(|pattern| {
+            Pattern::new(pattern).map_err(|err| {
+                std::io::Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("invalid glob:{} error:{}", pattern, err),
+                )
+                .into()
+            })
+        })
+        .collect()
+}
+
+/// path or any parent dir match pattern
+#[inline]
+fn is_match(patterns: &[Pattern], path: &Path) -> bool {
+    path.ancestors()
+        .filter(|x| !x.as_os_str().is_empty())
+        .any(|x| patterns.iter().any(|pattern| pattern.matches_path(x)))
+}
+
+/// extract entries to staging dir,not write through symlink
+struct Extractor {
+    root: PathBuf,
+    strip_components: usize,
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+    count: u64,
+    /// dir mode set after all entries,read only dir not block write
+    dirs: Vec<(PathBuf, u32)>,
+}
+
+impl Extractor {
+    /// safe relative path after strip components,none is skip,
+    /// absolute path and ".." is unsafe
+    #[inline]
+    fn entry_path(&self, name: &str) -> Result<Option<PathBuf>> {
+        let mut components = vec![];
+        for component in Path::new(name).components() {
+            match component {
+                Component::Normal(x) => components.push(x),
+                Component::CurDir => {}
+                _ => return Err(unsafe_entry(name)),
+            }
+        }
+        if components.len() <= self.strip_components {
+            return Ok(None);
+        }
+        let path = components[self.strip_components..]
+            .iter()
+            .collect::<PathBuf>();
+        if (!self.include.is_empty() && !is_match(&self.include, &path))
+            || is_match(&self.exclude, &path)
+        {
+            log::trace!("skip archive entry:{}", name);
+            return Ok(None);
+        }
+        Ok(Some(path))
+    }
+
+    /// create parent dirs of path,parent is symlink or file is unsafe
+    #[inline]
+    fn create_parent(&self, path: &Path) -> Result<()> {
+        let mut current = self.root.clone();
+        let parent = path.parent().unwrap_or(Path::new(""));
+        for component in parent.components() {
+            current.push(component);
+            match std::fs::symlink_metadata(&current) {
+                Ok(metadata) if metadata.is_dir() => {}
+                Ok(_) => return Err(unsafe_entry(&path.to_string_lossy())),
+                Err(err) if err.kind() == ErrorKind::NotFound => std::fs::create_dir(&current)?,
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(())
+    }
+
+    /// create parent dirs and remove old file of same path,return full path
+    #[inline]
+    fn prepare(&self, path: &Path) -> Result<PathBuf> {
+        self.create_parent(path)?;
+        let full = self.root.join(path);
+        match std::fs::symlink_metadata(&full) {
+            Ok(metadata) if metadata.is_dir() => std::fs::remove_dir_all(&full)?,
+            Ok(_) => std::fs::remove_file(&full)?,
+            Err(_) => {}
+        }
+        Ok(full)
+    }
+
+    #[inline]
+    fn write_file(&mut self, path: &Path, reader: &mut dyn Read, mode: Option<u32>) -> Result<()> {
+        let full = self.prepare(path)?;
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&full)?;
+        std::io::copy(reader, &mut file)?;
+        if let Some(mode) = mode {
+            set_mode(&full, mode)?;
+        }
+        self.count += 1;
+        Ok(())
+    }
+
+    #[inline]
+    fn create_dir(&mut self, path: &Path, mode: Option<u32>) -> Result<()> {
+        self.create_parent(path)?;
+        let full = self.root.join(path);
+        match std::fs::symlink_metadata(&full) {
+            Ok(metadata) if metadata.is_dir() => {}
+            Ok(_) => {
+                std::fs::remove_file(&full)?;
+                std::fs::create_dir(&full)?;
+            }
+            Err(_) => std::fs::create_dir(&full)?,
+        }
+        if let Some(mode) = mode {
+            self.dirs.push((full, mode));
+        }
+        self.count += 1;
+        Ok(())
+    }
+
+    /// symlink target must be relative,".." only at start and not leave root
+    #[inline]
+    fn create_symlink(&mut self, path: &Path, target: &Path) -> Result<()> {
+        let mut depth = path.components().count() - 1;
+        let mut leading = true;
+        for component in target.components() {
+            match component {
+                Component::ParentDir if leading && depth > 0 => depth -= 1,
+                Component::Normal(_) => leading = false,
+                Component::CurDir => {}
+                _ => {
+                    return Err(unsafe_entry(&format!(
+                        "{} -> {}",
+                        path.to_string_lossy(),
+                        target.to_string_lossy()
+                    )))
+                }
+            }
+        }
+        let full = self.prepare(path)?;
+        symlink(target, &full)?;
+        self.count += 1;
+        Ok(())
+    }
+
+    /// hard link target is entry path of archive,not write through symlink
+    #[inline]
+    fn create_hard_link(&mut self, path: &Path, target: &str) -> Result<()> {
+        let target = match self.entry_path(target)? {
+            Some(target) => target,
+            None => {
+                log::trace!("skip hard link:{:?} of skipped target:{}", path, target);
+                return Ok(());
+            }
+        };
+        self.create_parent(&target)?;
+        let full = self.prepare(path)?;
+        std::fs::hard_link(self.root.join(target), full)?;
+        self.count += 1;
+        Ok(())
+    }
+
+    #[inline]
+    fn extract_tar<R: Read>(&mut self, reader: R) -> Result<()> {
+        let mut archive = tar::Archive::new(reader);
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let name = entry.path()?.to_string_lossy().to_string();
+            let path = match self.entry_path(&name)? {
+                Some(path) => path,
+                None => continue,
+            };
+            let mode = entry.header().mode().ok();
+            match entry.header().entry_type() {
+                EntryType::Regular | EntryType::Continuous => {
+                    self.write_file(&path, &mut entry, mode)?
+                }
+                EntryType::Directory => self.create_dir(&path, mode)?,
+                EntryType::Symlink => {
+                    let target = entry.link_name()?.ok_or_else(|| unsafe_entry(&name))?;
+                    self.create_symlink(&path, &target)?
+                }
+                EntryType::Link => {
+                    let target = entry.link_name()?.ok_or_else(|| unsafe_entry(&name))?;
+                    self.create_hard_link(&path, &target.to_string_lossy())?
+                }
+                entry_type => {
+                    log::trace!("skip archive entry:{} type:{:?}", name, entry_type)
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn extract_zip(&mut self, file: File) -> Result<()> {
+        let mut archive =
+            zip::ZipArchive::new(BufReader::new(file)).map_err(std::io::Error::from)?;
+        for index in 0..archive.len() {
+            let mut file = archive.by_index(index).map_err(std::io::Error::from)?;
+            // some zip tools use windows separator
+            let name = file.name().replace('\\', "/");
+            let path = match self.entry_path(&name)? {
+                Some(path) => path,
+                None => continue,
+            };
+            let mode = file.unix_mode();
+            if file.is_dir() {
+                self.create_dir(&path, mode)?;
+            } else if file.is_symlink() {
+                let mut target = String::new();
+                file.read_to_string(&mut target)?;
+                self.create_symlink(&path, Path::new(&target))?;
+            } else {
+                self.write_file(&path, &mut file, mode)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// set mode of dirs,deepest first
+    #[inline]
+    fn finish(&mut self) -> Result<()> {
+        self.dirs.sort_by(|a, b| b.0.cmp(&a.0));
+        for (path, mode) in self.dirs.drain(..) {
+            set_mode(&path, mode)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+#[inline]
+fn set_mode(path: &Path, mode: u32) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    // not keep setuid,setgid and sticky bit
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode & 0o777))?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+#[inline]
+fn set_mode(_path: &Path, _mode: u32) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(unix)]
+#[inline]
+fn symlink(target: &Path, path: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(target, path)
+}
+
+#[cfg(windows)]
+#[inline]
+fn symlink(target: &Path, path: &Path) -> std::io::Result<()> {
+    std::os::windows::fs::symlink_file(target, path)
+}
+
+/// reader of compressed data
+#[inline]
+fn decoder<'a, R: Read + 'a>(
+    compression: Option<Compression>,
+    reader: R,
+) -> Result<Box<dyn Read + 'a>> {
+    Ok(match compression {
+        None => Box::new(reader),
+        Some(compression) => compression.reader(reader)?,
+    })
+}
+
+/// move every top level entry of staging tree to dir,
+/// old entry of same name is rename to old dir first,
+/// all moved entries are move back when any rename fail
+#[inline]
+fn place(tree: &Path, old: &Path, dir: &Path) -> Result<()> {
+    let mut placed = vec![];
+    let result = (|| {
+        for entry in std::fs::read_dir(tree)? {
+            let name = entry?.file_name();
+            let target = dir.join(&name);
+            let is_replace = std::fs::symlink_metadata(&target).is_ok();
+            if is_replace {
+                std::fs::rename(&target, old.join(&name))?;
+                log::trace!("replace old entry:{:?}", target);
+            }
+            placed.push((name.clone(), is_replace));
+            std::fs::rename(tree.join(&name), &target)?;
+        }
+        Ok::<_, DownloadError>(())
+    })();
+    if result.is_err() {
+        rollback(tree, old, dir, &placed);
+    }
+    result
+}
+
+/// move placed entries back to tree and old entries back to dir,
+/// old entry fail to restore is left in old dir
+#[inline]
+fn rollback(tree: &Path, old: &Path, dir: &Path, placed: &[(OsString, bool)]) {
+    for (name, is_replace) in placed.iter().rev() {
+        let target = dir.join(name);
+        let source = tree.join(name);
+        if std::fs::symlink_metadata(&source).is_err() {
+            if let Err(err) = std::fs::rename(&target, &source) {
+                log::error!("move back entry:{:?} error:{}", target, err);
+                continue;
+            }
+        }
+        if *is_replace {
+            match std::fs::rename(old.join(name), &target) {
+                Ok(_) => log::trace!("restore old entry:{:?}", target),
+                Err(err) => log::error!("restore old entry:{:?} error:{}", target, err),
+            }
+        }
+    }
+}
+
+/// extract archive in blocking thread,return count of extracted entries
+pub(crate) fn extract_archive_blocking(archive: &Path, options: &ExtractOptions) -> Result<u64> {
+    let mut file = File::open(archive)?;
+    let format = match options.format {
+        Some(format) => format,
+        None => {
+            let mut head = vec![0; 512];
+            let len = file.read(&mut head)?;
+            file.seek(SeekFrom::Start(0))?;
+            let name = archive
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default();
+            ArchiveFormat::detect(&name, &head[..len]).ok_or_else(|| {
+                std::io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("unknown archive format of file:{}", name),
+                )
+            })?
+        }
+    };
+    std::fs::create_dir_all(&options.dir)?;
+    // staging dir in target dir,rename is not cross file system
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_nanos())
+        .unwrap_or_default();
+    let staging = options
+        .dir
+        .join(format!(".durl-extract-{}-{}", std::process::id(), nanos));
+    let tree = staging.join("tree");
+    let old = staging.join("old");
+    std::fs::create_dir(&staging)?;
+    log::trace!(
+        "extract {:?} archive:{:?} to staging:{:?}",
+        format,
+        archive,
+        staging
+    );
+    let result = (|| {
+        std::fs::create_dir(&tree)?;
+        std::fs::create_dir(&old)?;
+        let mut extractor = Extractor {
+            root: tree.clone(),
+            strip_components: options.strip_components,
+            include: parse_patterns(&options.include)?,
+            exclude: parse_patterns(&options.exclude)?,
+            count: 0,
+            dirs: vec![],
+        };
+        match format {
+            ArchiveFormat::Tar(compression) => extractor.extract_tar(decoder(
+                compression,
+                BufReader::with_capacity(READ_BUFFER_SIZE, file),
+            )?)?,
+            ArchiveFormat::Zip => extractor.extract_zip(file)?,
+        }
+        extractor.finish()?;
+        place(&tree, &old, &options.dir)?;
+        Ok::<_, DownloadError>(extractor.count)
+    })();
+    // old entries not restored by rollback are not deleted
+    let is_keep_old = result.is_err()
+        && std::fs::read_dir(&old).is_ok_and(|mut entries| entries.next().is_some());
+    if is_keep_old {
+        log::error!(
+            "extract to dir:{:?} fail,old entries are keep in:{:?}",
+            options.dir,
+            old
+        );
+    } else if let Err(err) = std::fs::remove_dir_all(&staging) {
+        log::warn!("delete dir:{:?} error:{}", staging, err);
+    }
+    let count = result?;
+    log::trace!(
+        "extract archive:{:?} to dir:{:?} entries:{}",
+        archive,
+        options.dir,
+        count
+    );
+    Ok(count)
+}
+
+/// extract tar,compressed tar or zip archive to options dir,
+/// entries extract to staging dir first then rename into place,
+/// entry path leave dir or write through symlink is unsafe,
+/// return count of extracted entries
+#[inline]
+pub async fn extract_archive(archive: &Path, options: &ExtractOptions) -> Result<u64> {
+    let archive = archive.to_path_buf();
+    let options = options.clone();
+    tokio::task::spawn_blocking(move || extract_archive_blocking(&archive, &options))
+        .await
+        .map_err(DownloadError::JoinInError)?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{extract_archive_blocking, place, ExtractOptions};
+    use crate::error::DownloadError;
+    use std::io::Write;
+    use std::path::{Path, PathBuf};
+    use tar::EntryType;
+
+    /// tar entry of test archive
+    enum Item<'a> {
+        File(&'a str, &'a [u8]),
+        Dir(&'a str),
+        Symlink(&'a str, &'a str),
+        Link(&'a str, &'a str),
+    }
+
+    /// tar data of items,name and link name write raw,builder reject unsafe path
+    fn tar(items: &[Item]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(vec![]);
+        for item in items {
+            let (name, link, data, entry_type): (_, _, &[u8], _) = match *item {
+                Item::File(name, data) => (name, "", data, EntryType::Regular),
+                Item::Dir(name) => (name, "", &[], EntryType::Directory),
+                Item::Symlink(name, link) => (name, link, &[], EntryType::Symlink),
+                Item::Link(name, link) => (name, link, &[], EntryType::Link),
+            };
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(entry_type);
+            header.set_size(data.len() as u64);
+            header.set_mode(0o755);
+            let old = header.as_old_mut();
+            old.name[..name.len()].copy_from_slice(name.as_bytes());
+            old.linkname[..link.len()].copy_from_slice(link.as_bytes());
+            header.set_cksum();
+            builder.append(&header, data).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    /// extract archive data of name to "<root>/out",return result
+    fn extract(
+        root: &Path,
+        name: &str,
+        data: &[u8],
+        strip_components: usize,
+    ) -> crate::Result<u64> {
+        let archive = root.join(name);
+        std::fs::write(&archive, data).unwrap();
+        let options = ExtractOptions {
+            strip_components,
+            ..ExtractOptions::new(root.join("out"))
+        };
+        extract_archive_blocking(&archive, &options)
+    }
+
+    /// unsafe archive is reject,out dir is not changed and staging dir is removed
+    fn assert_reject(name: &str, items: &[Item]) {
+        let root = temp_dir(name);
+        std::fs::create_dir_all(root.join("out")).unwrap();
+        std::fs::write(root.join("out").join("keep"), b"keep").unwrap();
+        let result = extract(&root, "a.tar", &tar(items), 0);
+        assert!(
+            matches!(result, Err(DownloadError::UnsafeArchiveEntry(_))),
+            "{} not reject",
+            name
+        );
+        let names = std::fs::read_dir(root.join("out"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["keep"]);
+        assert!(!root.join("x").exists());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    /// empty temp dir of name
+    fn temp_dir(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("extract-test-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        path
+    }
+
+    #[test]
+    fn place_replace_entries() {
+        let root = temp_dir("place");
+        let (tree, old, dir) = (root.join("tree"), root.join("old"), root.join("dir"));
+        for path in [&tree, &old, &dir] {
+            std::fs::create_dir(path).unwrap();
+        }
+        std::fs::write(tree.join("a"), b"new a").unwrap();
+        std::fs::write(tree.join("b"), b"new b").unwrap();
+        std::fs::write(dir.join("b"), b"old b").unwrap();
+        place(&tree, &old, &dir).unwrap();
+        assert_eq!(std::fs::read(dir.join("a")).unwrap(), b"new a");
+        assert_eq!(std::fs::read(dir.join("b")).unwrap(), b"new b");
+        assert_eq!(std::fs::read(old.join("b")).unwrap(), b"old b");
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn place_rollback_on_error() {
+        let root = temp_dir("rollback");
+        let (tree, old, dir) = (root.join("tree"), root.join("old"), root.join("dir"));
+        for path in [&tree, &old, &dir] {
+            std::fs::create_dir(path).unwrap();
+        }
+        std::fs::write(tree.join("a"), b"new a").unwrap();
+        std::fs::write(tree.join("b"), b"new b").unwrap();
+        std::fs::write(tree.join("c"), b"new c").unwrap();
+        std::fs::write(dir.join("b"), b"old b").unwrap();
+        // move old dir c to not empty dir of same name fail
+        std::fs::create_dir_all(dir.join("c")).unwrap();
+        std::fs::create_dir_all(old.join("c").join("x")).unwrap();
+        assert!(place(&tree, &old, &dir).is_err());
+        let mut names = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, ["b", "c"]);
+        assert_eq!(std::fs::read(dir.join("b")).unwrap(), b"old b");
+        assert!(dir.join("c").is_dir());
+        assert!(!old.join("b").exists());
+        assert_eq!(std::fs::read_dir(&tree).unwrap().count(), 3);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn reject_parent_dir_entry() {
+        assert_reject("dotdot", &[Item::File("a", b"a"), Item::File("../x", b"x")]);
+        assert_reject("inner-dotdot", &[Item::File("a/../../x", b"x")]);
+    }
+
+    #[test]
+    fn reject_absolute_entry() {
+        let root = temp_dir("absolute-target");
+        let target = root.join("x").to_string_lossy().to_string();
+        assert_reject("absolute", &[Item::File(&target, b"x")]);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn reject_escape_symlink() {
+        assert_reject("symlink-dotdot", &[Item::Symlink("a/link", "../../x")]);
+        assert_reject("symlink-absolute", &[Item::Symlink("link", "/etc")]);
+        assert_reject("symlink-middle", &[Item::Symlink("a/link", "b/../../../x")]);
+    }
+
+    #[test]
+    fn reject_write_through_symlink() {
+        // link itself is safe,file under it is not written through it
+        assert_reject(
+            "through-symlink",
+            &[
+                Item::Dir("sub"),
+                Item::Symlink("s", "sub"),
+                Item::File("s/x", b"x"),
+            ],
+        );
+    }
+
+    #[test]
+    fn reject_hard_link_outside() {
+        assert_reject("link-dotdot", &[Item::Link("h", "../x")]);
+        assert_reject("link-absolute", &[Item::Link("h", "/etc/passwd")]);
+    }
+
+    #[test]
+    fn extract_tar_gz() {
+        let root = temp_dir("tar-gz");
+        let data = tar(&[
+            Item::Dir("app"),
+            Item::File("app/bin/app", b"binary"),
+            Item::Symlink("app/lib/link", "../bin/app"),
+            Item::Link("app/hard", "app/bin/app"),
+        ]);
+        let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        encoder.write_all(&data).unwrap();
+        let count = extract(&root, "a.tar.gz", &encoder.finish().unwrap(), 0).unwrap();
+        assert_eq!(count, 4);
+        let out = root.join("out").join("app");
+        assert_eq!(std::fs::read(out.join("bin/app")).unwrap(), b"binary");
+        assert_eq!(std::fs::read(out.join("lib/link")).unwrap(), b"binary");
+        assert_eq!(std::fs::read(out.join("hard")).unwrap(), b"binary");
+        assert!(std::fs::symlink_metadata(out.join("lib/link"))
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(out.join("bin/app"))
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o755);
+        }
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn extract_zip() {
+        let root = temp_dir("zip");
+        let mut writer = zip::ZipWriter::new(std::io::Cursor::new(vec![]));
+        let options = zip::write::SimpleFileOptions::default();
+        writer.add_directory("app/", options).unwrap();
+        writer.start_file("app/readme.txt", options).unwrap();
+        writer.write_all(b"readme").unwrap();
+        writer.start_file("app\\win.txt", options).unwrap();
+        writer.write_all(b"win").unwrap();
+        let data = writer.finish().unwrap().into_inner();
+        assert_eq!(extract(&root, "a.zip", &data, 0).unwrap(), 3);
+        let out = root.join("out").join("app");
+        assert_eq!(std::fs::read(out.join("readme.txt")).unwrap(), b"readme");
+        assert_eq!(std::fs::read(out.join("win.txt")).unwrap(), b"win");
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn extract_strip_components() {
+        let root = temp_dir("strip");
+        let data = tar(&[
+            Item::File("top", b"top"),
+            Item::Dir("app-1.0"),
+            Item::File("app-1.0/bin/app", b"binary"),
+            Item::File("app-1.0/readme", b"readme"),
+        ]);
+        assert_eq!(extract(&root, "a.tar", &data, 1).unwrap(), 2);
+        let out = root.join("out");
+        assert_eq!(std::fs::read(out.join("bin/app")).unwrap(), b"binary");
+        assert_eq!(std::fs::read(out.join("readme")).unwrap(), b"readme");
+        assert!(!out.join("top").exists());
+        assert!(!out.join("app-1.0").exists());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
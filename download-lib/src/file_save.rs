@@ -1,132 +1,518 @@
-use super::error::DownloadError::SaveFileFinish;
+use super::cache::DownloadCache;
+use super::error::DownloadError::{self, JoinInError, SaveFileClosed};
 use super::error::Result;
-use aqueue::Actor;
-use std::io::SeekFrom;
-use std::path::PathBuf;
-use tokio::fs::File;
-use tokio::io::{AsyncSeekExt, AsyncWriteExt};
-
-/// file save actor
-pub(crate) struct FileSave {
+use super::extract::{extract_archive_blocking, ExtractOptions};
+use super::http_cache::CachePolicy;
+use super::piece_hash::{HashType, PieceHashes, PieceManifest, PieceTracker};
+use super::signature::MinisignVerifier;
+use bytes::{Bytes, BytesMut};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::task::JoinHandle;
+
+/// write buffer size of one segment,small network chunk merge to it
+const WRITE_BUFFER_SIZE: usize = 1024 * 1024;
+/// flush write end offset align size
+const WRITE_ALIGN_SIZE: u64 = 64 * 1024;
+
+/// local file save,data write to temp file by offset in blocking pool,
+/// finish rename to real path
+pub struct FileSave {
     save_path: PathBuf,
     real_path: PathBuf,
-    len: u64,
-    file: Option<File>,
+    append_offset: Option<u64>,
+    file: Mutex<Option<Arc<File>>>,
+    /// piece hash while writing,manifest pieces and remote pieces may both track
+    pieces: Arc<Mutex<Vec<PieceTracker>>>,
+    /// piece length and remote validator of manifest save after finish
+    manifest: Option<(u64, Option<String>)>,
+    /// cache and url,remote validator,http caching policy of file add to cache after finish
+    cache: Option<(DownloadCache, String, Option<String>, CachePolicy)>,
+    /// verify signature of temp file before rename
+    signature: Option<Arc<MinisignVerifier>>,
+    /// extract archive after rename
+    extract: Option<ExtractOptions>,
 }
 
 impl FileSave {
-    /// create file save actor
+    /// create file save
     #[inline]
-    pub fn create(real_path: PathBuf, len: u64) -> Result<Actor<FileSave>> {
+    pub fn create(real_path: PathBuf) -> Result<FileSave> {
         let save_path = real_path.with_extension("dd");
         if save_path.exists() {
             std::fs::remove_file(save_path.as_path())?;
             log::trace!("delete old file:{:?}", save_path);
         }
-        Ok(Actor::new(Self {
+        Ok(Self {
             save_path,
             real_path,
-            len,
-            file: None,
-        }))
+            append_offset: None,
+            file: Mutex::new(None),
+            pieces: Default::default(),
+            manifest: None,
+            cache: None,
+            signature: None,
+            extract: None,
+        })
+    }
+
+    /// open file save in append mode,data write to real path after offset,
+    /// abort will truncate file to offset
+    #[inline]
+    pub fn open_append(real_path: PathBuf, offset: u64) -> FileSave {
+        Self {
+            save_path: real_path.clone(),
+            real_path,
+            append_offset: Some(offset),
+            file: Mutex::new(None),
+            pieces: Default::default(),
+            manifest: None,
+            cache: None,
+            signature: None,
+            extract: None,
+        }
+    }
+
+    /// save sha256 piece hashes to "<file>.pieces" after finish,
+    /// validator is remote etag or last modified,next download check it to use manifest
+    #[inline]
+    pub fn save_piece_manifest(mut self, length: u64, validator: Option<String>) -> Self {
+        if self.append_offset.is_none() {
+            self.manifest = Some((length.max(1), validator));
+        }
+        self
+    }
+
+    /// add file to cache after finish,ignored in append mode or response is no store
+    #[inline]
+    pub fn save_to_cache(
+        mut self,
+        cache: DownloadCache,
+        url: String,
+        validator: Option<String>,
+        policy: CachePolicy,
+    ) -> Self {
+        if self.append_offset.is_none() && !policy.no_store {
+            self.cache = Some((cache, url, validator, policy));
+        }
+        self
+    }
+
+    /// verify minisign signature of temp file before rename,
+    /// invalid signature delete temp file,ignored in append mode
+    #[inline]
+    pub fn verify_signature(mut self, verifier: MinisignVerifier) -> Self {
+        if self.append_offset.is_none() {
+            self.signature = Some(Arc::new(verifier));
+        }
+        self
     }
 
+    /// extract archive to dir after finish,extract fail is download error
     #[inline]
-    async fn init(&mut self) -> Result<()> {
+    pub fn extract_to(mut self, options: ExtractOptions) -> Self {
+        self.extract = Some(options);
+        self
+    }
+
+    /// get open file
+    #[inline]
+    fn get_file(&self) -> Result<Arc<File>> {
+        self.file
+            .lock()
+            .unwrap()
+            .clone()
+            .ok_or_else(|| SaveFileClosed(self.real_path.to_string_lossy().to_string()))
+    }
+
+    /// get save file path
+    #[inline]
+    pub fn get_save_file_path(&self) -> String {
+        self.save_path.to_string_lossy().to_string()
+    }
+
+    /// get real file save path
+    #[inline]
+    pub fn get_real_file_path(&self) -> String {
+        self.real_path.to_string_lossy().to_string()
+    }
+}
+
+/// download data save
+#[async_trait::async_trait]
+pub trait IFileSave: Send + Sync {
+    /// init save,size is download data size
+    async fn init(&self, size: u64) -> Result<()>;
+    /// write data,need offset,may be called concurrently
+    async fn write_all_by_offset(&self, data: Bytes, offset: u64) -> Result<()>;
+    /// all data write finish
+    async fn finish(&self) -> Result<()>;
+    /// download is fail,discard data
+    async fn abort(&self) -> Result<()>;
+    /// sha256 hex of all write data,call before finish,
+    /// none is not support,download not verify checksum
+    async fn sha256(&self) -> Result<Option<String>> {
+        Ok(None)
+    }
+    /// hex hash of write data range,end is included,call before finish,
+    /// none is not support read back,download not verify piece
+    async fn hash_range(
+        &self,
+        _hash_type: HashType,
+        _start: u64,
+        _end: u64,
+    ) -> Result<Option<String>> {
+        Ok(None)
+    }
+    /// hash pieces while writing,size is download data size,
+    /// hash_range of piece not need read back
+    fn track_pieces(&self, _pieces: &PieceHashes, _size: u64) {}
+}
+
+#[async_trait::async_trait]
+impl IFileSave for FileSave {
+    #[inline]
+    async fn init(&self, size: u64) -> Result<()> {
+        let offset = self.append_offset.unwrap_or_default();
         let file = tokio::fs::OpenOptions::new()
             .create(true)
+            .truncate(self.append_offset.is_none())
             .write(true)
             .open(self.save_path.as_path())
             .await?;
-        file.set_len(self.len).await?;
-        log::trace!("create file:{:?} size:{}", self.save_path, self.len);
-        self.file = Some(file);
+        file.set_len(offset + size).await?;
+        log::trace!(
+            "create file:{:?} size:{} append offset:{}",
+            self.save_path,
+            size,
+            offset
+        );
+        *self.file.lock().unwrap() = Some(Arc::new(file.into_std().await));
+        *self.pieces.lock().unwrap() = self
+            .manifest
+            .iter()
+            .map(|(length, _)| PieceTracker::new(*length, HashType::Sha256, size))
+            .collect();
         Ok(())
     }
 
-    /// write data to file,need offset
     #[inline]
-    async fn write_all_by_offset(&mut self, data: &[u8], offset: u64) -> Result<()> {
-        let file = self
-            .file
-            .as_mut()
-            .ok_or_else(|| SaveFileFinish(self.real_path.to_string_lossy().to_string()))?;
-        file.seek(SeekFrom::Start(offset)).await?;
-        file.write_all(data).await?;
+    async fn write_all_by_offset(&self, data: Bytes, offset: u64) -> Result<()> {
+        let file = self.get_file()?;
+        let pieces = self.pieces.clone();
+        let append_offset = self.append_offset.unwrap_or_default();
+        tokio::task::spawn_blocking(move || {
+            write_all_at(&file, &data, offset + append_offset)?;
+            for pieces in pieces.lock().unwrap().iter_mut() {
+                pieces.update(offset, &data);
+            }
+            Ok::<_, std::io::Error>(())
+        })
+        .await
+        .map_err(JoinInError)??;
         Ok(())
     }
 
-    /// write data
     #[inline]
-    async fn write_all(&mut self, data: &[u8]) -> Result<()> {
-        let file = self
-            .file
-            .as_mut()
-            .ok_or_else(|| SaveFileFinish(self.real_path.to_string_lossy().to_string()))?;
-        file.write_all(data).await?;
+    async fn finish(&self) -> Result<()> {
+        let file = self.file.lock().unwrap().take();
+        if let Some(file) = file {
+            let save_path = self.save_path.clone();
+            let real_path = self.real_path.clone();
+            let manifest = self.manifest.clone();
+            let pieces = std::mem::take(&mut *self.pieces.lock().unwrap())
+                .into_iter()
+                .find(|pieces| {
+                    manifest.as_ref().is_some_and(|(length, _)| {
+                        pieces.hash_type() == HashType::Sha256 && pieces.length() == *length
+                    })
+                });
+            let cache = self.cache.clone();
+            let signature = self.signature.clone();
+            let extract = self.extract.clone();
+            tokio::task::spawn_blocking(move || {
+                file.sync_all()?;
+                drop(file);
+                if let Some(signature) = signature {
+                    if let Err(err) = signature.verify_file(&save_path) {
+                        std::fs::remove_file(&save_path)?;
+                        log::trace!("delete file:{:?} of invalid signature", save_path);
+                        return Err(err);
+                    }
+                }
+                let manifest = match (manifest, pieces) {
+                    (Some((_, validator)), Some(pieces)) => Some(PieceManifest {
+                        size: pieces.size(),
+                        validator,
+                        pieces: pieces.hashes(|start, end| {
+                            hash_file(&save_path, pieces.hash_type(), start, end - start + 1)
+                        })?,
+                    }),
+                    _ => None,
+                };
+                if save_path != real_path {
+                    std::fs::rename(&save_path, &real_path)?;
+                }
+                if let Some(manifest) = manifest {
+                    manifest.save(&PieceManifest::path(&real_path))?;
+                    log::trace!("save piece manifest of file:{:?}", real_path);
+                }
+                if let Some((cache, url, validator, policy)) = cache {
+                    if let Err(err) = cache.insert(&url, validator.as_deref(), &policy, &real_path)
+                    {
+                        log::warn!("add file:{:?} to cache error:{}", real_path, err);
+                    }
+                }
+                if let Some(extract) = extract {
+                    extract_archive_blocking(&real_path, &extract)?;
+                }
+                Ok::<_, DownloadError>(())
+            })
+            .await
+            .map_err(JoinInError)??;
+        }
         Ok(())
     }
 
-    /// finish save file and rename real name
     #[inline]
-    async fn finish(&mut self) -> Result<()> {
-        if let Some(mut file) = self.file.take() {
-            file.flush().await?;
-            drop(file);
-            std::fs::rename(self.save_path.as_path(), self.real_path.as_path())?;
+    async fn abort(&self) -> Result<()> {
+        let file = self.file.lock().unwrap().take();
+        if let Some(file) = file {
+            if let Some(offset) = self.append_offset {
+                tokio::task::spawn_blocking(move || file.set_len(offset))
+                    .await
+                    .map_err(JoinInError)??;
+                log::trace!("truncate fail file:{:?} to:{}", self.save_path, offset);
+            } else {
+                drop(file);
+                tokio::fs::remove_file(self.save_path.as_path()).await?;
+                log::trace!("delete fail file:{:?}", self.save_path);
+            }
         }
         Ok(())
     }
+
+    #[inline]
+    async fn sha256(&self) -> Result<Option<String>> {
+        let save_path = self.save_path.clone();
+        let offset = self.append_offset.unwrap_or_default();
+        let sha256 = tokio::task::spawn_blocking(move || {
+            hash_file(&save_path, HashType::Sha256, offset, u64::MAX)
+        })
+        .await
+        .map_err(JoinInError)??;
+        Ok(Some(sha256))
+    }
+
+    #[inline]
+    async fn hash_range(
+        &self,
+        hash_type: HashType,
+        start: u64,
+        end: u64,
+    ) -> Result<Option<String>> {
+        let hash = self
+            .pieces
+            .lock()
+            .unwrap()
+            .iter()
+            .find_map(|pieces| pieces.hash(hash_type, start, end));
+        if hash.is_some() {
+            return Ok(hash);
+        }
+        let save_path = self.save_path.clone();
+        let offset = self.append_offset.unwrap_or_default();
+        let hash = tokio::task::spawn_blocking(move || {
+            hash_file(&save_path, hash_type, offset + start, end - start + 1)
+        })
+        .await
+        .map_err(JoinInError)??;
+        Ok(Some(hash))
+    }
+
+    /// keep manifest tracker,add remote pieces tracker if not same
+    #[inline]
+    fn track_pieces(&self, pieces: &PieceHashes, size: u64) {
+        let mut trackers = self.pieces.lock().unwrap();
+        if !trackers.iter().any(|tracker| {
+            tracker.hash_type() == pieces.hash_type && tracker.length() == pieces.length.max(1)
+        }) {
+            trackers.push(PieceTracker::new(pieces.length, pieces.hash_type, size));
+        }
+    }
 }
 
-#[async_trait::async_trait]
-pub(crate) trait IFileSave {
-    /// init file
-    async fn init(&self) -> Result<()>;
-    /// write data
-    async fn write_all(&self, data: &[u8]) -> Result<()>;
-    /// write data to file,need offset
-    async fn write_all_by_offset(&self, data: &[u8], offset: u64) -> Result<()>;
-    /// finish save file and rename real name
-    async fn finish(&self) -> Result<()>;
-    /// get save file path
-    fn get_save_file_path(&self) -> String;
-    /// get real file save path
-    fn get_real_file_path(&self) -> String;
+/// hex hash of file data,read len bytes from start or until end of file
+#[inline]
+pub(crate) fn hash_file(
+    path: &Path,
+    hash_type: HashType,
+    start: u64,
+    len: u64,
+) -> std::io::Result<String> {
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(start))?;
+    let mut reader = file.take(len);
+    let mut hasher = hash_type.hasher();
+    let mut buf = vec![0; WRITE_BUFFER_SIZE];
+    loop {
+        let len = reader.read(&mut buf)?;
+        if len == 0 {
+            break;
+        }
+        hasher.update(&buf[..len]);
+    }
+    Ok(hex::encode(hasher.finalize()))
 }
 
-#[async_trait::async_trait]
-impl IFileSave for Actor<FileSave> {
-    #[inline]
-    async fn init(&self) -> Result<()> {
-        self.inner_call(|inner| async move { inner.get_mut().init().await })
-            .await
+#[cfg(unix)]
+#[inline]
+fn write_all_at(file: &File, data: &[u8], offset: u64) -> std::io::Result<()> {
+    use std::os::unix::fs::FileExt;
+    file.write_all_at(data, offset)
+}
+
+#[cfg(windows)]
+#[inline]
+fn write_all_at(file: &File, mut data: &[u8], mut offset: u64) -> std::io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !data.is_empty() {
+        match file.seek_write(data, offset) {
+            Ok(0) => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::WriteZero,
+                    "failed to write whole buffer",
+                ))
+            }
+            Ok(n) => {
+                data = &data[n..];
+                offset += n as u64;
+            }
+            Err(ref err) if err.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
     }
+    Ok(())
+}
 
+/// segment writer,merge small chunk to big buffer,
+/// write to file in background when buffer full
+pub(crate) struct SegmentWriter<S> {
+    save_file: Arc<S>,
+    buffer: BytesMut,
+    offset: u64,
+    pending: Option<JoinHandle<Result<()>>>,
+}
+
+impl<S: IFileSave + 'static> SegmentWriter<S> {
     #[inline]
-    async fn write_all(&self, data: &[u8]) -> Result<()> {
-        self.inner_call(|inner| async move { inner.get_mut().write_all(data).await })
-            .await
+    pub fn new(save_file: Arc<S>) -> Self {
+        Self {
+            save_file,
+            buffer: BytesMut::with_capacity(WRITE_BUFFER_SIZE),
+            offset: 0,
+            pending: None,
+        }
     }
 
+    /// write data,offset is data start position in file
     #[inline]
-    async fn write_all_by_offset(&self, data: &[u8], offset: u64) -> Result<()> {
-        self.inner_call(
-            |inner| async move { inner.get_mut().write_all_by_offset(data, offset).await },
-        )
-        .await
+    pub async fn write(&mut self, data: &[u8], offset: u64) -> Result<()> {
+        if self.buffer.is_empty() {
+            self.offset = offset;
+        } else if self.offset + self.buffer.len() as u64 != offset {
+            self.flush().await?;
+            self.offset = offset;
+        }
+        self.buffer.extend_from_slice(data);
+        if self.buffer.len() >= WRITE_BUFFER_SIZE {
+            // write to align offset,the tail stay in buffer
+            let end = self.offset + self.buffer.len() as u64;
+            let len = self.buffer.len() - (end % WRITE_ALIGN_SIZE) as usize;
+            self.spawn_write(len).await?;
+        }
+        Ok(())
     }
+
+    /// write all buffer data and wait finish
     #[inline]
-    async fn finish(&self) -> Result<()> {
-        self.inner_call(|inner| async move { inner.get_mut().finish().await })
-            .await
+    pub async fn flush(&mut self) -> Result<()> {
+        if !self.buffer.is_empty() {
+            self.spawn_write(self.buffer.len()).await?;
+        }
+        self.wait_pending().await
     }
+
     #[inline]
-    fn get_save_file_path(&self) -> String {
-        unsafe { self.deref_inner().save_path.to_string_lossy().to_string() }
+    async fn spawn_write(&mut self, len: usize) -> Result<()> {
+        self.wait_pending().await?;
+        let data = self.buffer.split_to(len).freeze();
+        let offset = self.offset;
+        self.offset += len as u64;
+        let save_file = self.save_file.clone();
+        self.pending = Some(tokio::spawn(async move {
+            save_file.write_all_by_offset(data, offset).await
+        }));
+        Ok(())
     }
+
     #[inline]
-    fn get_real_file_path(&self) -> String {
-        unsafe { self.deref_inner().real_path.to_string_lossy().to_string() }
+    async fn wait_pending(&mut self) -> Result<()> {
+        if let Some(pending) = self.pending.take() {
+            pending.await.map_err(JoinInError)??;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FileSave, IFileSave};
+    use crate::error::DownloadError;
+    use crate::signature::tests::{DATA, PUBLIC_KEY, SIGNATURE};
+    use crate::signature::MinisignVerifier;
+    use bytes::Bytes;
+    use std::path::PathBuf;
+
+    /// empty temp dir of name
+    fn temp_dir(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("file-save-test-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        path
+    }
+
+    /// write data and finish file save with signature
+    async fn save_signed(real_path: PathBuf, data: &[u8]) -> crate::Result<()> {
+        let verifier = MinisignVerifier::new(PUBLIC_KEY, SIGNATURE).unwrap();
+        let save = FileSave::create(real_path)?.verify_signature(verifier);
+        save.init(data.len() as u64).await?;
+        save.write_all_by_offset(Bytes::copy_from_slice(data), 0)
+            .await?;
+        save.finish().await
+    }
+
+    #[tokio::test]
+    async fn finish_verify_signature() {
+        let dir = temp_dir("signature");
+        let real_path = dir.join("test.txt");
+        save_signed(real_path.clone(), DATA).await.unwrap();
+        assert_eq!(std::fs::read(&real_path).unwrap(), DATA);
+        assert!(!dir.join("test.dd").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn finish_remove_file_of_bad_signature() {
+        let dir = temp_dir("bad-signature");
+        let real_path = dir.join("test.txt");
+        let result = save_signed(real_path.clone(), b"minisign test data!").await;
+        assert!(matches!(result, Err(DownloadError::InvalidSignature(_))));
+        assert!(!dir.join("test.dd").exists());
+        assert!(!real_path.exists());
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 }
@@ -0,0 +1,604 @@
+use super::error::{DownloadError, Result};
+use super::transport::{ByteStream, ITransport, RemoteInfo};
+use super::DownloadInner;
+use bytes::Bytes;
+use percent_encoding::percent_decode_str;
+use reqwest::Url;
+use std::io::ErrorKind;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::io::{
+    AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader, ReadBuf,
+};
+use tokio::net::TcpStream;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::rustls::{ClientConfig, OwnedTrustAnchor, RootCertStore, ServerName};
+use tokio_rustls::TlsConnector;
+
+/// implicit ftps port,other port use explicit auth tls
+const IMPLICIT_FTPS_PORT: u16 = 990;
+/// data connection read buffer size
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+/// ftp control or data connection,plain or tls
+enum FtpStream {
+    Tcp(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+}
+
+impl AsyncRead for FtpStream {
+    #[inline]
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            FtpStream::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+            FtpStream::Tls(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for FtpStream {
+    #[inline]
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        match self.get_mut() {
+            FtpStream::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+            FtpStream::Tls(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    #[inline]
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            FtpStream::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+            FtpStream::Tls(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    #[inline]
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            FtpStream::Tcp(stream) => Pin::new(stream).poll_shutdown(cx),
+            FtpStream::Tls(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+/// ftp reply code and last line message
+struct Reply {
+    code: u16,
+    message: String,
+}
+
+/// logged in ftp control connection
+struct FtpConnection {
+    url: Url,
+    control: BufReader<FtpStream>,
+    peer: SocketAddr,
+    tls: Option<(TlsConnector, ServerName)>,
+}
+
+impl FtpConnection {
+    /// connect,auth tls if ftps,login and set binary mode
+    #[inline]
+    async fn connect(url: &Url, tls_config: &Arc<ClientConfig>) -> Result<Self> {
+        let host = url
+            .host_str()
+            .ok_or_else(|| std::io::Error::new(ErrorKind::InvalidInput, "ftp url has no host"))?;
+        let is_tls = url.scheme() == "ftps";
+        let port = url
+            .port()
+            .unwrap_or(if is_tls { IMPLICIT_FTPS_PORT } else { 21 });
+        let tcp = TcpStream::connect((host, port)).await?;
+        let peer = tcp.peer_addr()?;
+        let tls = if is_tls {
+            let server_name = ServerName::try_from(host.trim_matches(|c| c == '[' || c == ']'))
+                .map_err(|err| std::io::Error::new(ErrorKind::InvalidInput, err))?;
+            Some((TlsConnector::from(tls_config.clone()), server_name))
+        } else {
+            None
+        };
+        let stream = match &tls {
+            Some((connector, server_name)) if port == IMPLICIT_FTPS_PORT => {
+                FtpStream::Tls(Box::new(connector.connect(server_name.clone(), tcp).await?))
+            }
+            _ => FtpStream::Tcp(tcp),
+        };
+        let mut connection = Self {
+            url: url.clone(),
+            control: BufReader::new(stream),
+            peer,
+            tls,
+        };
+        connection.expect(&[220]).await?;
+        if let Some((connector, server_name)) = connection.tls.clone() {
+            if let FtpStream::Tcp(_) = connection.control.get_ref() {
+                connection.command("AUTH TLS", &[234]).await?;
+                let tcp = match connection.control.into_inner() {
+                    FtpStream::Tcp(tcp) => tcp,
+                    FtpStream::Tls(_) => unreachable!(),
+                };
+                connection.control = BufReader::new(FtpStream::Tls(Box::new(
+                    connector.connect(server_name, tcp).await?,
+                )));
+            }
+        }
+        connection.login().await?;
+        if connection.tls.is_some() {
+            connection.command("PBSZ 0", &[200]).await?;
+            connection.command("PROT P", &[200]).await?;
+        }
+        connection.command("TYPE I", &[200]).await?;
+        Ok(connection)
+    }
+
+    /// login by url user and password,default is anonymous
+    #[inline]
+    async fn login(&mut self) -> Result<()> {
+        let user = match self.url.username() {
+            "" => "anonymous".to_string(),
+            user => percent_decode_str(user).decode_utf8_lossy().to_string(),
+        };
+        let password = self
+            .url
+            .password()
+            .map(|password| percent_decode_str(password).decode_utf8_lossy().to_string())
+            .unwrap_or_else(|| "anonymous@".to_string());
+        let reply = self.command(&format!("USER {}", user), &[230, 331]).await?;
+        if reply.code == 331 {
+            self.command(&format!("PASS {}", password), &[230, 202])
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// file path of url,relative to login dir
+    #[inline]
+    fn path(&self) -> String {
+        let path = self.url.path();
+        percent_decode_str(path.strip_prefix('/').unwrap_or(path))
+            .decode_utf8_lossy()
+            .to_string()
+    }
+
+    /// read one reply,multi line reply return last line
+    #[inline]
+    async fn read_reply(&mut self) -> Result<Reply> {
+        let mut line = String::new();
+        let mut code = None;
+        loop {
+            line.clear();
+            if self.control.read_line(&mut line).await? == 0 {
+                return Err(std::io::Error::from(ErrorKind::UnexpectedEof).into());
+            }
+            let parse_code = line.get(..3).and_then(|code| code.parse::<u16>().ok());
+            let is_last = line.as_bytes().get(3) != Some(&b'-');
+            match code {
+                None => {
+                    code = Some(parse_code.ok_or_else(|| {
+                        std::io::Error::new(ErrorKind::InvalidData, format!("ftp reply:{line}"))
+                    })?);
+                    if is_last {
+                        break;
+                    }
+                }
+                Some(code) if parse_code == Some(code) && is_last => break,
+                _ => {}
+            }
+        }
+        Ok(Reply {
+            code: code.unwrap_or_default(),
+            message: line.trim_end().to_string(),
+        })
+    }
+
+    /// read reply,error if code not in expect
+    #[inline]
+    async fn expect(&mut self, expect: &[u16]) -> Result<Reply> {
+        let reply = self.read_reply().await?;
+        if expect.contains(&reply.code) {
+            Ok(reply)
+        } else {
+            Err(DownloadError::FtpStatus {
+                url: self.url.clone(),
+                code: reply.code,
+                message: reply.message,
+            })
+        }
+    }
+
+    /// send command and read reply
+    #[inline]
+    async fn command(&mut self, command: &str, expect: &[u16]) -> Result<Reply> {
+        log::trace!(
+            "ftp:{} command:{}",
+            self.url,
+            if command.starts_with("PASS ") {
+                "PASS ***"
+            } else {
+                command
+            }
+        );
+        let stream = self.control.get_mut();
+        stream.write_all(command.as_bytes()).await?;
+        stream.write_all(b"\r\n").await?;
+        stream.flush().await?;
+        self.expect(expect).await
+    }
+
+    /// get file size
+    #[inline]
+    async fn size(&mut self) -> Result<u64> {
+        let reply = self
+            .command(&format!("SIZE {}", self.path()), &[213])
+            .await?;
+        reply
+            .message
+            .get(4..)
+            .and_then(|size| size.trim().parse::<u64>().ok())
+            .ok_or_else(|| DownloadError::NotGetFileSize(self.url.clone()))
+    }
+
+    /// get file modify time,none if server not support
+    #[inline]
+    async fn modify_time(&mut self) -> Result<Option<String>> {
+        match self.command(&format!("MDTM {}", self.path()), &[213]).await {
+            Ok(reply) => Ok(reply.message.get(4..).map(|time| time.trim().to_string())),
+            Err(DownloadError::FtpStatus { .. }) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// enter passive mode and connect data connection,
+    /// use control connection address,ignore address of pasv reply
+    #[inline]
+    async fn open_data(&mut self) -> Result<TcpStream> {
+        let port = match self.command("EPSV", &[229]).await {
+            // 229 Entering Extended Passive Mode (|||port|)
+            Ok(reply) => reply.message.rsplit('(').next().and_then(|x| {
+                x.trim_end_matches(')')
+                    .trim_matches('|')
+                    .parse::<u16>()
+                    .ok()
+            }),
+            Err(DownloadError::FtpStatus { .. }) => {
+                // 227 Entering Passive Mode (h1,h2,h3,h4,p1,p2)
+                let reply = self.command("PASV", &[227]).await?;
+                parse_pasv_port(&reply.message)
+            }
+            Err(err) => return Err(err),
+        };
+        let port = port.ok_or_else(|| {
+            std::io::Error::new(ErrorKind::InvalidData, "ftp passive reply has no port")
+        })?;
+        Ok(TcpStream::connect(SocketAddr::new(self.peer.ip(), port)).await?)
+    }
+
+    /// start retrieve file from offset,return data connection
+    #[inline]
+    async fn retrieve(&mut self, offset: u64) -> Result<FtpStream> {
+        let data = self.open_data().await?;
+        if offset > 0 {
+            match self.command(&format!("REST {}", offset), &[350]).await {
+                Err(DownloadError::FtpStatus { code, .. }) if code >= 500 => {
+                    return Err(DownloadError::RangeNotSupported(self.url.clone()));
+                }
+                result => {
+                    result?;
+                }
+            }
+        }
+        let command = format!("RETR {}", self.path());
+        match self.tls.clone() {
+            Some((connector, server_name)) => {
+                // some server start data tls after retr,handshake and read reply together
+                let (_, data) = tokio::try_join!(self.command(&command, &[125, 150]), async {
+                    connector
+                        .connect(server_name, data)
+                        .await
+                        .map_err(DownloadError::from)
+                })?;
+                Ok(FtpStream::Tls(Box::new(data)))
+            }
+            None => {
+                self.command(&command, &[125, 150]).await?;
+                Ok(FtpStream::Tcp(data))
+            }
+        }
+    }
+}
+
+/// port of pasv reply,every number is one byte,
+/// like "227 Entering Passive Mode (h1,h2,h3,h4,p1,p2)"
+#[inline]
+fn parse_pasv_port(message: &str) -> Option<u16> {
+    let numbers = message
+        .split(|c: char| !c.is_ascii_digit())
+        .filter(|x| !x.is_empty())
+        .skip(1)
+        .map(|x| x.parse::<u8>().ok())
+        .collect::<Option<Vec<_>>>()?;
+    match numbers[..] {
+        [.., p1, p2] if numbers.len() >= 6 => Some(u16::from(p1) * 256 + u16::from(p2)),
+        _ => None,
+    }
+}
+
+/// read data connection to end and completion reply of retrieve,
+/// server report transfer fail after data phase is error
+#[inline]
+async fn finish_transfer(mut data: FtpStream, connection: &mut FtpConnection) -> Result<()> {
+    let mut buf = [0; 1024];
+    // tls data close without notify is not error,reply is the result
+    while let Ok(len) = data.read(&mut buf).await {
+        if len == 0 {
+            break;
+        }
+    }
+    drop(data);
+    connection.expect(&[226, 250]).await?;
+    Ok(())
+}
+
+/// ftp and ftps transport,
+/// every range use own control and data connection,start by rest offset
+#[derive(Clone)]
+pub struct FtpTransport {
+    tls_config: Arc<ClientConfig>,
+}
+
+impl Default for FtpTransport {
+    #[inline]
+    fn default() -> Self {
+        let mut roots = RootCertStore::empty();
+        roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|anchor| {
+            OwnedTrustAnchor::from_subject_spki_name_constraints(
+                anchor.subject,
+                anchor.spki,
+                anchor.name_constraints,
+            )
+        }));
+        Self::with_tls_config(Arc::new(
+            ClientConfig::builder()
+                .with_safe_defaults()
+                .with_root_certificates(roots)
+                .with_no_client_auth(),
+        ))
+    }
+}
+
+impl FtpTransport {
+    /// create transport,ftps verify server by webpki roots
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// create transport use custom tls config,like private ca
+    #[inline]
+    pub fn with_tls_config(tls_config: Arc<ClientConfig>) -> Self {
+        Self { tls_config }
+    }
+}
+
+#[async_trait::async_trait]
+impl ITransport for FtpTransport {
+    #[inline]
+    async fn probe(&self, url: &Url) -> Result<RemoteInfo> {
+        let mut connection = FtpConnection::connect(url, &self.tls_config).await?;
+        let size = connection.size().await?;
+        let validator = connection.modify_time().await?;
+        Ok(RemoteInfo {
+            size,
+            file_name: None,
+            validator,
+            sha256: None,
+            pieces: None,
+            cache_policy: Default::default(),
+            body: None,
+        })
+    }
+
+    #[inline]
+    async fn open_range(&self, status: &DownloadInner, start: u64, end: u64) -> Result<ByteStream> {
+        let mut connection = FtpConnection::connect(status.get_url(), &self.tls_config).await?;
+        if status.is_check_change() {
+            let size = connection.size().await?;
+            let validator = status.get_validator();
+            if size != status.get_remote_size()
+                || (validator.is_some() && connection.modify_time().await? != validator)
+            {
+                log::error!("ftp:{} remote file changed,size:{}", status.url, size);
+                return Err(DownloadError::RemoteChanged(status.url.clone()));
+            }
+        }
+        let data = connection.retrieve(start).await?;
+        log::trace!("open ftp:{} start:{} end:{}", status.url, start, end);
+        let remaining = end - start + 1;
+        let is_last = end + 1 >= status.get_remote_size();
+        // keep control connection alive until data read finish,
+        // stop read at end of range,drop connection abort transfer,
+        // read to end of file check completion reply
+        let stream = futures_util::stream::try_unfold(
+            (data, connection, remaining),
+            move |(mut data, mut connection, remaining)| async move {
+                if remaining == 0 {
+                    if is_last {
+                        finish_transfer(data, &mut connection).await?;
+                    }
+                    return Ok(None);
+                }
+                let mut buf = vec![0; READ_BUFFER_SIZE.min(remaining as usize)];
+                let len = data.read(&mut buf).await?;
+                if len == 0 {
+                    finish_transfer(data, &mut connection).await?;
+                    return Ok(None);
+                }
+                buf.truncate(len);
+                Ok(Some((
+                    Bytes::from(buf),
+                    (data, connection, remaining - len as u64),
+                )))
+            },
+        );
+        Ok(Box::pin(stream))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_pasv_port, FtpTransport};
+    use crate::error::{DownloadError, Result};
+    use crate::transport::ITransport;
+    use crate::DownloadInner;
+    use futures_util::TryStreamExt;
+    use reqwest::Url;
+    use std::sync::Arc;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    const DATA: &[u8] = b"0123456789abcdefghijklmnopqrstuvwxyz";
+
+    /// ftp server of test,reply of rest and transfer completion by arg
+    async fn server(rest_reply: &'static str, complete_reply: &'static str) -> Url {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = Url::parse(&format!(
+            "ftp://127.0.0.1:{}/data.bin",
+            listener.local_addr().unwrap().port()
+        ))
+        .unwrap();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let mut control = BufReader::new(stream);
+                    let mut data_listener = None;
+                    let mut offset = 0;
+                    let mut line = String::new();
+                    let mut reply = "220-welcome\r\n220 ready".to_string();
+                    loop {
+                        reply.push_str("\r\n");
+                        if control.get_mut().write_all(reply.as_bytes()).await.is_err() {
+                            return;
+                        }
+                        line.clear();
+                        if control.read_line(&mut line).await.unwrap_or_default() == 0 {
+                            return;
+                        }
+                        let line = line.trim_end();
+                        let (command, arg) = line.split_once(' ').unwrap_or((line, ""));
+                        reply = match command {
+                            "USER" => "230 logged in".to_string(),
+                            "TYPE" => "200 binary".to_string(),
+                            "SIZE" => format!("213 {}", DATA.len()),
+                            "EPSV" => {
+                                let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+                                let port = listener.local_addr().unwrap().port();
+                                data_listener = Some(listener);
+                                format!("229 Entering Extended Passive Mode (|||{}|)", port)
+                            }
+                            "REST" => {
+                                offset = arg.parse::<usize>().unwrap();
+                                rest_reply.to_string()
+                            }
+                            "RETR" => {
+                                let listener = data_listener.take().unwrap();
+                                control
+                                    .get_mut()
+                                    .write_all(b"150 opening data connection\r\n")
+                                    .await
+                                    .unwrap();
+                                let (mut data, _) = listener.accept().await.unwrap();
+                                let _ = data.write_all(&DATA[offset..]).await;
+                                drop(data);
+                                complete_reply.to_string()
+                            }
+                            _ => "502 not implemented".to_string(),
+                        };
+                    }
+                });
+            }
+        });
+        url
+    }
+
+    /// download range by ftp transport
+    async fn read_range(url: Url, start: u64, end: u64) -> Result<Vec<u8>> {
+        let transport = Arc::new(FtpTransport::new());
+        let size = DATA.len() as u64;
+        let status = DownloadInner::new(url, transport.clone(), size, size);
+        transport
+            .open_range(&status, start, end)
+            .await?
+            .try_fold(vec![], |mut data, chunk| async move {
+                data.extend_from_slice(&chunk);
+                Ok(data)
+            })
+            .await
+    }
+
+    #[test]
+    fn parse_pasv() {
+        assert_eq!(
+            parse_pasv_port("227 Entering Passive Mode (127,0,0,1,195,80)"),
+            Some(195 * 256 + 80)
+        );
+        assert_eq!(
+            parse_pasv_port("227 Entering Passive Mode (127,0,0,1,300,1)"),
+            None
+        );
+        assert_eq!(
+            parse_pasv_port("227 Entering Passive Mode (127,0,0,1,99999,1)"),
+            None
+        );
+        assert_eq!(parse_pasv_port("227 Entering Passive Mode (1,2)"), None);
+    }
+
+    #[tokio::test]
+    async fn read_range_by_rest() {
+        let url = server("350 restarting", "226 transfer complete").await;
+        let size = DATA.len() as u64;
+        assert_eq!(read_range(url.clone(), 0, size - 1).await.unwrap(), DATA);
+        assert_eq!(
+            read_range(url.clone(), 10, size - 1).await.unwrap(),
+            &DATA[10..]
+        );
+        // range not at end abort transfer,not wait completion reply
+        assert_eq!(read_range(url, 5, 9).await.unwrap(), &DATA[5..10]);
+    }
+
+    #[tokio::test]
+    async fn rest_not_supported() {
+        let url = server("502 command not implemented", "226 transfer complete").await;
+        assert!(matches!(
+            read_range(url.clone(), 10, 20).await,
+            Err(DownloadError::RangeNotSupported(_))
+        ));
+        // range from start not send rest
+        assert_eq!(read_range(url, 0, 9).await.unwrap(), &DATA[..10]);
+        let url = server("421 service not available", "226 transfer complete").await;
+        assert!(matches!(
+            read_range(url, 10, 20).await,
+            Err(DownloadError::FtpStatus { code: 421, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn transfer_fail_reply_is_error() {
+        let url = server("350 restarting", "451 local error in processing").await;
+        let size = DATA.len() as u64;
+        assert!(matches!(
+            read_range(url.clone(), 10, size - 1).await,
+            Err(DownloadError::FtpStatus { code: 451, .. })
+        ));
+        // range not at end not check completion reply
+        assert_eq!(read_range(url, 0, 9).await.unwrap(), &DATA[..10]);
+    }
+}
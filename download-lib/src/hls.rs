@@ -0,0 +1,734 @@
+use super::byte_range::ByteRange;
+use super::error::{DownloadError, Result};
+use super::file_save::{FileSave, IFileSave};
+use super::options::DownloadOptions;
+use super::range_file::fetch_range;
+use super::transport::{transport_for_url, IntoDownloadUrl};
+use super::{download_range_to_bytes, download_to_bytes, DownloadInner, DEFAULT_BYTES_LIMIT};
+use bytes::Bytes;
+use cbc::cipher::block_padding::Pkcs7;
+use cbc::cipher::{BlockDecryptMut, KeyIvInit};
+use futures_util::{FutureExt, StreamExt, TryFutureExt, TryStreamExt};
+use reqwest::Url;
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::OnceCell;
+
+/// variant stream of master playlist
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HlsVariant {
+    pub url: Url,
+    /// bits per second
+    pub bandwidth: u64,
+    /// width and height
+    pub resolution: Option<(u32, u32)>,
+    pub codecs: Option<String>,
+}
+
+/// aes-128 key of segment
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HlsKey {
+    pub url: Url,
+    /// none is use media sequence number
+    pub iv: Option<[u8; 16]>,
+}
+
+/// media segment of media playlist
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HlsSegment {
+    pub url: Url,
+    /// media sequence number
+    pub sequence: u64,
+    /// sub range of url
+    pub byte_range: Option<ByteRange>,
+    /// none is not encrypted
+    pub key: Option<HlsKey>,
+    /// init section url and range,fmp4 stream write it before segments
+    pub map: Option<(Url, Option<ByteRange>)>,
+}
+
+/// hls playlist
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HlsPlaylist {
+    /// variant streams
+    Master(Vec<HlsVariant>),
+    /// media segments in order,is_end false is live playlist
+    Media {
+        segments: Vec<HlsSegment>,
+        is_end: bool,
+    },
+}
+
+/// choose variant of master playlist
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HlsQuality {
+    /// max bandwidth
+    #[default]
+    Highest,
+    /// min bandwidth
+    Lowest,
+    /// max bandwidth not more than it,lowest if all more than it
+    MaxBandwidth(u64),
+}
+
+/// parse "highest","lowest" or max bandwidth number
+impl FromStr for HlsQuality {
+    type Err = DownloadError;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "highest" => Ok(HlsQuality::Highest),
+            "lowest" => Ok(HlsQuality::Lowest),
+            value => value
+                .parse()
+                .map(HlsQuality::MaxBandwidth)
+                .map_err(|_| DownloadError::InvalidArgument(format!("hls quality:{}", value))),
+        }
+    }
+}
+
+impl HlsQuality {
+    /// choose variant
+    #[inline]
+    pub fn select<'a>(&self, variants: &'a [HlsVariant]) -> Option<&'a HlsVariant> {
+        let lowest = variants.iter().min_by_key(|variant| variant.bandwidth);
+        match *self {
+            HlsQuality::Highest => variants.iter().max_by_key(|variant| variant.bandwidth),
+            HlsQuality::Lowest => lowest,
+            HlsQuality::MaxBandwidth(max) => variants
+                .iter()
+                .filter(|variant| variant.bandwidth <= max)
+                .max_by_key(|variant| variant.bandwidth)
+                .or(lowest),
+        }
+    }
+}
+
+/// hls download progress
+#[derive(Debug, Default)]
+pub struct HlsStatus {
+    segment_count: AtomicU64,
+    downloaded_count: AtomicU64,
+    written_size: AtomicU64,
+    is_finish: AtomicBool,
+}
+
+impl HlsStatus {
+    /// media segment count of playlist
+    #[inline]
+    pub fn get_segment_count(&self) -> u64 {
+        self.segment_count.load(Ordering::Acquire)
+    }
+
+    /// segment count already write to file
+    #[inline]
+    pub fn get_downloaded_count(&self) -> u64 {
+        self.downloaded_count.load(Ordering::Acquire)
+    }
+
+    /// written size of file
+    #[inline]
+    pub fn get_written_size(&self) -> u64 {
+        self.written_size.load(Ordering::Acquire)
+    }
+
+    #[inline]
+    pub fn get_percent_complete(&self) -> f64 {
+        let current =
+            self.get_downloaded_count() as f64 / self.get_segment_count().max(1) as f64 * 100.0;
+        (current * 100.0).round() / 100.0
+    }
+
+    #[inline]
+    pub fn is_finish(&self) -> bool {
+        self.is_finish.load(Ordering::Acquire)
+    }
+}
+
+/// parse attribute list,like 'BANDWIDTH=1280000,CODECS="avc1.4d401f,mp4a.40.2"'
+#[inline]
+fn parse_attributes(value: &str) -> HashMap<String, String> {
+    let mut attributes = HashMap::new();
+    let mut rest = value.trim();
+    while let Some((key, value)) = rest.split_once('=') {
+        let key = key.trim().to_ascii_uppercase();
+        let (value, next) = match value.strip_prefix('"') {
+            Some(quoted) => {
+                let (value, next) = quoted.split_once('"').unwrap_or((quoted, ""));
+                (value, next.split_once(',').map(|x| x.1).unwrap_or(""))
+            }
+            None => value.split_once(',').unwrap_or((value, "")),
+        };
+        attributes.insert(key, value.trim().to_string());
+        rest = next.trim_start();
+    }
+    attributes
+}
+
+/// parse "<n>[@<o>]",return length and offset
+#[inline]
+fn parse_byte_range(value: &str) -> Option<(u64, Option<u64>)> {
+    let (len, offset) = match value.split_once('@') {
+        Some((len, offset)) => (len, Some(offset.trim().parse::<u64>().ok()?)),
+        None => (value, None),
+    };
+    Some((len.trim().parse::<u64>().ok()?, offset))
+}
+
+/// range of length from start,none is end overflow
+#[inline]
+fn to_byte_range(len: u64, start: u64) -> Option<ByteRange> {
+    Some(ByteRange::Range(start, start.checked_add(len)?))
+}
+
+/// parse hex iv,like "0x0123..."
+#[inline]
+fn parse_iv(value: &str) -> Option<[u8; 16]> {
+    let value = value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))?;
+    let mut iv = [0; 16];
+    hex::decode_to_slice(format!("{:0>32}", value), &mut iv).ok()?;
+    Some(iv)
+}
+
+impl HlsPlaylist {
+    /// parse m3u8 playlist,relative uri resolve by base url
+    #[inline]
+    pub fn parse(base: &Url, text: &str) -> Result<Self> {
+        let invalid = |message: String| {
+            DownloadError::from(std::io::Error::new(ErrorKind::InvalidData, message))
+        };
+        let join = |uri: &str| {
+            base.join(uri)
+                .map_err(|_| DownloadError::InvalidUrl(format!("{} of {}", uri, base)))
+        };
+        let mut lines = text.lines().map(str::trim).filter(|line| !line.is_empty());
+        if lines.next() != Some("#EXTM3U") {
+            return Err(invalid(format!("{} is not m3u8 playlist", base)));
+        }
+        let mut variants = vec![];
+        let mut segments = vec![];
+        let mut is_end = false;
+        let mut sequence = 0;
+        let mut key: Option<HlsKey> = None;
+        let mut map = None;
+        let mut byte_range = None;
+        let mut previous_end = None;
+        let mut variant: Option<HashMap<String, String>> = None;
+        for line in lines {
+            let Some(tag) = line.strip_prefix('#') else {
+                let url = join(line)?;
+                if let Some(attributes) = variant.take() {
+                    variants.push(HlsVariant {
+                        url,
+                        bandwidth: attributes
+                            .get("BANDWIDTH")
+                            .and_then(|x| x.parse().ok())
+                            .unwrap_or_default(),
+                        resolution: attributes.get("RESOLUTION").and_then(|x| {
+                            let (width, height) = x.split_once(['x', 'X'])?;
+                            Some((width.parse().ok()?, height.parse().ok()?))
+                        }),
+                        codecs: attributes.get("CODECS").cloned(),
+                    });
+                } else {
+                    let byte_range = match byte_range.take() {
+                        Some((len, offset)) => {
+                            // offset default is end of previous range of same uri
+                            let start = match offset {
+                                Some(offset) => offset,
+                                None => previous_end
+                                    .as_ref()
+                                    .filter(|(previous, _)| *previous == url)
+                                    .map(|(_, end)| *end)
+                                    .ok_or_else(|| {
+                                        invalid(format!("byte range of {} has no offset", url))
+                                    })?,
+                            };
+                            Some(to_byte_range(len, start).ok_or_else(|| {
+                                invalid(format!("byte range of {} is overflow", url))
+                            })?)
+                        }
+                        None => None,
+                    };
+                    previous_end = match byte_range {
+                        Some(ByteRange::Range(_, end)) => Some((url.clone(), end)),
+                        _ => None,
+                    };
+                    segments.push(HlsSegment {
+                        url,
+                        sequence,
+                        byte_range,
+                        key: key.clone(),
+                        map: map.clone(),
+                    });
+                    sequence += 1;
+                }
+                continue;
+            };
+            let (name, value) = tag.split_once(':').unwrap_or((tag, ""));
+            match name {
+                "EXT-X-STREAM-INF" => variant = Some(parse_attributes(value)),
+                "EXT-X-MEDIA-SEQUENCE" => sequence = value.trim().parse().unwrap_or_default(),
+                "EXT-X-ENDLIST" => is_end = true,
+                "EXT-X-BYTERANGE" => {
+                    byte_range = Some(
+                        parse_byte_range(value)
+                            .ok_or_else(|| invalid(format!("invalid byte range:{}", value)))?,
+                    )
+                }
+                "EXT-X-KEY" => {
+                    let attributes = parse_attributes(value);
+                    key = match attributes.get("METHOD").map(String::as_str) {
+                        Some("NONE") => None,
+                        Some("AES-128") => Some(HlsKey {
+                            url: join(
+                                attributes
+                                    .get("URI")
+                                    .ok_or_else(|| invalid(format!("{} key has no uri", base)))?,
+                            )?,
+                            iv: match attributes.get("IV") {
+                                Some(iv) => Some(
+                                    parse_iv(iv)
+                                        .ok_or_else(|| invalid(format!("invalid key iv:{}", iv)))?,
+                                ),
+                                None => None,
+                            },
+                        }),
+                        method => {
+                            return Err(invalid(format!(
+                                "{} not support key method:{:?}",
+                                base, method
+                            )))
+                        }
+                    };
+                }
+                "EXT-X-MAP" => {
+                    let attributes = parse_attributes(value);
+                    let url = join(
+                        attributes
+                            .get("URI")
+                            .ok_or_else(|| invalid(format!("{} map has no uri", base)))?,
+                    )?;
+                    let range = match attributes.get("BYTERANGE") {
+                        Some(range) => Some(
+                            parse_byte_range(range)
+                                .and_then(|(len, offset)| {
+                                    to_byte_range(len, offset.unwrap_or_default())
+                                })
+                                .ok_or_else(|| invalid(format!("invalid byte range:{}", range)))?,
+                        ),
+                        None => None,
+                    };
+                    map = Some((url, range));
+                }
+                _ => {}
+            }
+        }
+        if !variants.is_empty() {
+            Ok(HlsPlaylist::Master(variants))
+        } else {
+            Ok(HlsPlaylist::Media { segments, is_end })
+        }
+    }
+
+    /// download and parse playlist of url
+    #[inline]
+    pub async fn load<U: IntoDownloadUrl>(url: U) -> Result<Self> {
+        let url = url.into_download_url()?;
+        let data = download_to_bytes(url.clone(), &DownloadOptions::default()).await?;
+        Self::parse(&url, &String::from_utf8_lossy(&data))
+    }
+}
+
+/// decrypt aes-128-cbc segment
+#[inline]
+fn decrypt_segment(url: &Url, key: &[u8], iv: &[u8; 16], data: Bytes) -> Result<Bytes> {
+    let invalid = |message: &str| {
+        DownloadError::from(std::io::Error::new(
+            ErrorKind::InvalidData,
+            format!("hls segment:{} {}", url, message),
+        ))
+    };
+    let decryptor = cbc::Decryptor::<aes::Aes128>::new_from_slices(key, iv)
+        .map_err(|_| invalid("key is not 16 bytes"))?;
+    let mut buf = data.to_vec();
+    let len = decryptor
+        .decrypt_padded_mut::<Pkcs7>(&mut buf)
+        .map_err(|_| invalid("decrypt fail"))?
+        .len();
+    buf.truncate(len);
+    Ok(Bytes::from(buf))
+}
+
+/// fetch segment data,url of byte range segments probe once,
+/// every range is request directly
+struct SegmentFetcher {
+    options: DownloadOptions,
+    probes: Mutex<HashMap<Url, Arc<OnceCell<Arc<DownloadInner>>>>>,
+}
+
+impl SegmentFetcher {
+    #[inline]
+    fn new(options: DownloadOptions) -> Self {
+        Self {
+            options,
+            probes: Default::default(),
+        }
+    }
+
+    /// probe url once,status is share by all ranges of url
+    #[inline]
+    async fn status(&self, url: &Url) -> Result<Arc<DownloadInner>> {
+        let cell = self
+            .probes
+            .lock()
+            .unwrap()
+            .entry(url.clone())
+            .or_default()
+            .clone();
+        cell.get_or_try_init(|| async {
+            let transport = transport_for_url(url);
+            let info = transport.probe_head(url).await?;
+            log::trace!("hls probe:{} size:{}", url, info.size);
+            let status = DownloadInner::new(url.clone(), transport, info.size, info.size);
+            status.set_validator(info.validator);
+            status.is_start.store(true, Ordering::Release);
+            Ok(Arc::new(status))
+        })
+        .await
+        .cloned()
+    }
+
+    /// download whole url or range of url
+    #[inline]
+    async fn fetch(&self, url: &Url, range: Option<ByteRange>) -> Result<Bytes> {
+        let Some(range) = range else {
+            return download_range_to_bytes(url.clone(), None, &self.options).await;
+        };
+        let status = self.status(url).await?;
+        let range = range.resolve(status.get_size())?;
+        let size = range.end - range.start;
+        let limit = self.options.max_size.unwrap_or(DEFAULT_BYTES_LIMIT);
+        if size > limit {
+            return Err(DownloadError::SizeLimitExceeded { size, limit });
+        }
+        if size == 0 {
+            return Ok(Bytes::new());
+        }
+        fetch_range(status, range.start, range.end - 1).await
+    }
+}
+
+/// iv of key,default is 128 bits big endian media sequence number
+#[inline]
+fn segment_iv(key: &HlsKey, sequence: u64) -> [u8; 16] {
+    key.iv.unwrap_or_else(|| (sequence as u128).to_be_bytes())
+}
+
+/// download segment data,decrypt if need
+#[inline]
+async fn download_segment(
+    segment: HlsSegment,
+    keys: Arc<HashMap<Url, Bytes>>,
+    fetcher: Arc<SegmentFetcher>,
+) -> Result<Bytes> {
+    let data = fetcher.fetch(&segment.url, segment.byte_range).await?;
+    match &segment.key {
+        Some(key) => decrypt_segment(
+            &segment.url,
+            &keys[&key.url],
+            &segment_iv(key, segment.sequence),
+            data,
+        ),
+        None => Ok(data),
+    }
+}
+
+/// download hls playlist,master playlist choose variant by quality,
+/// segments download in parallel and write to save path in order,
+/// live playlist only download current segments,return saved file
+#[inline]
+pub async fn download_hls<U: IntoDownloadUrl>(
+    url: U,
+    save_path: PathBuf,
+    quality: HlsQuality,
+    options: &DownloadOptions,
+    status: Arc<HlsStatus>,
+) -> Result<PathBuf> {
+    let result = hls_to_file(url, save_path, quality, options, &status).await;
+    status.is_finish.store(true, Ordering::Release);
+    result
+}
+
+#[inline]
+async fn hls_to_file<U: IntoDownloadUrl>(
+    url: U,
+    mut save_path: PathBuf,
+    quality: HlsQuality,
+    options: &DownloadOptions,
+    status: &HlsStatus,
+) -> Result<PathBuf> {
+    let mut url = url.into_download_url()?;
+    let mut playlist = HlsPlaylist::load(url.clone()).await?;
+    if let HlsPlaylist::Master(variants) = &playlist {
+        let variant = quality.select(variants).ok_or_else(|| {
+            DownloadError::InvalidArgument(format!("hls:{} no variant of {:?}", url, quality))
+        })?;
+        log::trace!(
+            "hls:{} choose variant:{} bandwidth:{}",
+            url,
+            variant.url,
+            variant.bandwidth
+        );
+        url = variant.url.clone();
+        playlist = HlsPlaylist::load(url.clone()).await?;
+    }
+    let HlsPlaylist::Media { segments, is_end } = playlist else {
+        return Err(std::io::Error::new(
+            ErrorKind::InvalidData,
+            format!("hls:{} variant is master playlist", url),
+        )
+        .into());
+    };
+    status
+        .segment_count
+        .store(segments.len() as u64, Ordering::Release);
+    if !is_end {
+        log::warn!(
+            "hls:{} is live playlist,only download current segments",
+            url
+        );
+    }
+    if save_path.is_dir() {
+        let name = url
+            .path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .and_then(|name| name.strip_suffix(".m3u8"))
+            .filter(|name| !name.is_empty())
+            .ok_or_else(|| DownloadError::NotFileName(url.clone()))?;
+        let extension = if segments.iter().any(|segment| segment.map.is_some()) {
+            "mp4"
+        } else {
+            "ts"
+        };
+        save_path.push(format!("{}.{}", name, extension));
+    }
+
+    let mut keys = HashMap::new();
+    for segment in &segments {
+        if let Some(key) = &segment.key {
+            if !keys.contains_key(&key.url) {
+                let data = download_to_bytes(key.url.clone(), options).await?;
+                keys.insert(key.url.clone(), data);
+            }
+        }
+    }
+    let keys = Arc::new(keys);
+
+    let fetcher = Arc::new(SegmentFetcher::new(DownloadOptions {
+        task_count: 1,
+        ..options.clone()
+    }));
+    let save_file = FileSave::create(save_path.clone())?;
+    save_file.init(0).await?;
+    let result = async {
+        let mut offset = 0;
+        let mut current_map = None;
+        let downloads = segments
+            .into_iter()
+            .map(|segment| {
+                let map = segment.map.clone();
+                download_segment(segment, keys.clone(), fetcher.clone())
+                    .map_ok(move |data| (map, data))
+                    .boxed()
+            })
+            .collect::<Vec<_>>();
+        let mut stream =
+            futures_util::stream::iter(downloads).buffered(options.task_count.max(1) as usize);
+        while let Some((map, data)) = stream.try_next().await? {
+            if map.is_some() && map != current_map {
+                if let Some((url, range)) = &map {
+                    let data = fetcher.fetch(url, *range).await?;
+                    let len = data.len() as u64;
+                    save_file.write_all_by_offset(data, offset).await?;
+                    offset += len;
+                }
+                current_map = map;
+            }
+            let len = data.len() as u64;
+            save_file.write_all_by_offset(data, offset).await?;
+            offset += len;
+            status.written_size.store(offset, Ordering::Release);
+            status.downloaded_count.fetch_add(1, Ordering::AcqRel);
+        }
+        Ok(())
+    }
+    .await;
+    match result {
+        Ok(()) => {
+            save_file.finish().await?;
+            Ok(save_path)
+        }
+        Err(err) => {
+            if let Err(abort_err) = save_file.abort().await {
+                log::error!("save file abort error:{:?}", abort_err);
+            }
+            Err(err)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        decrypt_segment, parse_attributes, segment_iv, ByteRange, HlsKey, HlsPlaylist, HlsQuality,
+    };
+    use bytes::Bytes;
+    use reqwest::Url;
+
+    #[test]
+    fn parse_attribute_list() {
+        let attributes =
+            parse_attributes(r#"BANDWIDTH=1280000,CODECS="avc1.4d401f,mp4a.40.2",res=1x2"#);
+        assert_eq!(attributes["BANDWIDTH"], "1280000");
+        assert_eq!(attributes["CODECS"], "avc1.4d401f,mp4a.40.2");
+        assert_eq!(attributes["RES"], "1x2");
+    }
+
+    #[test]
+    fn parse_master() {
+        let base = Url::parse("http://example.com/live/master.m3u8").unwrap();
+        let text = "#EXTM3U\n\
+            #EXT-X-STREAM-INF:BANDWIDTH=800000,RESOLUTION=640x360\n\
+            low.m3u8\n\
+            #EXT-X-STREAM-INF:BANDWIDTH=2500000,CODECS=\"avc1\"\n\
+            /high.m3u8\n";
+        let HlsPlaylist::Master(variants) = HlsPlaylist::parse(&base, text).unwrap() else {
+            panic!("not master playlist");
+        };
+        assert_eq!(variants[0].url.as_str(), "http://example.com/live/low.m3u8");
+        assert_eq!(variants[0].resolution, Some((640, 360)));
+        assert_eq!(variants[1].url.as_str(), "http://example.com/high.m3u8");
+        assert_eq!(variants[1].codecs.as_deref(), Some("avc1"));
+        let select = |quality: &str| {
+            let quality = quality.parse::<HlsQuality>().unwrap();
+            quality.select(&variants).unwrap().bandwidth
+        };
+        assert_eq!(select("highest"), 2500000);
+        assert_eq!(select("lowest"), 800000);
+        assert_eq!(select("1000000"), 800000);
+        assert_eq!(select("1"), 800000);
+        assert!("best".parse::<HlsQuality>().is_err());
+    }
+
+    #[test]
+    fn parse_media() {
+        let base = Url::parse("http://example.com/media.m3u8").unwrap();
+        let text = "#EXTM3U\n\
+            #EXT-X-MEDIA-SEQUENCE:7\n\
+            #EXT-X-MAP:URI=\"init.mp4\",BYTERANGE=\"100@0\"\n\
+            #EXT-X-KEY:METHOD=AES-128,URI=\"k.bin\",IV=0x1\n\
+            #EXT-X-BYTERANGE:10@100\n\
+            all.m4s\n\
+            #EXT-X-KEY:METHOD=NONE\n\
+            #EXT-X-BYTERANGE:20\n\
+            all.m4s\n\
+            #EXT-X-ENDLIST\n";
+        let HlsPlaylist::Media { segments, is_end } = HlsPlaylist::parse(&base, text).unwrap()
+        else {
+            panic!("not media playlist");
+        };
+        assert!(is_end);
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[0].sequence, 7);
+        assert_eq!(segments[0].byte_range, Some(ByteRange::Range(100, 110)));
+        assert_eq!(segments[1].byte_range, Some(ByteRange::Range(110, 130)));
+        let key = segments[0].key.as_ref().unwrap();
+        assert_eq!(key.url.as_str(), "http://example.com/k.bin");
+        assert_eq!(key.iv.unwrap()[15], 1);
+        assert!(segments[1].key.is_none());
+        let (map, range) = segments[1].map.as_ref().unwrap();
+        assert_eq!(map.as_str(), "http://example.com/init.mp4");
+        assert_eq!(*range, Some(ByteRange::Range(0, 100)));
+    }
+
+    #[test]
+    fn parse_invalid_playlist() {
+        let base = Url::parse("http://example.com/a.m3u8").unwrap();
+        assert!(HlsPlaylist::parse(&base, "a.ts\n").is_err());
+        assert!(HlsPlaylist::parse(&base, "#EXTM3U\n#EXT-X-BYTERANGE:x\na.ts\n").is_err());
+        assert!(HlsPlaylist::parse(&base, "#EXTM3U\n#EXT-X-KEY:METHOD=SAMPLE-AES\n").is_err());
+    }
+
+    #[test]
+    fn parse_overflow_byte_range() {
+        let base = Url::parse("http://example.com/a.m3u8").unwrap();
+        let text = "#EXTM3U\n#EXT-X-BYTERANGE:18446744073709551615@1\na.ts\n";
+        assert!(HlsPlaylist::parse(&base, text).is_err());
+        let text = "#EXTM3U\n#EXT-X-MAP:URI=\"i.mp4\",BYTERANGE=\"18446744073709551615@1\"\n";
+        assert!(HlsPlaylist::parse(&base, text).is_err());
+        let text = "#EXTM3U\n\
+            #EXT-X-BYTERANGE:10@18446744073709551600\n\
+            a.ts\n\
+            #EXT-X-BYTERANGE:10\n\
+            a.ts\n";
+        assert!(HlsPlaylist::parse(&base, text).is_err());
+    }
+
+    #[test]
+    fn byte_range_offset_of_same_uri() {
+        let base = Url::parse("http://example.com/a.m3u8").unwrap();
+        // offset of other uri is not continue
+        let text = "#EXTM3U\n\
+            #EXT-X-BYTERANGE:10@0\n\
+            a.ts\n\
+            #EXT-X-BYTERANGE:10\n\
+            b.ts\n";
+        assert!(HlsPlaylist::parse(&base, text).is_err());
+        let text = "#EXTM3U\n#EXT-X-BYTERANGE:10\na.ts\n";
+        assert!(HlsPlaylist::parse(&base, text).is_err());
+        let text = "#EXTM3U\n\
+            #EXT-X-BYTERANGE:10@5\n\
+            a.ts\n\
+            #EXT-X-BYTERANGE:10@0\n\
+            b.ts\n\
+            #EXT-X-BYTERANGE:4\n\
+            b.ts\n";
+        let HlsPlaylist::Media { segments, .. } = HlsPlaylist::parse(&base, text).unwrap() else {
+            panic!("not media playlist");
+        };
+        assert_eq!(segments[2].byte_range, Some(ByteRange::Range(10, 14)));
+    }
+
+    #[test]
+    fn decrypt_aes_128_by_sequence_iv() {
+        let url = Url::parse("http://example.com/7.ts").unwrap();
+        let key = HlsKey {
+            url: Url::parse("http://example.com/k.bin").unwrap(),
+            iv: None,
+        };
+        let iv = segment_iv(&key, 7);
+        assert_eq!(iv, [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 7]);
+        // key of nist sp 800-38a,ciphertext by openssl aes-128-cbc
+        let secret = hex::decode("2b7e151628aed2a6abf7158809cf4f3c").unwrap();
+        let data = hex::decode("4e27a33b3fce4964f56032065abf48d4a621b835c19f3c901a7ec733795d7712")
+            .unwrap();
+        let plain = decrypt_segment(&url, &secret, &iv, Bytes::from(data.clone())).unwrap();
+        assert_eq!(plain.as_ref(), b"hls segment data");
+        let explicit = HlsKey {
+            iv: Some([1; 16]),
+            ..key
+        };
+        assert_eq!(segment_iv(&explicit, 7), [1; 16]);
+        // wrong iv change first block only
+        let plain = decrypt_segment(&url, &secret, &[1; 16], Bytes::from(data)).unwrap();
+        assert_ne!(plain.as_ref(), b"hls segment data");
+        assert!(decrypt_segment(&url, &secret[..8], &iv, Bytes::new()).is_err());
+    }
+}
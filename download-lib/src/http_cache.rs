@@ -0,0 +1,170 @@
+use reqwest::header::{HeaderMap, AGE, CACHE_CONTROL, DATE, EXPIRES, PRAGMA};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// http caching policy of response,
+/// from "Cache-Control","Expires","Age" and "Date" headers
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CachePolicy {
+    /// response must not be stored
+    pub no_store: bool,
+    /// remaining freshness lifetime at receive time,none is stale,revalidate before use
+    pub fresh_for: Option<Duration>,
+    /// response receive time,none is now
+    pub received: Option<SystemTime>,
+}
+
+impl CachePolicy {
+    /// parse response headers
+    #[inline]
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let now = SystemTime::now();
+        let directives = headers
+            .get_all(CACHE_CONTROL)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(|directive| {
+                let (name, value) = directive.split_once('=').unwrap_or((directive, ""));
+                (
+                    name.trim().to_ascii_lowercase(),
+                    value.trim().trim_matches('"').to_string(),
+                )
+            })
+            .collect::<Vec<_>>();
+        let has = |name: &str| directives.iter().any(|(x, _)| x == name);
+        let no_store = has("no-store");
+        let no_cache = has("no-cache")
+            || (!has("max-age")
+                && headers
+                    .get(PRAGMA)
+                    .and_then(|value| value.to_str().ok())
+                    .is_some_and(|value| value.to_ascii_lowercase().contains("no-cache")));
+        if no_store || no_cache {
+            return Self {
+                no_store,
+                fresh_for: None,
+                received: Some(now),
+            };
+        }
+        let date = headers
+            .get(DATE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| httpdate::parse_http_date(value).ok());
+        let lifetime = match directives.iter().find(|(name, _)| name == "max-age") {
+            Some((_, value)) => value.parse::<u64>().ok().map(Duration::from_secs),
+            // invalid expires like "0" is already expired
+            None => headers.get(EXPIRES).map(|value| {
+                value
+                    .to_str()
+                    .ok()
+                    .and_then(|value| httpdate::parse_http_date(value).ok())
+                    .and_then(|expires| expires.duration_since(date.unwrap_or(now)).ok())
+                    .unwrap_or_default()
+            }),
+        };
+        // current age is max of age header and apparent age
+        let age = headers
+            .get(AGE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse::<u64>().ok())
+            .map(Duration::from_secs)
+            .unwrap_or_default()
+            .max(
+                date.and_then(|date| now.duration_since(date).ok())
+                    .unwrap_or_default(),
+            );
+        Self {
+            no_store,
+            fresh_for: lifetime
+                .and_then(|lifetime| lifetime.checked_sub(age))
+                .filter(|fresh_for| !fresh_for.is_zero()),
+            received: Some(now),
+        }
+    }
+
+    /// fresh until unix time in milliseconds,count from receive time
+    #[inline]
+    pub fn expires(&self) -> Option<u64> {
+        self.fresh_for.map(|fresh_for| {
+            (self.received.unwrap_or_else(SystemTime::now) + fresh_for)
+                .duration_since(UNIX_EPOCH)
+                .map(|x| x.as_millis() as u64)
+                .unwrap_or_default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CachePolicy;
+    use reqwest::header::HeaderMap;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    fn parse(headers: &[(&'static str, String)]) -> CachePolicy {
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            map.append(*name, value.parse().unwrap());
+        }
+        CachePolicy::from_headers(&map)
+    }
+
+    #[test]
+    fn max_age_sub_age() {
+        let policy = parse(&[
+            ("cache-control", "public, max-age=60".to_string()),
+            ("age", "10".to_string()),
+        ]);
+        assert!(!policy.no_store);
+        assert_eq!(policy.fresh_for, Some(Duration::from_secs(50)));
+        let policy = parse(&[
+            ("cache-control", "max-age=60".to_string()),
+            ("age", "100".to_string()),
+        ]);
+        assert_eq!(policy.fresh_for, None);
+    }
+
+    #[test]
+    fn no_store_and_no_cache() {
+        let policy = parse(&[("cache-control", "no-store, max-age=60".to_string())]);
+        assert!(policy.no_store);
+        assert_eq!(policy.fresh_for, None);
+        let policy = parse(&[("cache-control", "no-cache".to_string())]);
+        assert!(!policy.no_store);
+        assert_eq!(policy.fresh_for, None);
+        let policy = parse(&[("pragma", "no-cache".to_string())]);
+        assert_eq!(policy.fresh_for, None);
+        // max-age override pragma
+        let policy = parse(&[
+            ("pragma", "no-cache".to_string()),
+            ("cache-control", "max-age=60".to_string()),
+        ]);
+        assert_eq!(policy.fresh_for, Some(Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn expires_by_date() {
+        let date = SystemTime::now() - Duration::from_secs(5);
+        let policy = parse(&[
+            ("date", httpdate::fmt_http_date(date)),
+            (
+                "expires",
+                httpdate::fmt_http_date(date + Duration::from_secs(30)),
+            ),
+        ]);
+        let fresh_for = policy.fresh_for.unwrap().as_secs();
+        assert!((24..=25).contains(&fresh_for), "fresh for:{}", fresh_for);
+        assert_eq!(parse(&[("expires", "0".to_string())]).fresh_for, None);
+        assert_eq!(CachePolicy::default().fresh_for, None);
+    }
+
+    #[test]
+    fn expires_from_receive_time() {
+        let policy = CachePolicy {
+            no_store: false,
+            fresh_for: Some(Duration::from_secs(60)),
+            received: Some(UNIX_EPOCH + Duration::from_secs(1000)),
+        };
+        assert_eq!(policy.expires(), Some(1_060_000));
+        assert_eq!(CachePolicy::default().expires(), None);
+    }
+}
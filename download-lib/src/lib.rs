@@ -1,276 +1,934 @@
+mod append_sync;
+mod byte_range;
+mod cache;
+mod checksum;
+mod decompress;
+mod delta;
+mod download_stream;
 mod error;
+mod extract;
 mod file_save;
+mod ftp_file;
+mod hls;
+mod http_cache;
+mod local_file;
+mod memory_save;
+mod metalink;
+mod oci_file;
+mod options;
+mod piece_hash;
+mod range_file;
+mod remote_file;
 mod reqwest_file;
+mod s3_file;
+#[cfg(feature = "sftp")]
+mod sftp_file;
+mod signature;
+mod transport;
 
-use aqueue::Actor;
+pub use append_sync::{follow_append, sync_append};
+pub use byte_range::ByteRange;
+use bytes::Bytes;
+pub use cache::{CacheEntry, CacheStats, DownloadCache};
+pub use checksum::{ChecksumManifest, ChecksumSource};
+pub use decompress::{decompress_file, download_decompress, Compression, DecompressStatus};
+pub use delta::{DeltaBlock, DeltaControl, DeltaSave};
+pub use download_stream::{download_stream, DownloadStream};
 pub use error::DownloadError;
 use error::Result;
-use file_save::FileSave;
-use file_save::IFileSave;
-use reqwest::{IntoUrl, Response, StatusCode, Url};
-use reqwest_file::ReqwestFile;
+use extract::extract_archive_blocking;
+pub use extract::{extract_archive, ArchiveFormat, ExtractOptions};
+pub use file_save::FileSave;
+pub use file_save::IFileSave;
+pub use ftp_file::FtpTransport;
+pub use hls::{download_hls, HlsKey, HlsPlaylist, HlsQuality, HlsSegment, HlsStatus, HlsVariant};
+pub use http_cache::CachePolicy;
+pub use local_file::{DataTransport, FileTransport};
+pub use memory_save::MemorySave;
+pub use metalink::{download_metalink, Metalink, MetalinkFile, MetalinkTransport, MetalinkUrl};
+pub use oci_file::OciTransport;
+pub use options::DownloadOptions;
+pub use piece_hash::{HashType, PieceHashes, PieceManifest};
+use range_file::RangeFile;
+pub use remote_file::RemoteFile;
+use reqwest::Url;
+pub use reqwest_file::ReqwestTransport;
+pub use s3_file::{download_s3_prefix, S3Config, S3Object, S3Transport};
+#[cfg(feature = "sftp")]
+pub use sftp_file::SftpTransport;
+pub use signature::{MinisignCheck, MinisignVerifier};
 use std::cmp::{max, min};
+use std::ops::Range;
 use std::path::PathBuf;
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
-use tokio::sync::OnceCell;
+use tokio::sync::{Notify, OnceCell};
 use tokio::task::JoinHandle;
 use tokio::time::sleep;
+use transport::transport_for_url;
+pub use transport::{ByteStream, ITransport, IntoDownloadUrl, RemoteInfo, Revalidation};
+
+/// max restart count when remote file changed
+const MAX_RESTART_COUNT: u32 = 3;
+/// max download again count of mismatch pieces
+const PIECE_RETRY_COUNT: u32 = 3;
+/// default max size of download to memory if options has no max size,
+/// memory save preallocate whole size
+const DEFAULT_BYTES_LIMIT: u64 = 1024 * 1024 * 1024;
+
+/// download url data to memory,use concurrent range request write to preallocated buffer
+#[inline]
+pub async fn download_to_bytes<U: IntoDownloadUrl>(
+    url: U,
+    options: &DownloadOptions,
+) -> Result<Bytes> {
+    download_range_to_bytes(url, None, options).await
+}
+
+/// download byte range of url data to memory,none is whole file,
+/// whole file use cache of options if it is fresh or remote not changed,
+/// max size of options none is limit to 1G
+#[inline]
+pub async fn download_range_to_bytes<U: IntoDownloadUrl>(
+    url: U,
+    range: Option<ByteRange>,
+    options: &DownloadOptions,
+) -> Result<Bytes> {
+    let url = url.into_download_url()?;
+    let cache = options.cache.as_ref().filter(|_| range.is_none());
+    let transport = transport_for_url(&url);
+    let mut probed = None;
+    if let Some(cache) = cache {
+        let (entry, info) = lookup_cache(cache, transport.as_ref(), &url).await?;
+        if let Some(entry) = entry {
+            let data = cache.blocking(move |cache| cache.read(&entry)).await?;
+            return Ok(Bytes::from(data));
+        }
+        probed = info;
+    }
+    let info = match probed {
+        Some(info) => info,
+        None => transport.probe(&url).await?,
+    };
+    let range = match range {
+        Some(range) => range.resolve(info.size)?,
+        None => 0..info.size,
+    };
+    let size = range.end - range.start;
+    let limit = options.max_size.unwrap_or(DEFAULT_BYTES_LIMIT);
+    if size > limit {
+        return Err(DownloadError::SizeLimitExceeded { size, limit });
+    }
+    let key = url.to_string();
+    let validator = info.validator.clone();
+    let policy = info.cache_policy;
+    if let Some(cache) = cache {
+        let key = key.clone();
+        let validator = validator.clone();
+        let sha256 = info.sha256.clone();
+        let data = cache
+            .blocking(move |cache| {
+                match cache.lookup(&key, validator.as_deref(), sha256.as_deref(), size, &policy)? {
+                    Some(entry) => cache.read(&entry).map(Some),
+                    None => Ok(None),
+                }
+            })
+            .await?;
+        if let Some(data) = data {
+            return Ok(Bytes::from(data));
+        }
+    }
+    let download = DownloadFile::new(
+        url,
+        transport,
+        info.size,
+        vec![range],
+        MemorySave::new(),
+        options.task_count,
+        options.block,
+    );
+    download.set_restart_on_change(options.restart_on_change);
+    download.run_now(info).await?;
+    let data = download.save_file.take_bytes().unwrap_or_default();
+    if let Some(cache) = cache.filter(|_| !policy.no_store) {
+        let data = data.clone();
+        let url = key.clone();
+        if let Err(err) = cache
+            .blocking(move |cache| cache.insert_bytes(&url, validator.as_deref(), &policy, &data))
+            .await
+        {
+            log::warn!("add url:{} to cache error:{}", key, err);
+        }
+    }
+    Ok(data)
+}
+
+/// fresh cache entry of url,or stale entry revalidated by conditional request,
+/// remote info is return if remote modified
+#[inline]
+async fn lookup_cache(
+    cache: &DownloadCache,
+    transport: &dyn ITransport,
+    url: &Url,
+) -> Result<(Option<CacheEntry>, Option<RemoteInfo>)> {
+    let key = url.to_string();
+    let (fresh, stale) = cache
+        .blocking(move |cache| match cache.lookup_fresh(&key)? {
+            Some(entry) => Ok((Some(entry), None)),
+            None => Ok((None, cache.lookup_stale(&key)?)),
+        })
+        .await?;
+    if fresh.is_some() {
+        return Ok((fresh, None));
+    }
+    let Some((entry, validator)) =
+        stale.and_then(|entry| entry.validator.clone().map(|validator| (entry, validator)))
+    else {
+        return Ok((None, None));
+    };
+    match transport.probe_if_modified(url, &validator).await? {
+        Revalidation::NotModified(policy) => {
+            let entry = cache
+                .blocking(move |cache| cache.revalidated(entry, &policy))
+                .await?;
+            Ok((Some(entry), None))
+        }
+        Revalidation::Modified(info) => Ok((None, Some(info))),
+    }
+}
 
 /// Down file handler
-pub struct DownloadFile {
+pub struct DownloadFile<S: IFileSave = FileSave> {
     task_count: u64,
-    save_file: Arc<Actor<FileSave>>,
+    ranges: Vec<Range<u64>>,
+    save_file: Arc<S>,
     inner_status: Arc<DownloadInner>,
 }
 
 impl DownloadFile {
-    /// start download now
+    /// start download now,use download cache of env DURL_CACHE_DIR if set
     #[inline]
-    pub async fn start_download<U: IntoUrl>(
+    pub async fn start_download<U: IntoDownloadUrl>(
         url: U,
-        mut save_path: PathBuf,
+        save_path: PathBuf,
+        task_count: u64,
+        block: u64,
+    ) -> Result<Self> {
+        // cache dir of env is used by plain download too
+        if let Some(cache) = DownloadCache::from_env() {
+            let options = DownloadOptions {
+                task_count,
+                block,
+                cache: Some(cache),
+                ..Default::default()
+            };
+            return Self::start_download_with_options(url, save_path, &options).await;
+        }
+        let url = url.into_download_url()?;
+        let transport = transport_for_url(&url);
+        Self::start_download_with_transport(url, save_path, transport, task_count, block).await
+    }
+
+    /// start download now,remote file read by custom transport
+    #[inline]
+    pub async fn start_download_with_transport<U: IntoDownloadUrl>(
+        url: U,
+        save_path: PathBuf,
+        transport: Arc<dyn ITransport>,
+        task_count: u64,
+        block: u64,
+    ) -> Result<Self> {
+        let url = url.into_download_url()?;
+        let info = transport.probe(&url).await?;
+        let save_path = Self::get_save_path(&url, save_path, info.file_name.clone())?;
+        let size = info.size;
+        Self::start(
+            url,
+            transport,
+            vec![Range {
+                start: 0,
+                end: size,
+            }],
+            info,
+            FileSave::create(save_path)?,
+            task_count,
+            block,
+        )
+        .await
+    }
+
+    /// start download now by options,
+    /// verify pieces by options or piece manifest of previous download
+    #[inline]
+    pub async fn start_download_with_options<U: IntoDownloadUrl>(
+        url: U,
+        save_path: PathBuf,
+        options: &DownloadOptions,
+    ) -> Result<Self> {
+        let url = url.into_download_url()?;
+        let transport = transport_for_url(&url);
+        let signature = match &options.signature {
+            Some(signature) => Some(signature.resolve().await?),
+            None => None,
+        };
+        // cache lookup is before probe,checksum is find by file name of url
+        let mut checksum = match (&options.checksum, &options.cache) {
+            (Some(checksum), Some(_)) => checksum.resolve(&url, None).await?,
+            _ => None,
+        };
+        let mut probed = None;
+        if let Some(cache) = &options.cache {
+            let (entry, info) = lookup_cache(cache, transport.as_ref(), &url).await?;
+            let entry = entry.filter(|entry| match &checksum {
+                Some(sha256) if !entry.sha256.eq_ignore_ascii_case(sha256) => {
+                    log::warn!(
+                        "cache of url:{} sha256:{} not match checksum:{}",
+                        url,
+                        entry.sha256,
+                        sha256
+                    );
+                    false
+                }
+                _ => true,
+            });
+            if let Some(entry) = entry {
+                let save_path = Self::get_save_path(&url, save_path, None)?;
+                return Self::place_cached(
+                    cache,
+                    entry,
+                    signature,
+                    options.extract.clone(),
+                    url,
+                    transport,
+                    save_path,
+                )
+                .await;
+            }
+            probed = info;
+        }
+        let mut info = match probed {
+            Some(info) => info,
+            None => transport.probe(&url).await?,
+        };
+        let size = info.size;
+        if let Some(limit) = options.max_size {
+            if size > limit {
+                return Err(DownloadError::SizeLimitExceeded { size, limit });
+            }
+        }
+        let save_path = Self::get_save_path(&url, save_path, info.file_name.clone())?;
+        if checksum.is_none() {
+            if let Some(source) = &options.checksum {
+                checksum = source.resolve(&url, info.file_name.as_deref()).await?;
+            }
+        }
+        if let Some(sha256) = checksum {
+            match &info.sha256 {
+                Some(actual) if !actual.eq_ignore_ascii_case(&sha256) => {
+                    return Err(DownloadError::ChecksumMismatch {
+                        expected: sha256,
+                        actual: actual.clone(),
+                    })
+                }
+                _ => info.sha256 = Some(sha256),
+            }
+        }
+        if let Some(cache) = &options.cache {
+            let key = url.to_string();
+            let validator = info.validator.clone();
+            let sha256 = info.sha256.clone();
+            let policy = info.cache_policy;
+            let entry = cache
+                .blocking(move |cache| {
+                    cache.lookup(&key, validator.as_deref(), sha256.as_deref(), size, &policy)
+                })
+                .await?;
+            if let Some(entry) = entry {
+                return Self::place_cached(
+                    cache,
+                    entry,
+                    signature,
+                    options.extract.clone(),
+                    url,
+                    transport,
+                    save_path,
+                )
+                .await;
+            }
+        }
+        if info.pieces.is_none() {
+            info.pieces = options.pieces.clone().or_else(|| {
+                PieceManifest::load_previous(&save_path, size, info.validator.as_deref())
+            });
+        }
+        let mut save_file = FileSave::create(save_path)?;
+        if let Some(length) = options.piece_manifest {
+            save_file = save_file.save_piece_manifest(length, info.validator.clone());
+        }
+        if let Some(signature) = signature {
+            save_file = save_file.verify_signature(signature);
+        }
+        if let Some(cache) = &options.cache {
+            save_file = save_file.save_to_cache(
+                cache.clone(),
+                url.to_string(),
+                info.validator.clone(),
+                info.cache_policy,
+            );
+        }
+        if let Some(extract) = &options.extract {
+            save_file = save_file.extract_to(extract.clone());
+        }
+        let download = Self::new(
+            url,
+            transport,
+            size,
+            vec![Range {
+                start: 0,
+                end: size,
+            }],
+            save_file,
+            options.task_count,
+            options.block,
+        );
+        download.set_restart_on_change(options.restart_on_change);
+        download.start_now(info).await
+    }
+
+    /// place cache file to save path,verify signature and extract archive,
+    /// return download handle already finish
+    #[inline]
+    async fn place_cached(
+        cache: &DownloadCache,
+        entry: CacheEntry,
+        signature: Option<MinisignVerifier>,
+        extract: Option<ExtractOptions>,
+        url: Url,
+        transport: Arc<dyn ITransport>,
+        save_path: PathBuf,
+    ) -> Result<Self> {
+        let size = entry.size;
+        let path = save_path.clone();
+        cache
+            .blocking(move |cache| {
+                cache.place(&entry, &path)?;
+                if let Some(signature) = signature {
+                    if let Err(err) = signature.verify_file(&path) {
+                        std::fs::remove_file(&path)?;
+                        return Err(err);
+                    }
+                }
+                if let Some(extract) = extract {
+                    extract_archive_blocking(&path, &extract)?;
+                }
+                Ok(())
+            })
+            .await?;
+        let file = Self::new(
+            url,
+            transport,
+            size,
+            vec![Range {
+                start: 0,
+                end: size,
+            }],
+            FileSave::open_append(save_path, size),
+            1,
+            1,
+        );
+        file.inner_status.down_size.store(size, Ordering::Release);
+        file.inner_status.set_finish();
+        Ok(file)
+    }
+
+    /// start download file of metalink now,use all mirrors,
+    /// verify pieces and sha256 if metalink has them
+    #[inline]
+    pub async fn start_metalink(
+        file: MetalinkFile,
+        save_path: PathBuf,
+        location: Option<&str>,
+        task_count: u64,
+        block: u64,
+    ) -> Result<Self> {
+        let transport = MetalinkTransport::new(file, location);
+        let url = transport.url()?;
+        Self::start_download_with_transport(url, save_path, Arc::new(transport), task_count, block)
+            .await
+    }
+
+    /// start download byte ranges of url now,
+    /// all ranges data save to file one by one
+    #[inline]
+    pub async fn start_download_ranges<U: IntoDownloadUrl>(
+        url: U,
+        save_path: PathBuf,
+        ranges: &[ByteRange],
         task_count: u64,
         block: u64,
     ) -> Result<Self> {
-        let url = url.into_url()?;
-        let (size,file_name, response) = Self::get_size_and_filename(&url).await?;
+        let url = url.into_download_url()?;
+        let transport = transport_for_url(&url);
+        let info = transport.probe(&url).await?;
+        let ranges = ranges
+            .iter()
+            .map(|range| range.resolve(info.size))
+            .collect::<Result<Vec<_>>>()?;
+        let save_path = Self::get_save_path(&url, save_path, info.file_name.clone())?;
+        let save_file = FileSave::create(save_path)?;
+        Self::start(url, transport, ranges, info, save_file, task_count, block).await
+    }
+
+    /// if save path is dir,push file name to it
+    #[inline]
+    fn get_save_path(
+        url: &Url,
+        mut save_path: PathBuf,
+        file_name: Option<String>,
+    ) -> Result<PathBuf> {
         if save_path.is_dir() {
-            if let Some(filename)=file_name{
+            if let Some(filename) = file_name {
                 save_path.push(filename);
-            }else{
+            } else {
                 let file_name = url
                     .path_segments()
                     .ok_or_else(|| DownloadError::NotFileName(url.clone()))?
-                    .rev()
-                    .next()
+                    .next_back()
                     .ok_or_else(|| DownloadError::NotFileName(url.clone()))?;
                 save_path.push(file_name);
             }
         }
+        Ok(save_path)
+    }
+
+    /// get save file real path
+    #[inline]
+    pub fn get_real_file_path(&self) -> String {
+        self.save_file.get_real_file_path()
+    }
+
+    /// get download temp file path
+    #[inline]
+    pub fn get_save_file_path(&self) -> String {
+        self.save_file.get_save_file_path()
+    }
+}
 
-        let task_count = { max(min(task_count, size / block), 1) };
+impl<S: IFileSave + 'static> DownloadFile<S> {
+    /// start download now,data write to custom save
+    #[inline]
+    pub async fn start_download_with_save<U: IntoDownloadUrl>(
+        url: U,
+        save_file: S,
+        task_count: u64,
+        block: u64,
+    ) -> Result<Self> {
+        let url = url.into_download_url()?;
+        let transport = transport_for_url(&url);
+        Self::start_with_transport(url, transport, save_file, task_count, block).await
+    }
 
-        let file = Self {
+    /// start download now,remote file read by custom transport,data write to custom save
+    #[inline]
+    pub async fn start_with_transport<U: IntoDownloadUrl>(
+        url: U,
+        transport: Arc<dyn ITransport>,
+        save_file: S,
+        task_count: u64,
+        block: u64,
+    ) -> Result<Self> {
+        let url = url.into_download_url()?;
+        let info = transport.probe(&url).await?;
+        let size = info.size;
+        Self::start(
+            url,
+            transport,
+            vec![Range {
+                start: 0,
+                end: size,
+            }],
+            info,
+            save_file,
             task_count,
-            save_file: Arc::new(FileSave::create(save_path, size)?),
-            inner_status: Arc::new(DownloadInner {
-                size,
-                url,
-                is_start: Default::default(),
-                is_finish: Default::default(),
-                down_size: Default::default(),
-                byte_sec_total: Default::default(),
-                byte_sec: Default::default(),
-                error: OnceCell::default(),
-            }),
-        };
-        file.save_file.init().await?;
+            block,
+        )
+        .await
+    }
+
+    #[inline]
+    fn new(
+        url: Url,
+        transport: Arc<dyn ITransport>,
+        remote_size: u64,
+        ranges: Vec<Range<u64>>,
+        save_file: S,
+        task_count: u64,
+        block: u64,
+    ) -> Self {
+        let size = ranges.iter().map(|range| range.end - range.start).sum();
+        Self {
+            task_count: max(min(task_count, size / block.max(1)), 1),
+            ranges,
+            save_file: Arc::new(save_file),
+            inner_status: Arc::new(DownloadInner::new(url, transport, remote_size, size)),
+        }
+    }
+
+    #[inline]
+    async fn start(
+        url: Url,
+        transport: Arc<dyn ITransport>,
+        ranges: Vec<Range<u64>>,
+        info: RemoteInfo,
+        save_file: S,
+        task_count: u64,
+        block: u64,
+    ) -> Result<Self> {
+        Self::new(
+            url, transport, info.size, ranges, save_file, task_count, block,
+        )
+        .start_now(info)
+        .await
+    }
+
+    /// init save and spawn download task
+    #[inline]
+    async fn start_now(self, info: RemoteInfo) -> Result<Self> {
+        let file = self;
+        if file.inner_status.is_check_change() {
+            file.inner_status.set_validator(info.validator);
+        }
+        file.inner_status.set_checksum(info.sha256, info.pieces);
+        let size = file.size();
+        file.save_file.init(size).await?;
         log::trace!("url file:{} init ok size:{}", file.inner_status.url, size);
-        if file.size() > 0 {
-            let size = file.size();
+        if size > 0 {
             file.inner_status.is_start.store(true, Ordering::Release);
             let connect_count = file.task_count;
-
-            if connect_count > 1 {
-                drop(response);
-                let block_size = size / connect_count;
-                let end_add_size = size % block_size;
-                assert_eq!(block_size * connect_count + end_add_size, size);
-                log::trace!(
-                    "computer task count:{}  block size:{} end add size:{}",
-                    connect_count,
-                    block_size,
-                    end_add_size
-                );
-                let save_file = file.save_file.clone();
-                let inner_status = file.inner_status.clone();
+            let ranges = file.ranges.clone();
+            let save_file = file.save_file.clone();
+            let inner_status = file.inner_status.clone();
+            tokio::spawn(async move {
+                let inner_status_sec = inner_status.clone();
                 tokio::spawn(async move {
-                    let mut join_vec = Vec::with_capacity(connect_count as usize);
-                    for i in 0..connect_count {
-                        let down_size = if i == connect_count - 1 {
-                            block_size + end_add_size
-                        } else {
-                            block_size
-                        };
-                        let start = i * block_size;
-
-                        let save_file = save_file.clone();
-                        let inner_status = inner_status.clone();
-                        let join: JoinHandle<Result<()>> = tokio::spawn(async move {
-                            let end = start + down_size - 1;
-
-                            log::trace!(
-                                "task:{} start:{} down size:{} end:{} init",
-                                i,
-                                start,
-                                down_size,
-                                end
-                            );
-
-                            ReqwestFile::new(save_file, inner_status, start, end)
-                                .run()
-                                .await?;
-                            log::trace!("task:{} finish", i);
-                            Ok(())
-                        });
-                        join_vec.push(join);
+                    while !inner_status_sec.is_finish() {
+                        inner_status_sec.byte_sec.store(
+                            inner_status_sec.byte_sec_total.swap(0, Ordering::Release),
+                            Ordering::Release,
+                        );
+                        sleep(Duration::from_secs(1)).await
                     }
-
-                    let inner_status_sec = inner_status.clone();
-                    tokio::spawn(async move {
-                        while !inner_status_sec.is_finish() {
-                            inner_status_sec.byte_sec.store(
-                                inner_status_sec.byte_sec_total.swap(0, Ordering::Release),
-                                Ordering::Release,
-                            );
-                            sleep(Duration::from_secs(1)).await
-                        }
-                    });
-
-                    for task in join_vec {
-                        match task.await {
-                            Ok(Err(err)) => {
-                                log::error!("http download error:{:?}", err);
-                                if !inner_status.error.initialized() {
-                                    if let Err(err) = inner_status.error.set(err) {
-                                        log::error!("set error fail:{}", err)
-                                    }
-                                }
-                            }
-                            Err(err) => {
-                                log::error!("join error:{:?}", err);
-                                if !inner_status.error.initialized() {
-                                    if let Err(err) =
-                                        inner_status.error.set(DownloadError::JoinInError(err))
-                                    {
-                                        log::error!("set error fail:{}", err)
-                                    }
-                                }
-                            }
-                            _ => {}
-                        }
-                    }
-                    if let Err(err) = save_file.finish().await {
-                        log::error!("save file finish error:{:?}", err);
-                        if !inner_status.error.initialized() {
-                            if let Err(err) = inner_status.error.set(err) {
-                                log::error!("set error fail:{}", err)
-                            }
-                        }
-                    }
-                    inner_status
-                        .down_size
-                        .store(inner_status.size, Ordering::Release);
-                    inner_status.is_finish.store(true, Ordering::Release);
                 });
-            } else {
-                let save_file = file.save_file.clone();
-                let inner_status = file.inner_status.clone();
 
-                tokio::spawn(async move {
-                    let inner_status_sec = inner_status.clone();
-                    tokio::spawn(async move {
-                        while !inner_status_sec.is_finish() {
-                            inner_status_sec.byte_sec.store(
-                                inner_status_sec.byte_sec_total.swap(0, Ordering::Release),
-                                Ordering::Release,
-                            );
-                            sleep(Duration::from_secs(1)).await
-                        }
-                    });
+                if let Err(err) = Self::run(
+                    save_file,
+                    inner_status.clone(),
+                    info.body,
+                    ranges,
+                    connect_count,
+                )
+                .await
+                {
+                    log::error!("http download error:{:?}", err);
+                    inner_status.set_error(err);
+                }
+                inner_status
+                    .down_size
+                    .store(inner_status.get_size(), Ordering::Release);
+                inner_status.set_finish();
+            });
+        } else {
+            file.save_file.finish().await?;
+            file.inner_status.set_finish();
+        }
 
-                    log::trace!(
-                        "start once task download url:{} size:{}",
-                        inner_status.url,
-                        size
-                    );
+        Ok(file)
+    }
 
-                    match ReqwestFile::new(save_file.clone(), inner_status.clone(), 0, size - 1)
-                        .run_once(response)
-                        .await
-                    {
-                        Err(err) => {
-                            log::error!("http download error:{:?}", err);
-                            if !inner_status.error.initialized() {
-                                if let Err(err) = inner_status.error.set(err) {
-                                    log::error!("set error fail:{}", err)
-                                }
-                            }
-                        }
-                        _ => {}
-                    }
+    /// init save and run download in current task,return error when fail
+    #[inline]
+    async fn run_now(&self, info: RemoteInfo) -> Result<()> {
+        if self.inner_status.is_check_change() {
+            self.inner_status.set_validator(info.validator);
+        }
+        self.inner_status.set_checksum(info.sha256, info.pieces);
+        let size = self.size();
+        self.save_file.init(size).await?;
+        if size > 0 {
+            self.inner_status.is_start.store(true, Ordering::Release);
+            Self::run(
+                self.save_file.clone(),
+                self.inner_status.clone(),
+                info.body,
+                self.ranges.clone(),
+                self.task_count,
+            )
+            .await?;
+        } else {
+            self.save_file.finish().await?;
+        }
+        self.inner_status.set_finish();
+        Ok(())
+    }
 
-                    if let Err(err) = save_file.finish().await {
-                        log::error!("save file finish error:{:?}", err);
-                        if !inner_status.error.initialized() {
-                            if let Err(err) = inner_status.error.set(err) {
-                                log::error!("set error fail:{}", err)
-                            }
-                        }
+    /// run all download task until finish,
+    /// if remote file changed and enable restart,download whole file again
+    #[inline]
+    async fn run(
+        save_file: Arc<S>,
+        inner_status: Arc<DownloadInner>,
+        mut body: Option<ByteStream>,
+        mut ranges: Vec<Range<u64>>,
+        mut connect_count: u64,
+    ) -> Result<()> {
+        let is_whole_file = ranges.len() == 1
+            && ranges[0].start == 0
+            && ranges[0].end == inner_status.get_remote_size();
+        let mut restart_count = 0;
+        loop {
+            match Self::run_tasks(
+                save_file.clone(),
+                inner_status.clone(),
+                body,
+                ranges.clone(),
+                connect_count,
+            )
+            .await
+            {
+                Err(DownloadError::RemoteChanged(url))
+                    if is_whole_file
+                        && inner_status.is_restart_on_change()
+                        && restart_count < MAX_RESTART_COUNT =>
+                {
+                    restart_count += 1;
+                    log::warn!(
+                        "url:{} remote file changed,restart download:{}",
+                        url,
+                        restart_count
+                    );
+                    let info = inner_status.transport.probe(&url).await?;
+                    let size = info.size;
+                    inner_status.reset(size, size);
+                    inner_status.set_validator(info.validator);
+                    inner_status.set_checksum(info.sha256, info.pieces);
+                    save_file.init(size).await?;
+                    if size == 0 {
+                        return save_file.finish().await;
                     }
-
-                    inner_status
-                        .down_size
-                        .store(inner_status.size, Ordering::Release);
-                    inner_status.is_finish.store(true, Ordering::Release);
-                });
+                    body = info.body;
+                    ranges = vec![Range {
+                        start: 0,
+                        end: size,
+                    }];
+                    connect_count = connect_count.min(size);
+                }
+                result => return result,
             }
-        } else {
-            file.save_file.finish().await?;
-            file.inner_status.is_finish.store(true, Ordering::Release);
         }
-
-        Ok(file)
     }
 
-    /// get url file size and file name
+    /// run all download task until finish,
+    /// finish save if download ok,otherwise abort it and return first error
     #[inline]
-    async fn get_size_and_filename(url: &Url) -> Result<(u64, Option<String>, Response)> {
-        let response = reqwest::Client::new().get(url.as_str()).send().await?;
-        if response.status() == StatusCode::OK {
-            let filename=Self::parse_content_filename(response.headers());
-            let size= Self::parse_content_length(response.headers())
-                .ok_or_else(|| DownloadError::NotGetFileSize(url.clone()))?;
-            Ok((
+    async fn run_tasks(
+        save_file: Arc<S>,
+        inner_status: Arc<DownloadInner>,
+        body: Option<ByteStream>,
+        ranges: Vec<Range<u64>>,
+        connect_count: u64,
+    ) -> Result<()> {
+        let size = inner_status.get_size();
+        let is_whole_file = size == inner_status.get_remote_size() && ranges.len() == 1;
+        if let Some(pieces) = inner_status.get_pieces().filter(|_| is_whole_file) {
+            save_file.track_pieces(&pieces, size);
+        }
+        let result = if connect_count > 1 || !is_whole_file {
+            drop(body);
+            log::trace!(
+                "computer task count:{} download size:{} ranges:{:?}",
+                connect_count,
                 size,
-                filename,
-                response,
-            ))
+                ranges
+            );
+
+            let mut join_vec = Vec::with_capacity(connect_count as usize);
+            for (i, segments) in split_ranges(&ranges, connect_count).into_iter().enumerate() {
+                let save_file = save_file.clone();
+                let inner_status = inner_status.clone();
+                let join: JoinHandle<Result<()>> = tokio::spawn(async move {
+                    for (start, end, save_start) in segments {
+                        log::trace!(
+                            "task:{} start:{} end:{} save start:{} init",
+                            i,
+                            start,
+                            end,
+                            save_start
+                        );
+
+                        RangeFile::new(save_file.clone(), inner_status.clone(), start, end)
+                            .save_at(save_start)
+                            .run()
+                            .await?;
+                    }
+                    log::trace!("task:{} finish", i);
+                    Ok(())
+                });
+                join_vec.push(join);
+            }
+
+            let mut result = Ok(());
+            for task in join_vec {
+                match task.await {
+                    Ok(Err(err)) => {
+                        log::error!("http download error:{:?}", err);
+                        if result.is_ok() {
+                            result = Err(err);
+                        }
+                    }
+                    Err(err) => {
+                        log::error!("join error:{:?}", err);
+                        if result.is_ok() {
+                            result = Err(DownloadError::JoinInError(err));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            result
         } else {
-            Err(DownloadError::HttpStatusError(
-                response.status().to_string(),
-            ))
+            log::trace!(
+                "start once task download url:{} size:{}",
+                inner_status.url,
+                size
+            );
+            let mut file = RangeFile::new(save_file.clone(), inner_status.clone(), 0, size - 1);
+            match body {
+                Some(body) => file.run_once(body).await,
+                None => file.run().await,
+            }
+        };
+
+        let result = match result {
+            Ok(()) if is_whole_file => {
+                match Self::verify_pieces(&save_file, &inner_status, connect_count).await {
+                    Ok(()) => Self::verify_sha256(&save_file, &inner_status).await,
+                    err => err,
+                }
+            }
+            result => result,
+        };
+        match result {
+            Ok(()) => save_file.finish().await,
+            Err(err) => {
+                if let Err(abort_err) = save_file.abort().await {
+                    log::error!("save file abort error:{:?}", abort_err);
+                }
+                Err(err)
+            }
         }
     }
 
+    /// check save data of every piece,download mismatch pieces again,
+    /// fail if piece still mismatch after retry
     #[inline]
-    fn parse_content_length(headers: &reqwest::header::HeaderMap) -> Option<u64> {
-        headers
-            .get(reqwest::header::CONTENT_LENGTH)?
-            .to_str()
-            .ok()?
-            .parse::<u64>()
-            .ok()
+    async fn verify_pieces(
+        save_file: &Arc<S>,
+        inner_status: &Arc<DownloadInner>,
+        connect_count: u64,
+    ) -> Result<()> {
+        let Some(pieces) = inner_status.get_pieces() else {
+            return Ok(());
+        };
+        let size = inner_status.get_remote_size();
+        if !pieces.is_match_size(size) {
+            log::warn!(
+                "url:{} piece count:{} not match size:{},skip verify",
+                inner_status.url,
+                pieces.hashes.len(),
+                size
+            );
+            return Ok(());
+        }
+        for retry in (0..=PIECE_RETRY_COUNT).rev() {
+            let mut mismatch = vec![];
+            for (index, expected) in pieces.hashes.iter().enumerate() {
+                let Some((start, end)) = pieces.piece_range(index, size) else {
+                    continue;
+                };
+                match save_file.hash_range(pieces.hash_type, start, end).await? {
+                    Some(actual) if !actual.eq_ignore_ascii_case(expected) => {
+                        mismatch.push((start, end, expected, actual))
+                    }
+                    Some(_) => {}
+                    None => {
+                        log::warn!(
+                            "url:{} save not support read back,skip verify",
+                            inner_status.url
+                        );
+                        return Ok(());
+                    }
+                }
+            }
+            if mismatch.is_empty() {
+                return Ok(());
+            }
+            log::error!(
+                "url:{} {} pieces mismatch,retry:{}",
+                inner_status.url,
+                mismatch.len(),
+                retry
+            );
+            if retry == 0 {
+                let (_, _, expected, actual) = mismatch.swap_remove(0);
+                return Err(DownloadError::ChecksumMismatch {
+                    expected: expected.clone(),
+                    actual,
+                });
+            }
+            for chunk in mismatch.chunks(connect_count.max(1) as usize) {
+                let mut join_vec = Vec::with_capacity(chunk.len());
+                for &(start, end, _, _) in chunk {
+                    inner_status.sub_down_size(end - start + 1);
+                    let mut file =
+                        RangeFile::new(save_file.clone(), inner_status.clone(), start, end);
+                    let join: JoinHandle<Result<()>> =
+                        tokio::spawn(async move { file.run().await });
+                    join_vec.push(join);
+                }
+                for join in join_vec {
+                    join.await.map_err(DownloadError::JoinInError)??;
+                }
+            }
+        }
+        Ok(())
     }
 
+    /// check save data sha256 same as expected
     #[inline]
-    fn parse_content_filename(headers: &reqwest::header::HeaderMap)->Option<String> {
-        headers
-            .get(reqwest::header::CONTENT_DISPOSITION)?
-            .to_str()
-            .ok()?
-            .trim()
-            .split(';')
-            .find_map(|content| {
-                let content = content.trim();
-                if content.find("filename") == Some(0) {
-                    content.split('=').last()
-                } else {
-                    None
-                }
-            }).map_or(None, |x| Some(x.to_string()))
+    async fn verify_sha256(save_file: &Arc<S>, inner_status: &DownloadInner) -> Result<()> {
+        let Some(expected) = inner_status.get_sha256() else {
+            return Ok(());
+        };
+        match save_file.sha256().await? {
+            Some(actual) if actual != expected => {
+                log::error!(
+                    "url:{} sha256 mismatch expected:{} actual:{}",
+                    inner_status.url,
+                    expected,
+                    actual
+                );
+                Err(DownloadError::ChecksumMismatch { expected, actual })
+            }
+            Some(_) => Ok(()),
+            None => {
+                log::warn!(
+                    "url:{} save not support sha256,skip verify",
+                    inner_status.url
+                );
+                Ok(())
+            }
+        }
     }
 
     /// get url
@@ -288,7 +946,7 @@ impl DownloadFile {
     /// file size
     #[inline]
     pub fn size(&self) -> u64 {
-        self.inner_status.size
+        self.inner_status.get_size()
     }
 
     /// get down size
@@ -321,10 +979,16 @@ impl DownloadFile {
         self.inner_status.get_error()
     }
 
-    /// get save file real path
+    /// wait download finish
     #[inline]
-    pub fn get_real_file_path(&self) -> String {
-        self.save_file.get_real_file_path()
+    pub async fn wait_finish(&self) {
+        self.inner_status.wait_finish().await
+    }
+
+    /// get save
+    #[inline]
+    pub fn get_save(&self) -> &Arc<S> {
+        &self.save_file
     }
 
     /// suspend download
@@ -338,27 +1002,120 @@ impl DownloadFile {
     pub fn restart(&self) {
         self.inner_status.is_start.store(true, Ordering::Release);
     }
+
+    /// set restart download when remote file changed,default is false
+    #[inline]
+    pub fn set_restart_on_change(&self, restart: bool) {
+        self.inner_status
+            .restart_on_change
+            .store(restart, Ordering::Release);
+    }
+
+    /// cancel download,all task stop with cancelled error
+    #[inline]
+    pub fn cancel(&self) {
+        self.inner_status.is_cancel.store(true, Ordering::Release);
+    }
+}
+
+/// split ranges to task count parts of same download size,
+/// return every task (start,end,save start) list,end is included
+#[inline]
+fn split_ranges(ranges: &[Range<u64>], task_count: u64) -> Vec<Vec<(u64, u64, u64)>> {
+    let size: u64 = ranges.iter().map(|range| range.end - range.start).sum();
+    let block_size = size / task_count;
+    let mut tasks = Vec::with_capacity(task_count as usize);
+    // save offset of range start
+    let mut range_save_start = 0;
+    let mut iter = ranges
+        .iter()
+        .filter(|range| range.start < range.end)
+        .peekable();
+    for i in 0..task_count {
+        let task_start = i * block_size;
+        let task_end = if i == task_count - 1 {
+            size
+        } else {
+            task_start + block_size
+        };
+        let mut segments = vec![];
+        while let Some(range) = iter.peek() {
+            let range_len = range.end - range.start;
+            let start = task_start.max(range_save_start);
+            let end = task_end.min(range_save_start + range_len);
+            if start < end {
+                let offset = range.start - range_save_start;
+                segments.push((start + offset, end + offset - 1, start));
+            }
+            if range_save_start + range_len <= task_end {
+                range_save_start += range_len;
+                iter.next();
+            } else {
+                break;
+            }
+        }
+        tasks.push(segments);
+    }
+    tasks
 }
 
 /// download status
 pub struct DownloadInner {
     url: Url,
-    size: u64,
+    transport: Arc<dyn ITransport>,
+    remote_size: AtomicU64,
+    size: AtomicU64,
+    validator: Mutex<Option<String>>,
+    sha256: Mutex<Option<String>>,
+    pieces: Mutex<Option<Arc<PieceHashes>>>,
     down_size: AtomicU64,
     is_start: AtomicBool,
     is_finish: AtomicBool,
+    is_cancel: AtomicBool,
+    check_change: AtomicBool,
+    restart_on_change: AtomicBool,
     error: OnceCell<DownloadError>,
     byte_sec: AtomicU64,
     byte_sec_total: AtomicU64,
+    finish_notify: Notify,
 }
 
 impl DownloadInner {
+    #[inline]
+    fn new(url: Url, transport: Arc<dyn ITransport>, remote_size: u64, size: u64) -> Self {
+        Self {
+            transport,
+            remote_size: AtomicU64::new(remote_size),
+            size: AtomicU64::new(size),
+            validator: Mutex::new(None),
+            sha256: Mutex::new(None),
+            pieces: Mutex::new(None),
+            url,
+            is_start: Default::default(),
+            is_finish: Default::default(),
+            is_cancel: Default::default(),
+            check_change: AtomicBool::new(true),
+            restart_on_change: Default::default(),
+            down_size: Default::default(),
+            byte_sec_total: Default::default(),
+            byte_sec: Default::default(),
+            error: OnceCell::default(),
+            finish_notify: Default::default(),
+        }
+    }
+
     /// get url
     #[inline]
     pub fn url(&self) -> &str {
         self.url.as_str()
     }
 
+    /// get parsed url
+    #[inline]
+    pub fn get_url(&self) -> &Url {
+        &self.url
+    }
+
     /// is start
     #[inline]
     pub fn is_start(&self) -> bool {
@@ -371,6 +1128,12 @@ impl DownloadInner {
         self.is_finish.load(Ordering::Acquire)
     }
 
+    /// is cancel
+    #[inline]
+    pub fn is_cancel(&self) -> bool {
+        self.is_cancel.load(Ordering::Acquire)
+    }
+
     /// is error
     #[inline]
     pub fn is_error(&self) -> bool {
@@ -387,7 +1150,7 @@ impl DownloadInner {
     #[inline]
     pub fn get_percent_complete(&self) -> f64 {
         let current =
-            self.down_size.load(Ordering::Acquire) as f64 / self.size.max(1) as f64 * 100.0;
+            self.down_size.load(Ordering::Acquire) as f64 / self.get_size().max(1) as f64 * 100.0;
         (current * 100.0).round() / 100.0
     }
 
@@ -403,10 +1166,144 @@ impl DownloadInner {
         self.down_size.load(Ordering::Acquire)
     }
 
+    /// get download size
+    #[inline]
+    pub fn get_size(&self) -> u64 {
+        self.size.load(Ordering::Acquire)
+    }
+
+    /// get remote file size
+    #[inline]
+    pub fn get_remote_size(&self) -> u64 {
+        self.remote_size.load(Ordering::Acquire)
+    }
+
+    /// get remote file etag or last modified,
+    /// every range request check remote file not changed by it
+    #[inline]
+    pub fn get_validator(&self) -> Option<String> {
+        self.validator.lock().unwrap().clone()
+    }
+
+    /// get expected sha256 hex of whole file
+    #[inline]
+    pub fn get_sha256(&self) -> Option<String> {
+        self.sha256.lock().unwrap().clone()
+    }
+
+    /// get expected piece hashes of whole file
+    #[inline]
+    pub fn get_pieces(&self) -> Option<Arc<PieceHashes>> {
+        self.pieces.lock().unwrap().clone()
+    }
+
+    /// is check remote file not changed while downloading
+    #[inline]
+    pub fn is_check_change(&self) -> bool {
+        self.check_change.load(Ordering::Acquire)
+    }
+
+    /// is restart download when remote file changed
+    #[inline]
+    pub fn is_restart_on_change(&self) -> bool {
+        self.restart_on_change.load(Ordering::Acquire)
+    }
+
+    /// set remote file etag or last modified
+    #[inline]
+    fn set_validator(&self, validator: Option<String>) {
+        *self.validator.lock().unwrap() = validator;
+    }
+
+    /// set expected sha256 hex and piece hashes of whole file,none is keep current
+    #[inline]
+    fn set_checksum(&self, sha256: Option<String>, pieces: Option<PieceHashes>) {
+        if let Some(sha256) = sha256 {
+            *self.sha256.lock().unwrap() = Some(sha256.to_ascii_lowercase());
+        }
+        if let Some(pieces) = pieces {
+            *self.pieces.lock().unwrap() = Some(Arc::new(pieces));
+        }
+    }
+
+    /// reset size and progress for restart download
+    #[inline]
+    fn reset(&self, remote_size: u64, size: u64) {
+        self.remote_size.store(remote_size, Ordering::Release);
+        self.size.store(size, Ordering::Release);
+        self.down_size.store(0, Ordering::Release);
+    }
+
+    /// wait download finish
+    #[inline]
+    pub async fn wait_finish(&self) {
+        let notified = self.finish_notify.notified();
+        tokio::pin!(notified);
+        notified.as_mut().enable();
+        if !self.is_finish() {
+            notified.await;
+        }
+    }
+
     /// add down size
     #[inline]
     fn add_down_size(&self, len: u64) {
         self.down_size.fetch_add(len, Ordering::Release);
         self.byte_sec_total.fetch_add(len, Ordering::Release);
     }
+
+    /// sub download size of data need download again
+    #[inline]
+    fn sub_down_size(&self, len: u64) {
+        let _ = self
+            .down_size
+            .fetch_update(Ordering::Release, Ordering::Acquire, |size| {
+                Some(size.saturating_sub(len))
+            });
+    }
+
+    /// set error if not set
+    #[inline]
+    fn set_error(&self, err: DownloadError) {
+        if !self.error.initialized() {
+            if let Err(err) = self.error.set(err) {
+                log::error!("set error fail:{}", err)
+            }
+        }
+    }
+
+    /// set finish and wake up all wait
+    #[inline]
+    fn set_finish(&self) {
+        self.is_finish.store(true, Ordering::Release);
+        self.finish_notify.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::split_ranges;
+    use std::ops::Range;
+
+    #[test]
+    fn split_whole_file() {
+        assert_eq!(
+            split_ranges(&[Range { start: 0, end: 100 }], 3),
+            vec![vec![(0, 32, 0)], vec![(33, 65, 33)], vec![(66, 99, 66)]]
+        );
+    }
+
+    #[test]
+    fn split_multi_ranges() {
+        // save offset of second range start after first range
+        assert_eq!(
+            split_ranges(&[10..20, 50..70], 2),
+            vec![vec![(10, 19, 0), (50, 54, 10)], vec![(55, 69, 15)]]
+        );
+    }
+
+    #[test]
+    fn split_skip_empty_range() {
+        assert_eq!(split_ranges(&[5..5, 0..4], 1), vec![vec![(0, 3, 0)]]);
+    }
 }
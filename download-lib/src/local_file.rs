@@ -0,0 +1,209 @@
+use super::error::{DownloadError, Result};
+use super::transport::{ByteStream, ITransport, RemoteInfo};
+use super::DownloadInner;
+use base64::Engine;
+use bytes::Bytes;
+use percent_encoding::percent_decode_str;
+use reqwest::Url;
+use std::io::{ErrorKind, SeekFrom};
+use std::path::PathBuf;
+use std::time::UNIX_EPOCH;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+/// local file read buffer size
+const READ_BUFFER_SIZE: usize = 256 * 1024;
+
+/// file:// transport,read local file chunk by offset
+#[derive(Clone, Default)]
+pub struct FileTransport;
+
+impl FileTransport {
+    /// local path of file url
+    #[inline]
+    fn path(url: &Url) -> Result<PathBuf> {
+        url.to_file_path().map_err(|_| {
+            std::io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("url:{} is not local file path", url),
+            )
+            .into()
+        })
+    }
+
+    /// get file size and modify time
+    #[inline]
+    async fn stat(url: &Url) -> Result<(u64, Option<String>)> {
+        let metadata = tokio::fs::metadata(Self::path(url)?).await?;
+        if !metadata.is_file() {
+            return Err(DownloadError::NotGetFileSize(url.clone()));
+        }
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|time| time.as_nanos().to_string());
+        Ok((metadata.len(), modified))
+    }
+}
+
+#[async_trait::async_trait]
+impl ITransport for FileTransport {
+    #[inline]
+    async fn probe(&self, url: &Url) -> Result<RemoteInfo> {
+        let (size, validator) = Self::stat(url).await?;
+        Ok(RemoteInfo {
+            size,
+            file_name: None,
+            validator,
+            sha256: None,
+            pieces: None,
+            cache_policy: Default::default(),
+            body: None,
+        })
+    }
+
+    #[inline]
+    async fn open_range(&self, status: &DownloadInner, start: u64, end: u64) -> Result<ByteStream> {
+        if status.is_check_change() {
+            let (size, modified) = Self::stat(status.get_url()).await?;
+            let validator = status.get_validator();
+            if size != status.get_remote_size() || (validator.is_some() && modified != validator) {
+                return Err(DownloadError::RemoteChanged(status.url.clone()));
+            }
+        }
+        let mut file = tokio::fs::File::open(Self::path(status.get_url())?).await?;
+        file.seek(SeekFrom::Start(start)).await?;
+        let stream = futures_util::stream::try_unfold(
+            (file, end - start + 1),
+            |(mut file, remaining)| async move {
+                if remaining == 0 {
+                    return Ok(None);
+                }
+                let mut buf = vec![0; READ_BUFFER_SIZE.min(remaining as usize)];
+                let len = file.read(&mut buf).await?;
+                if len == 0 {
+                    return Ok(None);
+                }
+                buf.truncate(len);
+                Ok(Some((Bytes::from(buf), (file, remaining - len as u64))))
+            },
+        );
+        Ok(Box::pin(stream))
+    }
+}
+
+/// data: transport,decode base64 or percent encoded data in url
+#[derive(Clone, Default)]
+pub struct DataTransport;
+
+impl DataTransport {
+    /// decode data url,"data:[<mediatype>][;base64],<data>"
+    #[inline]
+    fn decode(url: &Url) -> Result<Bytes> {
+        let invalid = || {
+            DownloadError::from(std::io::Error::new(
+                ErrorKind::InvalidData,
+                format!("invalid data url:{}", url),
+            ))
+        };
+        let (header, data) = url.path().split_once(',').ok_or_else(invalid)?;
+        // query is part of data
+        let data = match url.query() {
+            Some(query) => format!("{}?{}", data, query),
+            None => data.to_string(),
+        };
+        let data = percent_decode_str(&data).collect::<Vec<u8>>();
+        if header
+            .rsplit(';')
+            .next()
+            .is_some_and(|x| x.eq_ignore_ascii_case("base64"))
+        {
+            let data = data
+                .into_iter()
+                .filter(|x| !x.is_ascii_whitespace())
+                .collect::<Vec<u8>>();
+            base64::engine::general_purpose::STANDARD
+                .decode(data)
+                .map(Bytes::from)
+                .map_err(|_| invalid())
+        } else {
+            Ok(Bytes::from(data))
+        }
+    }
+
+    /// default file name by media type,like "data.png",
+    /// no media type is text/plain
+    #[inline]
+    fn file_name(url: &Url) -> String {
+        let media_type = url.path().split([';', ',']).next().unwrap_or_default();
+        let extension = match media_type.split_once('/') {
+            None => "txt".to_string(),
+            Some((_, subtype)) if subtype.eq_ignore_ascii_case("plain") => "txt".to_string(),
+            Some((_, subtype))
+                if !subtype.is_empty() && subtype.chars().all(|c| c.is_ascii_alphanumeric()) =>
+            {
+                subtype.to_ascii_lowercase()
+            }
+            _ => "bin".to_string(),
+        };
+        format!("data.{}", extension)
+    }
+}
+
+#[async_trait::async_trait]
+impl ITransport for DataTransport {
+    #[inline]
+    async fn probe(&self, url: &Url) -> Result<RemoteInfo> {
+        let data = Self::decode(url)?;
+        Ok(RemoteInfo {
+            size: data.len() as u64,
+            file_name: Some(Self::file_name(url)),
+            validator: None,
+            sha256: None,
+            pieces: None,
+            cache_policy: Default::default(),
+            body: Some(Box::pin(futures_util::stream::iter([Ok(data)]))),
+        })
+    }
+
+    #[inline]
+    async fn open_range(&self, status: &DownloadInner, start: u64, end: u64) -> Result<ByteStream> {
+        let data = Self::decode(status.get_url())?;
+        if start > data.len() as u64 {
+            return Err(DownloadError::InvalidRange(format!(
+                "{}-{} not satisfiable of size:{}",
+                start,
+                end,
+                data.len()
+            )));
+        }
+        let end = end.saturating_add(1).min(data.len() as u64);
+        let range = data.slice(start as usize..end.max(start) as usize);
+        Ok(Box::pin(futures_util::stream::iter([Ok(range)])))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DataTransport;
+    use reqwest::Url;
+
+    #[test]
+    fn decode_data_url() {
+        let url = Url::parse("data:text/plain;base64,aGVsbG8=").unwrap();
+        assert_eq!(&DataTransport::decode(&url).unwrap()[..], b"hello");
+        let url = Url::parse("data:,a%20b?c").unwrap();
+        assert_eq!(&DataTransport::decode(&url).unwrap()[..], b"a b?c");
+        let url = Url::parse("data:;base64,!!").unwrap();
+        assert!(DataTransport::decode(&url).is_err());
+    }
+
+    #[test]
+    fn data_url_file_name() {
+        let name = |url: &str| DataTransport::file_name(&Url::parse(url).unwrap());
+        assert_eq!(name("data:,hello"), "data.txt");
+        assert_eq!(name("data:text/plain;charset=utf-8,hello"), "data.txt");
+        assert_eq!(name("data:image/png;base64,AAAA"), "data.png");
+        assert_eq!(name("data:application/vnd.api+json,{}"), "data.bin");
+    }
+}
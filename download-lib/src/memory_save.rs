@@ -0,0 +1,98 @@
+use super::error::{DownloadError, Result};
+use super::file_save::IFileSave;
+use super::piece_hash::HashType;
+use bytes::Bytes;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// memory save,data write to preallocated buffer of init size,
+/// caller should limit size,like max size of download_to_bytes
+#[derive(Default)]
+pub struct MemorySave {
+    buffer: Mutex<Vec<u8>>,
+    is_finish: AtomicBool,
+}
+
+impl MemorySave {
+    /// create memory save
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// is all data write finish
+    #[inline]
+    pub fn is_finish(&self) -> bool {
+        self.is_finish.load(Ordering::Acquire)
+    }
+
+    /// take data,if not finish return none
+    #[inline]
+    pub fn take_bytes(&self) -> Option<Bytes> {
+        if self.is_finish() {
+            Some(Bytes::from(std::mem::take(
+                &mut *self.buffer.lock().unwrap(),
+            )))
+        } else {
+            None
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl IFileSave for MemorySave {
+    #[inline]
+    async fn init(&self, size: u64) -> Result<()> {
+        *self.buffer.lock().unwrap() = vec![0; size as usize];
+        self.is_finish.store(false, Ordering::Release);
+        Ok(())
+    }
+
+    #[inline]
+    async fn write_all_by_offset(&self, data: Bytes, offset: u64) -> Result<()> {
+        let mut buffer = self.buffer.lock().unwrap();
+        let start = offset as usize;
+        let end = start + data.len();
+        if end > buffer.len() {
+            return Err(DownloadError::SizeMismatch {
+                expected: buffer.len() as u64,
+                actual: end as u64,
+            });
+        }
+        buffer[start..end].copy_from_slice(&data);
+        Ok(())
+    }
+
+    #[inline]
+    async fn finish(&self) -> Result<()> {
+        self.is_finish.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    #[inline]
+    async fn abort(&self) -> Result<()> {
+        self.buffer.lock().unwrap().clear();
+        Ok(())
+    }
+
+    #[inline]
+    async fn sha256(&self) -> Result<Option<String>> {
+        Ok(Some(
+            HashType::Sha256.digest(&self.buffer.lock().unwrap()[..]),
+        ))
+    }
+
+    #[inline]
+    async fn hash_range(
+        &self,
+        hash_type: HashType,
+        start: u64,
+        end: u64,
+    ) -> Result<Option<String>> {
+        let buffer = self.buffer.lock().unwrap();
+        let end = (end as usize + 1).min(buffer.len());
+        Ok(buffer
+            .get(start as usize..end)
+            .map(|data| hash_type.digest(data)))
+    }
+}
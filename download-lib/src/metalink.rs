@@ -0,0 +1,579 @@
+use super::error::{DownloadError, Result};
+use super::file_save::hash_file;
+use super::options::DownloadOptions;
+use super::piece_hash::{HashType, PieceHashes};
+use super::transport::{transport_for_url, ByteStream, ITransport, IntoDownloadUrl, RemoteInfo};
+use super::{download_to_bytes, DownloadFile, DownloadInner, FileSave};
+use futures_util::{Stream, StreamExt};
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use reqwest::Url;
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::ops::Range;
+use std::path::{Component, Path, PathBuf};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use tokio::sync::OnceCell;
+
+/// mirror error count,more than it is use only when all mirrors fail
+const MAX_MIRROR_ERROR_COUNT: u32 = 3;
+
+/// mirror url of metalink file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetalinkUrl {
+    pub url: Url,
+    /// lower is better,default is 999999
+    pub priority: u32,
+    /// country code of mirror
+    pub location: Option<String>,
+}
+
+/// file of metalink,size,hashes and mirrors
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MetalinkFile {
+    /// file name,may contain relative dir
+    pub name: String,
+    pub size: Option<u64>,
+    /// whole file hex hashes
+    pub hashes: Vec<(HashType, String)>,
+    pub pieces: Option<PieceHashes>,
+    pub urls: Vec<MetalinkUrl>,
+}
+
+impl MetalinkFile {
+    /// whole file hex hash of type
+    #[inline]
+    pub fn hash(&self, hash_type: HashType) -> Option<&str> {
+        self.hashes
+            .iter()
+            .find(|(x, _)| *x == hash_type)
+            .map(|(_, hash)| hash.as_str())
+    }
+
+    /// strongest whole file hash other than sha256,
+    /// sha256 is verified by download before finish
+    #[inline]
+    pub fn other_hash(&self) -> Option<(HashType, &str)> {
+        [HashType::Sha512, HashType::Sha1, HashType::Md5]
+            .into_iter()
+            .find_map(|hash_type| self.hash(hash_type).map(|hash| (hash_type, hash)))
+    }
+
+    /// relative save path of name,none is not safe path like "../x"
+    #[inline]
+    pub fn relative_path(&self) -> Option<&Path> {
+        let path = Path::new(&self.name);
+        (!self.name.is_empty()
+            && path
+                .components()
+                .all(|component| matches!(component, Component::Normal(_))))
+        .then_some(path)
+    }
+}
+
+/// metalink document,support RFC 5854 meta4 and metalink 3.0
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Metalink {
+    pub files: Vec<MetalinkFile>,
+}
+
+impl Metalink {
+    /// parse metalink xml
+    #[inline]
+    pub fn parse(xml: &str) -> Result<Self> {
+        let invalid = |err: quick_xml::Error| {
+            DownloadError::from(std::io::Error::new(ErrorKind::InvalidData, err))
+        };
+        let mut reader = Reader::from_str(xml);
+        reader.trim_text(true);
+        let mut files = vec![];
+        let mut path: Vec<(String, HashMap<String, String>)> = vec![];
+        let mut file: Option<MetalinkFile> = None;
+        loop {
+            match reader.read_event().map_err(invalid)? {
+                Event::Start(tag) => {
+                    let name = String::from_utf8_lossy(tag.local_name().as_ref()).to_string();
+                    let mut attributes = HashMap::new();
+                    for attribute in tag.attributes().flatten() {
+                        attributes.insert(
+                            String::from_utf8_lossy(attribute.key.local_name().as_ref())
+                                .to_string(),
+                            attribute.unescape_value().map_err(invalid)?.to_string(),
+                        );
+                    }
+                    match name.as_str() {
+                        "file" => {
+                            file = Some(MetalinkFile {
+                                name: attributes.get("name").cloned().unwrap_or_default(),
+                                ..Default::default()
+                            })
+                        }
+                        "pieces" => {
+                            if let Some(file) = file.as_mut() {
+                                let length = attributes.get("length").and_then(|x| x.parse().ok());
+                                let hash_type =
+                                    attributes.get("type").and_then(|x| HashType::parse(x));
+                                if let (Some(length), Some(hash_type)) = (length, hash_type) {
+                                    file.pieces = Some(PieceHashes {
+                                        length,
+                                        hash_type,
+                                        hashes: vec![],
+                                    });
+                                }
+                            }
+                        }
+                        _ => {}
+                    }
+                    path.push((name, attributes));
+                }
+                Event::End(_) => {
+                    let is_file = path.pop().is_some_and(|(name, _)| name == "file");
+                    if is_file {
+                        files.extend(file.take());
+                    }
+                }
+                Event::Text(text) => {
+                    let Some(file) = file.as_mut() else {
+                        continue;
+                    };
+                    let text = text.unescape().map_err(invalid)?.trim().to_string();
+                    let Some((name, attributes)) = path.last() else {
+                        continue;
+                    };
+                    let parent = path
+                        .len()
+                        .checked_sub(2)
+                        .map(|i| path[i].0.as_str())
+                        .unwrap_or_default();
+                    match (parent, name.as_str()) {
+                        ("file", "size") => file.size = text.parse().ok(),
+                        ("pieces", "hash") => {
+                            if let Some(pieces) = file.pieces.as_mut() {
+                                pieces.hashes.push(text.to_ascii_lowercase());
+                            }
+                        }
+                        (_, "hash") => {
+                            if let Some(hash_type) =
+                                attributes.get("type").and_then(|x| HashType::parse(x))
+                            {
+                                file.hashes.push((hash_type, text.to_ascii_lowercase()));
+                            }
+                        }
+                        (_, "url") => {
+                            // metalink 3.0 list torrent in url
+                            if attributes.get("type").is_some_and(|x| x == "bittorrent") {
+                                continue;
+                            }
+                            let Ok(url) = Url::parse(&text) else {
+                                log::warn!("metalink file:{} invalid url:{}", file.name, text);
+                                continue;
+                            };
+                            // metalink 3.0 preference 100 is best
+                            let priority = match attributes.get("priority") {
+                                Some(priority) => priority.parse().ok(),
+                                None => attributes
+                                    .get("preference")
+                                    .and_then(|x| x.parse::<u32>().ok())
+                                    .map(|x| 101u32.saturating_sub(x)),
+                            };
+                            file.urls.push(MetalinkUrl {
+                                url,
+                                priority: priority.unwrap_or(999999),
+                                location: attributes
+                                    .get("location")
+                                    .map(|x| x.to_ascii_lowercase()),
+                            });
+                        }
+                        _ => {}
+                    }
+                }
+                Event::Eof => break,
+                _ => {}
+            }
+        }
+        Ok(Self { files })
+    }
+
+    /// download and parse metalink of url
+    #[inline]
+    pub async fn load<U: IntoDownloadUrl>(url: U) -> Result<Self> {
+        let data = download_to_bytes(url, &DownloadOptions::default()).await?;
+        Self::parse(&String::from_utf8_lossy(&data))
+    }
+}
+
+/// mirror transport and status
+struct Mirror {
+    url: Url,
+    transport: Arc<dyn ITransport>,
+    /// mirror own size and validator
+    status: OnceCell<Arc<DownloadInner>>,
+    error_count: Arc<AtomicU32>,
+}
+
+/// served range,start end and mirror index
+type Served = (u64, u64, usize);
+
+/// mirror range stream,remove served range when stream end,
+/// count mirror error when stream read error
+struct MirrorStream {
+    stream: ByteStream,
+    served: Arc<Mutex<Vec<Served>>>,
+    range: Served,
+    error_count: Arc<AtomicU32>,
+}
+
+impl Stream for MirrorStream {
+    type Item = Result<bytes::Bytes>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let poll = self.stream.poll_next_unpin(cx);
+        match &poll {
+            Poll::Ready(None) => {
+                let mut served = self.served.lock().unwrap();
+                if let Some(index) = served.iter().position(|x| *x == self.range) {
+                    served.swap_remove(index);
+                }
+            }
+            Poll::Ready(Some(Err(_))) => {
+                self.error_count.fetch_add(1, Ordering::AcqRel);
+            }
+            _ => {}
+        }
+        poll
+    }
+}
+
+/// metalink file transport,
+/// range request use mirrors in turn by priority,fail mirror retry by next one
+pub struct MetalinkTransport {
+    file: MetalinkFile,
+    mirrors: Vec<Mirror>,
+    next: AtomicUsize,
+    /// range served by mirror and not finish,request it again is retry
+    served: Arc<Mutex<Vec<Served>>>,
+}
+
+impl MetalinkTransport {
+    /// create transport of metalink file,mirrors order by priority,
+    /// location of mirrors same as it is first
+    #[inline]
+    pub fn new(file: MetalinkFile, location: Option<&str>) -> Self {
+        let mut urls = file.urls.clone();
+        urls.sort_by_key(|url| {
+            let is_location = location.is_some_and(|location| {
+                url.location
+                    .as_deref()
+                    .is_some_and(|x| x.eq_ignore_ascii_case(location))
+            });
+            (!is_location, url.priority)
+        });
+        let mirrors = urls
+            .into_iter()
+            .map(|url| Mirror {
+                transport: transport_for_url(&url.url),
+                url: url.url,
+                status: OnceCell::new(),
+                error_count: Arc::new(AtomicU32::new(0)),
+            })
+            .collect();
+        Self {
+            file,
+            mirrors,
+            next: AtomicUsize::new(0),
+            served: Arc::new(Mutex::new(vec![])),
+        }
+    }
+
+    /// first mirror url
+    #[inline]
+    pub fn url(&self) -> Result<Url> {
+        self.mirrors
+            .first()
+            .map(|mirror| mirror.url.clone())
+            .ok_or_else(|| {
+                DownloadError::InvalidUrl(format!("metalink file:{} has no url", self.file.name))
+            })
+    }
+
+    /// probe mirror once,check size same as file
+    #[inline]
+    async fn mirror_status(
+        &self,
+        mirror: &Mirror,
+        size: u64,
+        check_change: bool,
+    ) -> Result<Arc<DownloadInner>> {
+        mirror
+            .status
+            .get_or_try_init(|| async {
+                let info = mirror.transport.probe(&mirror.url).await?;
+                if info.size != size {
+                    log::error!(
+                        "metalink mirror:{} size:{} not match:{}",
+                        mirror.url,
+                        info.size,
+                        size
+                    );
+                    return Err(DownloadError::SizeMismatch {
+                        expected: size,
+                        actual: info.size,
+                    });
+                }
+                let status =
+                    DownloadInner::new(mirror.url.clone(), mirror.transport.clone(), size, size);
+                status.check_change.store(check_change, Ordering::Release);
+                if check_change {
+                    status.set_validator(info.validator);
+                }
+                Ok(Arc::new(status))
+            })
+            .await
+            .cloned()
+    }
+
+    /// mirrors index of range start from next one,
+    /// mirror of many errors is last,mirror serving overlap range is after others
+    #[inline]
+    fn mirror_order(&self, start: u64, end: u64) -> Vec<usize> {
+        let served = self
+            .served
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(served_start, served_end, _)| start <= *served_end && *served_start <= end)
+            .map(|(_, _, i)| *i)
+            .collect::<Vec<_>>();
+        let count = self.mirrors.len();
+        let first = self.next.fetch_add(1, Ordering::AcqRel);
+        let mut order = (0..count).map(|i| (first + i) % count).collect::<Vec<_>>();
+        order.sort_by_key(|&i| {
+            (
+                self.mirrors[i].error_count.load(Ordering::Acquire) >= MAX_MIRROR_ERROR_COUNT,
+                served.contains(&i),
+            )
+        });
+        order
+    }
+}
+
+#[async_trait::async_trait]
+impl ITransport for MetalinkTransport {
+    #[inline]
+    async fn probe(&self, url: &Url) -> Result<RemoteInfo> {
+        let size = match self.file.size {
+            Some(size) => size,
+            None => {
+                let mut result = Err(DownloadError::NotGetFileSize(url.clone()));
+                for mirror in &self.mirrors {
+                    match mirror.transport.probe(&mirror.url).await {
+                        Ok(info) => {
+                            result = Ok(info.size);
+                            break;
+                        }
+                        Err(err) => {
+                            log::warn!("metalink mirror:{} probe error:{}", mirror.url, err);
+                            result = Err(err);
+                        }
+                    }
+                }
+                result?
+            }
+        };
+        Ok(RemoteInfo {
+            size,
+            file_name: self
+                .file
+                .relative_path()
+                .and_then(|path| path.file_name())
+                .map(|name| name.to_string_lossy().to_string()),
+            validator: None,
+            sha256: self.file.hash(HashType::Sha256).map(|x| x.to_string()),
+            pieces: self.file.pieces.clone(),
+            cache_policy: Default::default(),
+            body: None,
+        })
+    }
+
+    #[inline]
+    async fn open_range(&self, status: &DownloadInner, start: u64, end: u64) -> Result<ByteStream> {
+        let mut last_error = None;
+        for i in self.mirror_order(start, end) {
+            let mirror = &self.mirrors[i];
+            let result = match self
+                .mirror_status(mirror, status.get_remote_size(), status.is_check_change())
+                .await
+            {
+                Ok(mirror_status) => {
+                    mirror
+                        .transport
+                        .open_range(&mirror_status, start, end)
+                        .await
+                }
+                Err(err) => Err(err),
+            };
+            match result {
+                Ok(stream) => {
+                    log::trace!("metalink mirror:{} range:{}-{}", mirror.url, start, end);
+                    self.served.lock().unwrap().push((start, end, i));
+                    return Ok(Box::pin(MirrorStream {
+                        stream,
+                        served: self.served.clone(),
+                        range: (start, end, i),
+                        error_count: mirror.error_count.clone(),
+                    }));
+                }
+                Err(err) => {
+                    log::warn!("metalink mirror:{} error:{}", mirror.url, err);
+                    mirror.error_count.fetch_add(1, Ordering::AcqRel);
+                    last_error = Some(err);
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(|| {
+            DownloadError::InvalidUrl(format!("metalink file:{} has no url", self.file.name))
+        }))
+    }
+}
+
+/// download all files of metalink to dir,
+/// local path is file name of metalink,return saved files
+#[inline]
+pub async fn download_metalink<U: IntoDownloadUrl>(
+    url: U,
+    save_dir: PathBuf,
+    options: &DownloadOptions,
+) -> Result<Vec<PathBuf>> {
+    let metalink = Metalink::load(url).await?;
+    let mut files = Vec::with_capacity(metalink.files.len());
+    for file in metalink.files {
+        let Some(relative) = file.relative_path() else {
+            log::warn!("metalink file name:{} is not safe path,skip it", file.name);
+            continue;
+        };
+        let save_path = save_dir.join(relative);
+        if let Some(parent) = save_path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let other_hash = match file.hash(HashType::Sha256) {
+            Some(_) => None,
+            None => file
+                .other_hash()
+                .map(|(hash_type, hash)| (hash_type, hash.to_string())),
+        };
+        if file.hashes.is_empty() && file.pieces.is_none() {
+            log::warn!("metalink file:{} has no hash,not verify", file.name);
+        }
+        let transport = Arc::new(MetalinkTransport::new(file, options.location.as_deref()));
+        let url = transport.url()?;
+        log::trace!("metalink download:{} to {:?}", url, save_path);
+        let info = transport.probe(&url).await?;
+        let size = info.size;
+        let download = DownloadFile::new(
+            url,
+            transport,
+            size,
+            vec![Range {
+                start: 0,
+                end: size,
+            }],
+            FileSave::create(save_path.clone())?,
+            options.task_count,
+            options.block,
+        );
+        download.set_restart_on_change(options.restart_on_change);
+        download.run_now(info).await?;
+        if let Some((hash_type, expected)) = other_hash {
+            verify_file(&save_path, hash_type, expected).await?;
+        }
+        files.push(save_path);
+    }
+    Ok(files)
+}
+
+/// check saved file hash same as metalink,remove file if mismatch
+#[inline]
+async fn verify_file(save_path: &Path, hash_type: HashType, expected: String) -> Result<()> {
+    let path = save_path.to_path_buf();
+    let actual = tokio::task::spawn_blocking(move || hash_file(&path, hash_type, 0, u64::MAX))
+        .await
+        .map_err(DownloadError::JoinInError)??;
+    if !actual.eq_ignore_ascii_case(&expected) {
+        log::error!(
+            "metalink file:{:?} {} mismatch expected:{} actual:{}",
+            save_path,
+            hash_type.name(),
+            expected,
+            actual
+        );
+        tokio::fs::remove_file(save_path).await?;
+        return Err(DownloadError::ChecksumMismatch { expected, actual });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HashType, Metalink};
+
+    #[test]
+    fn parse_meta4() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<metalink xmlns="urn:ietf:params:xml:ns:metalink">
+  <file name="dir/a.iso">
+    <size>10</size>
+    <hash type="sha-256">ABCD</hash>
+    <hash type="md5">1234</hash>
+    <pieces length="4" type="sha-1">
+      <hash>01</hash><hash>02</hash><hash>03</hash>
+    </pieces>
+    <url location="de" priority="2">http://de.example.com/a.iso</url>
+    <url priority="1">ftp://example.com/a.iso</url>
+    <url>not a url</url>
+  </file>
+  <file name="../b.iso"><url>http://example.com/b.iso</url></file>
+</metalink>"#;
+        let metalink = Metalink::parse(xml).unwrap();
+        assert_eq!(metalink.files.len(), 2);
+        let file = &metalink.files[0];
+        assert_eq!(file.size, Some(10));
+        assert_eq!(file.hash(HashType::Sha256), Some("abcd"));
+        assert_eq!(file.other_hash(), Some((HashType::Md5, "1234")));
+        let pieces = file.pieces.as_ref().unwrap();
+        assert_eq!((pieces.length, pieces.hash_type), (4, HashType::Sha1));
+        assert_eq!(pieces.hashes, ["01", "02", "03"]);
+        assert!(pieces.is_match_size(10));
+        assert_eq!(file.urls.len(), 2);
+        assert_eq!(file.urls[0].location.as_deref(), Some("de"));
+        assert_eq!(file.urls[1].priority, 1);
+        assert!(file.relative_path().is_some());
+        assert!(metalink.files[1].relative_path().is_none());
+    }
+
+    #[test]
+    fn parse_metalink3() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<metalink version="3.0" xmlns="http://www.metalinker.org/">
+  <files>
+    <file name="a.iso">
+      <size>5</size>
+      <verification><hash type="sha1">AA</hash></verification>
+      <resources>
+        <url type="http" preference="90">http://a.example.com/a.iso</url>
+        <url type="bittorrent">http://a.example.com/a.torrent</url>
+        <url type="http" preference="100">http://b.example.com/a.iso</url>
+      </resources>
+    </file>
+  </files>
+</metalink>"#;
+        let metalink = Metalink::parse(xml).unwrap();
+        let file = &metalink.files[0];
+        assert_eq!(file.hash(HashType::Sha256), None);
+        assert_eq!(file.other_hash(), Some((HashType::Sha1, "aa")));
+        let priority = file.urls.iter().map(|x| x.priority).collect::<Vec<_>>();
+        assert_eq!(priority, [11, 1]);
+    }
+}
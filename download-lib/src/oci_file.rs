@@ -0,0 +1,597 @@
+use super::error::{DownloadError, Result};
+use super::reqwest_file::{probe_response, range_response};
+use super::transport::{ByteStream, ITransport, RemoteInfo};
+use super::DownloadInner;
+use base64::Engine;
+use percent_encoding::percent_decode_str;
+use reqwest::header::{AUTHORIZATION, IF_RANGE, RANGE, WWW_AUTHENTICATE};
+use reqwest::{Client, Response, StatusCode, Url};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// docker hub registry host
+const DOCKER_HUB_REGISTRY: &str = "registry-1.docker.io";
+
+/// oci registry blob transport,
+/// url like "oci://registry[:port]/repository@sha256:digest",
+/// get bearer token by registry challenge,follow blob redirect,
+/// download verify sha256 of digest
+#[derive(Clone, Default)]
+pub struct OciTransport {
+    client: Client,
+    credential: Option<(String, String)>,
+    plain_http: bool,
+    /// authorization header of repository
+    tokens: Arc<Mutex<HashMap<String, String>>>,
+}
+
+/// blob location of oci url
+struct OciBlob {
+    registry: String,
+    repository: String,
+    /// sha256 hex
+    digest: String,
+    url: Url,
+}
+
+impl OciBlob {
+    /// parse "oci://registry/repository@sha256:digest"
+    #[inline]
+    fn parse(url: &Url, plain_http: bool) -> Result<Self> {
+        let invalid = |reason: &str| DownloadError::InvalidUrl(format!("{} {}", url, reason));
+        let host = url.host_str().ok_or_else(|| invalid("has no registry"))?;
+        let registry = match url.port() {
+            Some(port) => format!("{}:{}", host, port),
+            None if host == "docker.io" || host == "index.docker.io" => {
+                DOCKER_HUB_REGISTRY.to_string()
+            }
+            None => host.to_string(),
+        };
+        let path = percent_decode_str(url.path().trim_start_matches('/')).decode_utf8_lossy();
+        let (repository, digest) = path
+            .rsplit_once('@')
+            .ok_or_else(|| invalid("has no blob digest"))?;
+        let digest = digest
+            .strip_prefix("sha256:")
+            .filter(|hex| hex.len() == 64 && hex.bytes().all(|x| x.is_ascii_hexdigit()))
+            .ok_or_else(|| invalid("digest is not sha256"))?
+            .to_ascii_lowercase();
+        if repository.is_empty() {
+            return Err(invalid("has no repository"));
+        }
+        // docker hub official image
+        let repository = if registry == DOCKER_HUB_REGISTRY && !repository.contains('/') {
+            format!("library/{}", repository)
+        } else {
+            repository.to_string()
+        };
+        let scheme = if plain_http || matches!(host, "localhost" | "127.0.0.1" | "[::1]") {
+            "http"
+        } else {
+            "https"
+        };
+        let blob_url = format!(
+            "{}://{}/v2/{}/blobs/sha256:{}",
+            scheme, registry, repository, digest
+        );
+        let blob_url = Url::parse(&blob_url).map_err(|_| invalid("is invalid blob url"))?;
+        Ok(Self {
+            registry,
+            repository,
+            digest,
+            url: blob_url,
+        })
+    }
+}
+
+impl OciTransport {
+    /// create transport,anonymous or ~/.docker/config.json credential
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// create transport use custom client
+    #[inline]
+    pub fn with_client(client: Client) -> Self {
+        Self {
+            client,
+            ..Default::default()
+        }
+    }
+
+    /// registry user and password or token
+    #[inline]
+    pub fn with_credential(mut self, user: String, password: String) -> Self {
+        self.credential = Some((user, password));
+        self
+    }
+
+    /// use http not https,localhost always use http
+    #[inline]
+    pub fn plain_http(mut self, plain_http: bool) -> Self {
+        self.plain_http = plain_http;
+        self
+    }
+
+    /// credential of registry,url userinfo first,then custom,then docker config
+    #[inline]
+    fn credential(&self, url: &Url, registry: &str) -> Option<(String, String)> {
+        if !url.username().is_empty() {
+            let decode = |x: &str| percent_decode_str(x).decode_utf8_lossy().to_string();
+            return Some((
+                decode(url.username()),
+                url.password().map(decode).unwrap_or_default(),
+            ));
+        }
+        self.credential
+            .clone()
+            .or_else(|| docker_config_credential(registry))
+    }
+
+    /// get blob,auth by registry challenge if need
+    #[inline]
+    async fn get(
+        &self,
+        url: &Url,
+        range: Option<(u64, u64)>,
+        validator: Option<String>,
+    ) -> Result<(OciBlob, Response)> {
+        let blob = OciBlob::parse(url, self.plain_http)?;
+        let key = format!("{}/{}", blob.registry, blob.repository);
+        let mut is_auth = false;
+        loop {
+            let mut request = self.client.get(blob.url.clone());
+            let authorization = self.tokens.lock().unwrap().get(&key).cloned();
+            if let Some(authorization) = authorization {
+                request = request.header(AUTHORIZATION, authorization);
+            }
+            if let Some((start, end)) = range {
+                request = request.header(RANGE, format!("bytes={}-{}", start, end));
+                if let Some(validator) = &validator {
+                    request = request.header(IF_RANGE, validator.as_str());
+                }
+            }
+            let response = request.send().await?;
+            if response.status() != StatusCode::UNAUTHORIZED || is_auth {
+                return Ok((blob, response));
+            }
+            let challenge = response
+                .headers()
+                .get(WWW_AUTHENTICATE)
+                .and_then(|challenge| challenge.to_str().ok());
+            let credential = self.credential(url, &blob.registry);
+            let authorization = match challenge {
+                Some(challenge) => self.authorize(&blob, challenge, credential).await?,
+                None => None,
+            };
+            match authorization {
+                Some(authorization) => {
+                    log::trace!("oci registry:{} auth ok", key);
+                    self.tokens
+                        .lock()
+                        .unwrap()
+                        .insert(key.clone(), authorization);
+                    is_auth = true;
+                }
+                None => return Ok((blob, response)),
+            }
+        }
+    }
+
+    /// get authorization header by www-authenticate challenge
+    #[inline]
+    async fn authorize(
+        &self,
+        blob: &OciBlob,
+        challenge: &str,
+        credential: Option<(String, String)>,
+    ) -> Result<Option<String>> {
+        let (scheme, params) = parse_challenge(challenge);
+        if scheme.eq_ignore_ascii_case("basic") {
+            return Ok(credential.map(|(user, password)| basic_authorization(&user, &password)));
+        }
+        if !scheme.eq_ignore_ascii_case("bearer") {
+            log::warn!("oci registry:{} not support auth:{}", blob.registry, scheme);
+            return Ok(None);
+        }
+        let Some(realm) = params.get("realm") else {
+            return Ok(None);
+        };
+        let mut token_url = Url::parse(realm)
+            .map_err(|_| DownloadError::InvalidUrl(format!("token realm:{}", realm)))?;
+        {
+            let mut query = token_url.query_pairs_mut();
+            if let Some(service) = params.get("service") {
+                query.append_pair("service", service);
+            }
+            match params.get("scope") {
+                Some(scope) => query.append_pair("scope", scope),
+                None => query.append_pair("scope", &format!("repository:{}:pull", blob.repository)),
+            };
+        }
+        let mut request = self.client.get(token_url.clone());
+        if let Some((user, password)) = &credential {
+            if is_trusted_realm(&token_url, &blob.url) {
+                request = request.basic_auth(user, Some(password));
+            } else {
+                log::warn!(
+                    "oci registry:{} token realm:{} is not https,get token without credential",
+                    blob.registry,
+                    token_url
+                );
+            }
+        }
+        let response = request.send().await?;
+        if !response.status().is_success() {
+            return Err(DownloadError::http_status(
+                &token_url,
+                response.status(),
+                response.headers(),
+                None,
+            ));
+        }
+        let json = serde_json::from_slice::<serde_json::Value>(&response.bytes().await?)
+            .map_err(std::io::Error::from)?;
+        let token = json
+            .get("token")
+            .or_else(|| json.get("access_token"))
+            .and_then(|token| token.as_str())
+            .ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("token response of {} has no token", token_url),
+                )
+            })?;
+        Ok(Some(format!("Bearer {}", token)))
+    }
+}
+
+/// parse www-authenticate,like 'Bearer realm="..",service="..",scope=".."'
+#[inline]
+fn parse_challenge(challenge: &str) -> (String, HashMap<String, String>) {
+    let challenge = challenge.trim();
+    let (scheme, mut rest) = challenge.split_once(' ').unwrap_or((challenge, ""));
+    let mut params = HashMap::new();
+    while let Some((key, value)) = rest.split_once('=') {
+        let key = key.trim_matches(|x: char| x == ',' || x.is_whitespace());
+        let value = value.trim_start();
+        let (value, next) = match value.strip_prefix('"') {
+            Some(quoted) => {
+                let mut unquote = String::new();
+                let mut chars = quoted.char_indices();
+                let mut end = quoted.len();
+                while let Some((i, x)) = chars.next() {
+                    match x {
+                        '\\' => {
+                            if let Some((_, x)) = chars.next() {
+                                unquote.push(x);
+                            }
+                        }
+                        '"' => {
+                            end = i + 1;
+                            break;
+                        }
+                        x => unquote.push(x),
+                    }
+                }
+                (unquote, &quoted[end..])
+            }
+            None => {
+                let (value, next) = value.split_once(',').unwrap_or((value, ""));
+                (value.trim().to_string(), next)
+            }
+        };
+        params.insert(key.to_ascii_lowercase(), value);
+        rest = next;
+    }
+    (scheme.to_string(), params)
+}
+
+/// credential only send to https realm or realm of registry host
+#[inline]
+fn is_trusted_realm(realm: &Url, registry: &Url) -> bool {
+    realm.scheme() == "https"
+        || (realm.host_str() == registry.host_str()
+            && realm.port_or_known_default() == registry.port_or_known_default())
+}
+
+#[inline]
+fn basic_authorization(user: &str, password: &str) -> String {
+    format!(
+        "Basic {}",
+        base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", user, password))
+    )
+}
+
+/// read registry credential from docker config.json auths
+#[inline]
+fn docker_config_credential(registry: &str) -> Option<(String, String)> {
+    let config = match std::env::var_os("DOCKER_CONFIG") {
+        Some(dir) => PathBuf::from(dir),
+        None => {
+            PathBuf::from(std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE"))?)
+                .join(".docker")
+        }
+    }
+    .join("config.json");
+    read_docker_config(&config, registry)
+}
+
+/// read credential of registry from docker config file
+#[inline]
+fn read_docker_config(config: &Path, registry: &str) -> Option<(String, String)> {
+    let json = serde_json::from_slice::<serde_json::Value>(&std::fs::read(config).ok()?).ok()?;
+    let is_docker_hub = registry == DOCKER_HUB_REGISTRY;
+    json.get("auths")?
+        .as_object()?
+        .iter()
+        .find(|(host, _)| {
+            let host = host
+                .trim_start_matches("https://")
+                .trim_start_matches("http://")
+                .split('/')
+                .next()
+                .unwrap_or_default();
+            host == registry || (is_docker_hub && host == "index.docker.io")
+        })
+        .and_then(|(_, auth)| auth.get("auth")?.as_str())
+        .and_then(|auth| base64::engine::general_purpose::STANDARD.decode(auth).ok())
+        .and_then(|auth| {
+            let auth = String::from_utf8(auth).ok()?;
+            let (user, password) = auth.split_once(':')?;
+            Some((user.to_string(), password.to_string()))
+        })
+}
+
+#[async_trait::async_trait]
+impl ITransport for OciTransport {
+    #[inline]
+    async fn probe(&self, url: &Url) -> Result<RemoteInfo> {
+        let (blob, response) = self.get(url, None, None).await?;
+        let mut info = probe_response(url, response)?;
+        info.file_name = info
+            .file_name
+            .or_else(|| Some(format!("sha256-{}", blob.digest)));
+        info.sha256 = Some(blob.digest);
+        Ok(info)
+    }
+
+    #[inline]
+    async fn open_range(&self, status: &DownloadInner, start: u64, end: u64) -> Result<ByteStream> {
+        let (_, response) = self
+            .get(status.get_url(), Some((start, end)), status.get_validator())
+            .await?;
+        range_response(status, start, end, response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        basic_authorization, is_trusted_realm, parse_challenge, read_docker_config, OciBlob,
+        OciTransport, DOCKER_HUB_REGISTRY,
+    };
+    use crate::error::DownloadError;
+    use crate::piece_hash::HashType;
+    use crate::transport::ITransport;
+    use crate::DownloadFile;
+    use reqwest::Url;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+    /// path and authorization header of request
+    type Requests = Arc<Mutex<Vec<(String, Option<String>)>>>;
+
+    /// registry of test,blob need bearer token of realm,
+    /// token request need basic credential if expect
+    async fn registry(blob: &'static [u8], realm_host: &'static str) -> (u16, Requests) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let requests = Requests::default();
+        let log = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let log = log.clone();
+                tokio::spawn(async move {
+                    let mut stream = BufReader::new(stream);
+                    let mut path = String::new();
+                    let mut authorization = None;
+                    let mut line = String::new();
+                    while stream.read_line(&mut line).await.unwrap_or_default() > 2 {
+                        if let Some(request) = line.strip_prefix("GET ") {
+                            path = request.split(' ').next().unwrap_or_default().to_string();
+                        } else if let Some((name, value)) = line.split_once(':') {
+                            if name.eq_ignore_ascii_case("authorization") {
+                                authorization = Some(value.trim().to_string());
+                            }
+                        }
+                        line.clear();
+                    }
+                    log.lock()
+                        .unwrap()
+                        .push((path.clone(), authorization.clone()));
+                    let (status, header, body): (_, _, &[u8]) = if path.starts_with("/token") {
+                        ("200 OK", String::new(), br#"{"token":"abc"}"#)
+                    } else if authorization.as_deref() == Some("Bearer abc") {
+                        ("200 OK", String::new(), blob)
+                    } else {
+                        let challenge = format!(
+                            "WWW-Authenticate: Bearer realm=\"http://{}:{}/token\",service=\"test\"\r\n",
+                            realm_host, port
+                        );
+                        ("401 Unauthorized", challenge, b"")
+                    };
+                    let response = format!(
+                        "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n{}\r\n",
+                        status,
+                        body.len(),
+                        header
+                    );
+                    let stream = stream.get_mut();
+                    let _ = stream.write_all(response.as_bytes()).await;
+                    let _ = stream.write_all(body).await;
+                });
+            }
+        });
+        (port, requests)
+    }
+
+    fn blob_url(port: u16, data: &[u8]) -> Url {
+        let digest = HashType::Sha256.digest(data);
+        Url::parse(&format!(
+            "oci://127.0.0.1:{}/team/app@sha256:{}",
+            port, digest
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn parse_www_authenticate() {
+        let (scheme, params) = parse_challenge(
+            r#"Bearer realm="https://auth.example.com/token",service="registry",scope="repository:a/b:pull,push",error=invalid_token"#,
+        );
+        assert_eq!(scheme, "Bearer");
+        assert_eq!(params["realm"], "https://auth.example.com/token");
+        assert_eq!(params["service"], "registry");
+        assert_eq!(params["scope"], "repository:a/b:pull,push");
+        assert_eq!(params["error"], "invalid_token");
+        let (scheme, params) = parse_challenge(r#"Basic Realm="say \"hi\"""#);
+        assert_eq!(scheme, "Basic");
+        assert_eq!(params["realm"], "say \"hi\"");
+    }
+
+    #[test]
+    fn parse_blob_url() {
+        let digest = "A".repeat(64);
+        let url = Url::parse(&format!("oci://docker.io/alpine@sha256:{}", digest)).unwrap();
+        let blob = OciBlob::parse(&url, false).unwrap();
+        assert_eq!(blob.registry, DOCKER_HUB_REGISTRY);
+        assert_eq!(blob.repository, "library/alpine");
+        assert_eq!(blob.digest, "a".repeat(64));
+        assert_eq!(
+            blob.url.as_str(),
+            format!(
+                "https://registry-1.docker.io/v2/library/alpine/blobs/sha256:{}",
+                "a".repeat(64)
+            )
+        );
+        let url = Url::parse(&format!("oci://ghcr.io:5000/a/b/c@sha256:{}", digest)).unwrap();
+        let blob = OciBlob::parse(&url, true).unwrap();
+        assert_eq!(blob.registry, "ghcr.io:5000");
+        assert_eq!(blob.repository, "a/b/c");
+        assert!(blob
+            .url
+            .as_str()
+            .starts_with("http://ghcr.io:5000/v2/a/b/c/"));
+        let url = Url::parse(&format!("oci://localhost/a@sha256:{}", digest)).unwrap();
+        assert_eq!(OciBlob::parse(&url, false).unwrap().url.scheme(), "http");
+        for invalid in [
+            "oci://ghcr.io/a".to_string(),
+            "oci://ghcr.io/a@sha512:00".to_string(),
+            format!("oci://ghcr.io/a@sha256:{}", "g".repeat(64)),
+            format!("oci://ghcr.io/a@sha256:{}", "a".repeat(63)),
+            format!("oci://ghcr.io/@sha256:{}", "a".repeat(64)),
+        ] {
+            let url = Url::parse(&invalid).unwrap();
+            assert!(matches!(
+                OciBlob::parse(&url, false),
+                Err(DownloadError::InvalidUrl(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn read_docker_config_auths() {
+        let config = std::env::temp_dir().join(format!("oci-test-{}.json", std::process::id()));
+        let auth = |x: &str| {
+            basic_authorization(x, "p")
+                .trim_start_matches("Basic ")
+                .to_string()
+        };
+        let json = serde_json::json!({
+            "auths": {
+                "https://index.docker.io/v1/": {"auth": auth("hub")},
+                "ghcr.io": {"auth": auth("gh")},
+                "bad.io": {"auth": "not base64"},
+            }
+        });
+        std::fs::write(&config, json.to_string()).unwrap();
+        let user = |registry: &str| read_docker_config(&config, registry).map(|(user, _)| user);
+        assert_eq!(user(DOCKER_HUB_REGISTRY).as_deref(), Some("hub"));
+        assert_eq!(user("ghcr.io").as_deref(), Some("gh"));
+        assert_eq!(read_docker_config(&config, "ghcr.io").unwrap().1, "p");
+        assert_eq!(user("bad.io"), None);
+        assert_eq!(user("quay.io"), None);
+        std::fs::remove_file(&config).unwrap();
+    }
+
+    #[test]
+    fn trust_realm_of_https_or_registry_host() {
+        let registry = Url::parse("http://127.0.0.1:5000/v2/a/blobs/sha256:00").unwrap();
+        let trusted = |realm: &str| is_trusted_realm(&Url::parse(realm).unwrap(), &registry);
+        assert!(trusted("https://auth.example.com/token"));
+        assert!(trusted("http://127.0.0.1:5000/token"));
+        assert!(!trusted("http://127.0.0.1:5001/token"));
+        assert!(!trusted("http://auth.example.com/token"));
+    }
+
+    #[tokio::test]
+    async fn auth_by_token_and_retry() {
+        const BLOB: &[u8] = b"oci blob data";
+        let (port, requests) = registry(BLOB, "127.0.0.1").await;
+        let transport = OciTransport::new().with_credential("user".into(), "pass".into());
+        let url = blob_url(port, BLOB);
+        let info = transport.probe(&url).await.unwrap();
+        assert_eq!(info.size, BLOB.len() as u64);
+        assert_eq!(info.sha256, Some(HashType::Sha256.digest(BLOB)));
+        let requests = requests.lock().unwrap().clone();
+        let blob_path = format!(
+            "/v2/team/app/blobs/sha256:{}",
+            HashType::Sha256.digest(BLOB)
+        );
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[0], (blob_path.clone(), None));
+        assert!(requests[1]
+            .0
+            .starts_with("/token?service=test&scope=repository%3Ateam%2Fapp%3Apull"));
+        assert_eq!(requests[1].1, Some(basic_authorization("user", "pass")));
+        assert_eq!(requests[2], (blob_path, Some("Bearer abc".to_string())));
+    }
+
+    #[tokio::test]
+    async fn not_send_credential_to_other_http_realm() {
+        const BLOB: &[u8] = b"oci blob data";
+        let (port, requests) = registry(BLOB, "localhost").await;
+        let transport = OciTransport::new().with_credential("user".into(), "pass".into());
+        transport.probe(&blob_url(port, BLOB)).await.unwrap();
+        let requests = requests.lock().unwrap().clone();
+        assert!(requests[1].0.starts_with("/token"));
+        assert_eq!(requests[1].1, None);
+    }
+
+    #[tokio::test]
+    async fn fail_of_digest_mismatch() {
+        let (port, _) = registry(b"oci blob data", "127.0.0.1").await;
+        let dir = std::env::temp_dir().join(format!("oci-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let save_path = dir.join("blob");
+        let download = DownloadFile::start_download_with_transport(
+            blob_url(port, b"other data"),
+            save_path.clone(),
+            Arc::new(OciTransport::new()),
+            1,
+            1024,
+        )
+        .await
+        .unwrap();
+        download.wait_finish().await;
+        assert!(matches!(
+            download.get_error(),
+            Some(DownloadError::ChecksumMismatch { .. })
+        ));
+        assert!(!save_path.exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
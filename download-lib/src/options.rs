@@ -0,0 +1,56 @@
+use super::cache::DownloadCache;
+use super::checksum::ChecksumSource;
+use super::extract::ExtractOptions;
+use super::piece_hash::PieceHashes;
+use super::signature::MinisignCheck;
+
+/// download options
+#[derive(Debug, Clone)]
+pub struct DownloadOptions {
+    /// number of concurrent download
+    pub task_count: u64,
+    /// min block size of one task
+    pub block: u64,
+    /// max download size,none is unlimited
+    pub max_size: Option<u64>,
+    /// append sync compare local file tail size with remote
+    pub append_overlap: u64,
+    /// restart download when remote file changed while downloading
+    pub restart_on_change: bool,
+    /// preferred mirror location of metalink,like "de"
+    pub location: Option<String>,
+    /// expected piece hashes,mismatch piece download again
+    pub pieces: Option<PieceHashes>,
+    /// piece length of sha256 piece manifest save to "<file>.pieces" after download,
+    /// next download use it verify pieces if remote file not changed
+    pub piece_manifest: Option<u64>,
+    /// local download cache,use verified cache file instead of download,
+    /// add file to cache after download
+    pub cache: Option<DownloadCache>,
+    /// minisign public key and signature,verify file before rename to save path
+    pub signature: Option<MinisignCheck>,
+    /// find sha256 from checksum manifest,verify file before finish
+    pub checksum: Option<ChecksumSource>,
+    /// extract archive to dir after download
+    pub extract: Option<ExtractOptions>,
+}
+
+impl Default for DownloadOptions {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            task_count: 15,
+            block: 1024 * 1024,
+            max_size: None,
+            append_overlap: 64 * 1024,
+            restart_on_change: false,
+            location: None,
+            pieces: None,
+            piece_manifest: None,
+            cache: None,
+            signature: None,
+            checksum: None,
+            extract: None,
+        }
+    }
+}
@@ -0,0 +1,408 @@
+use super::error::{DownloadError, Result};
+use sha2::digest::DynDigest;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+/// hash algorithm of checksum
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HashType {
+    Md5,
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+impl HashType {
+    /// parse name like "sha-256","SHA256","md5",none is not support
+    #[inline]
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().replace('-', "").as_str() {
+            "md5" => Some(HashType::Md5),
+            "sha1" => Some(HashType::Sha1),
+            "sha256" => Some(HashType::Sha256),
+            "sha512" => Some(HashType::Sha512),
+            _ => None,
+        }
+    }
+
+    /// metalink style name,like "sha-256"
+    #[inline]
+    pub fn name(self) -> &'static str {
+        match self {
+            HashType::Md5 => "md5",
+            HashType::Sha1 => "sha-1",
+            HashType::Sha256 => "sha-256",
+            HashType::Sha512 => "sha-512",
+        }
+    }
+
+    /// new hasher of algorithm
+    #[inline]
+    pub(crate) fn hasher(self) -> Box<dyn DynDigest + Send> {
+        match self {
+            HashType::Md5 => Box::<md5::Md5>::default(),
+            HashType::Sha1 => Box::<sha1::Sha1>::default(),
+            HashType::Sha256 => Box::<sha2::Sha256>::default(),
+            HashType::Sha512 => Box::<sha2::Sha512>::default(),
+        }
+    }
+
+    /// hex hash of data
+    #[inline]
+    pub fn digest(self, data: &[u8]) -> String {
+        let mut hasher = self.hasher();
+        hasher.update(data);
+        hex::encode(hasher.finalize())
+    }
+}
+
+/// hex hashes of fixed size pieces,last piece may be shorter
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PieceHashes {
+    /// piece size
+    pub length: u64,
+    pub hash_type: HashType,
+    /// hex hash of every piece in order
+    pub hashes: Vec<String>,
+}
+
+/// byte range of piece,end is included,none if piece is out of size
+#[inline]
+fn piece_range(length: u64, index: usize, size: u64) -> Option<(u64, u64)> {
+    let start = (index as u64).checked_mul(length)?;
+    (length > 0 && start < size).then(|| (start, start.saturating_add(length).min(size) - 1))
+}
+
+impl PieceHashes {
+    /// byte range of piece,end is included,none if piece is out of size
+    #[inline]
+    pub fn piece_range(&self, index: usize, size: u64) -> Option<(u64, u64)> {
+        piece_range(self.length, index, size)
+    }
+
+    /// piece count same as file size need
+    #[inline]
+    pub fn is_match_size(&self, size: u64) -> bool {
+        self.length > 0 && self.hashes.len() as u64 == size.div_ceil(self.length)
+    }
+}
+
+/// hash state of piece
+enum PieceState {
+    /// write in order from piece start,next is offset of next write
+    Hashing {
+        next: u64,
+        hasher: Box<dyn DynDigest + Send>,
+    },
+    Done(String),
+    /// write out of order,need read back to hash
+    Unknown,
+}
+
+/// hash every piece while data writing,
+/// piece write in order from start is hashed without read back
+pub(crate) struct PieceTracker {
+    length: u64,
+    hash_type: HashType,
+    size: u64,
+    pieces: Vec<PieceState>,
+}
+
+impl PieceTracker {
+    #[inline]
+    pub fn new(length: u64, hash_type: HashType, size: u64) -> Self {
+        let length = length.max(1);
+        Self {
+            length,
+            hash_type,
+            size,
+            pieces: (0..size.div_ceil(length))
+                .map(|_| PieceState::Unknown)
+                .collect(),
+        }
+    }
+
+    /// data size
+    #[inline]
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// piece size
+    #[inline]
+    pub fn length(&self) -> u64 {
+        self.length
+    }
+
+    #[inline]
+    pub fn hash_type(&self) -> HashType {
+        self.hash_type
+    }
+
+    /// update piece hash by write data,write at piece start begin hash it again
+    #[inline]
+    pub fn update(&mut self, mut offset: u64, mut data: &[u8]) {
+        while !data.is_empty() && offset < self.size {
+            let index = (offset / self.length) as usize;
+            let Some(state) = self.pieces.get_mut(index) else {
+                break;
+            };
+            let piece_start = index as u64 * self.length;
+            let piece_end = (piece_start + self.length).min(self.size);
+            let len = (data.len() as u64).min(piece_end - offset) as usize;
+            if offset == piece_start {
+                *state = PieceState::Hashing {
+                    next: offset,
+                    hasher: self.hash_type.hasher(),
+                };
+            }
+            match state {
+                PieceState::Hashing { next, hasher } if *next == offset => {
+                    hasher.update(&data[..len]);
+                    *next += len as u64;
+                    if *next == piece_end {
+                        *state = PieceState::Done(hex::encode(hasher.finalize_reset()));
+                    }
+                }
+                _ => *state = PieceState::Unknown,
+            }
+            offset += len as u64;
+            data = &data[len..];
+        }
+    }
+
+    /// hashes of all pieces,piece not hashed while writing is hash by read
+    #[inline]
+    pub fn hashes<F>(&self, mut read_hash: F) -> std::io::Result<PieceHashes>
+    where
+        F: FnMut(u64, u64) -> std::io::Result<String>,
+    {
+        let mut hashes = Vec::with_capacity(self.pieces.len());
+        for (index, state) in self.pieces.iter().enumerate() {
+            match state {
+                PieceState::Done(hash) => hashes.push(hash.clone()),
+                _ => {
+                    let (start, end) =
+                        piece_range(self.length, index, self.size).ok_or_else(|| {
+                            std::io::Error::new(
+                                ErrorKind::InvalidInput,
+                                format!("piece:{} out of size:{}", index, self.size),
+                            )
+                        })?;
+                    hashes.push(read_hash(start, end)?);
+                }
+            }
+        }
+        Ok(PieceHashes {
+            length: self.length,
+            hash_type: self.hash_type,
+            hashes,
+        })
+    }
+
+    /// hash of whole piece range if it is hashed while writing
+    #[inline]
+    pub fn hash(&self, hash_type: HashType, start: u64, end: u64) -> Option<String> {
+        if hash_type != self.hash_type || !start.is_multiple_of(self.length) {
+            return None;
+        }
+        let index = (start / self.length) as usize;
+        let piece_end = (start + self.length).min(self.size);
+        match self.pieces.get(index) {
+            Some(PieceState::Done(hash)) if end + 1 == piece_end => Some(hash.clone()),
+            _ => None,
+        }
+    }
+}
+
+/// piece hashes of downloaded file,save to "<file>.pieces" after download,
+/// next download of same remote file verify pieces by it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PieceManifest {
+    /// remote file size
+    pub size: u64,
+    /// remote etag or last modified
+    pub validator: Option<String>,
+    pub pieces: PieceHashes,
+}
+
+impl PieceManifest {
+    /// manifest path of file
+    #[inline]
+    pub fn path(file: &Path) -> PathBuf {
+        let mut path = file.as_os_str().to_owned();
+        path.push(".pieces");
+        PathBuf::from(path)
+    }
+
+    /// load manifest json
+    #[inline]
+    pub fn load(path: &Path) -> Result<Self> {
+        let invalid = || {
+            DownloadError::from(std::io::Error::new(
+                ErrorKind::InvalidData,
+                format!("invalid piece manifest:{:?}", path),
+            ))
+        };
+        let json = serde_json::from_slice::<serde_json::Value>(&std::fs::read(path)?)
+            .map_err(std::io::Error::from)?;
+        let hashes = json["hashes"]
+            .as_array()
+            .ok_or_else(invalid)?
+            .iter()
+            .map(|hash| hash.as_str().map(|x| x.to_ascii_lowercase()))
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(invalid)?;
+        Ok(Self {
+            size: json["size"].as_u64().ok_or_else(invalid)?,
+            validator: json["validator"].as_str().map(|x| x.to_string()),
+            pieces: PieceHashes {
+                length: json["length"]
+                    .as_u64()
+                    .filter(|length| *length > 0)
+                    .ok_or_else(invalid)?,
+                hash_type: json["type"]
+                    .as_str()
+                    .and_then(HashType::parse)
+                    .ok_or_else(invalid)?,
+                hashes,
+            },
+        })
+    }
+
+    /// save manifest json
+    #[inline]
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let json = serde_json::json!({
+            "size": self.size,
+            "validator": self.validator,
+            "length": self.pieces.length,
+            "type": self.pieces.hash_type.name(),
+            "hashes": self.pieces.hashes,
+        });
+        std::fs::write(path, json.to_string())?;
+        Ok(())
+    }
+
+    /// load manifest of file saved by previous download,
+    /// none if not exists or remote file changed
+    #[inline]
+    pub fn load_previous(file: &Path, size: u64, validator: Option<&str>) -> Option<PieceHashes> {
+        let manifest = Self::load(&Self::path(file)).ok()?;
+        (validator.is_some()
+            && manifest.validator.as_deref() == validator
+            && manifest.size == size
+            && manifest.pieces.is_match_size(size))
+        .then_some(manifest.pieces)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HashType, PieceHashes, PieceManifest, PieceTracker};
+
+    const DATA: &[u8] = b"0123456789abcdefghij";
+
+    /// hash of piece read back from data
+    fn read_hash(start: u64, end: u64) -> std::io::Result<String> {
+        Ok(HashType::Sha256.digest(&DATA[start as usize..=end as usize]))
+    }
+
+    fn expected_hashes() -> Vec<String> {
+        DATA.chunks(8).map(|x| HashType::Sha256.digest(x)).collect()
+    }
+
+    #[test]
+    fn piece_range_out_of_size() {
+        let pieces = PieceHashes {
+            length: 8,
+            hash_type: HashType::Sha256,
+            hashes: vec![],
+        };
+        assert_eq!(pieces.piece_range(0, 20), Some((0, 7)));
+        assert_eq!(pieces.piece_range(2, 20), Some((16, 19)));
+        assert_eq!(pieces.piece_range(3, 20), None);
+        assert_eq!(pieces.piece_range(0, 0), None);
+        assert_eq!(pieces.piece_range(usize::MAX, u64::MAX), None);
+    }
+
+    #[test]
+    fn hash_in_order_write() {
+        let mut tracker = PieceTracker::new(8, HashType::Sha256, DATA.len() as u64);
+        for (index, chunk) in DATA.chunks(3).enumerate() {
+            tracker.update(index as u64 * 3, chunk);
+        }
+        let hashes = tracker
+            .hashes(|_, _| panic!("in order write not read back"))
+            .unwrap();
+        assert_eq!(hashes.hashes, expected_hashes());
+        // short last piece
+        assert_eq!(
+            tracker.hash(HashType::Sha256, 16, 19),
+            Some(HashType::Sha256.digest(&DATA[16..]))
+        );
+        assert_eq!(tracker.hash(HashType::Sha256, 16, 18), None);
+        assert_eq!(tracker.hash(HashType::Sha256, 4, 11), None);
+        assert_eq!(tracker.hash(HashType::Md5, 0, 7), None);
+    }
+
+    #[test]
+    fn hash_out_of_order_write_by_read() {
+        let mut tracker = PieceTracker::new(8, HashType::Sha256, DATA.len() as u64);
+        tracker.update(4, &DATA[4..8]);
+        tracker.update(0, &DATA[..4]);
+        tracker.update(10, &DATA[10..]);
+        tracker.update(8, &DATA[8..10]);
+        assert_eq!(tracker.hash(HashType::Sha256, 0, 7), None);
+        assert_eq!(tracker.hash(HashType::Sha256, 8, 15), None);
+        assert!(tracker.hash(HashType::Sha256, 16, 19).is_some());
+        let mut reads = vec![];
+        let hashes = tracker
+            .hashes(|start, end| {
+                reads.push((start, end));
+                read_hash(start, end)
+            })
+            .unwrap();
+        assert_eq!(reads, [(0, 7), (8, 15)]);
+        assert_eq!(hashes.hashes, expected_hashes());
+        // write beyond size is ignored
+        tracker.update(18, b"xyz");
+    }
+
+    #[test]
+    fn rewrite_at_piece_start_hash_again() {
+        let mut tracker = PieceTracker::new(8, HashType::Sha256, DATA.len() as u64);
+        tracker.update(0, b"xxxxxxxx");
+        tracker.update(0, &DATA[..5]);
+        assert_eq!(tracker.hash(HashType::Sha256, 0, 7), None);
+        tracker.update(5, &DATA[5..8]);
+        assert_eq!(
+            tracker.hash(HashType::Sha256, 0, 7),
+            Some(HashType::Sha256.digest(&DATA[..8]))
+        );
+    }
+
+    #[test]
+    fn load_previous_of_same_validator() {
+        let file =
+            std::env::temp_dir().join(format!("piece-hash-test-{}-manifest", std::process::id()));
+        let size = DATA.len() as u64;
+        PieceManifest {
+            size,
+            validator: Some("\"v1\"".to_string()),
+            pieces: PieceHashes {
+                length: 8,
+                hash_type: HashType::Sha256,
+                hashes: expected_hashes(),
+            },
+        }
+        .save(&PieceManifest::path(&file))
+        .unwrap();
+        let pieces = PieceManifest::load_previous(&file, size, Some("\"v1\"")).unwrap();
+        assert_eq!(pieces.hashes, expected_hashes());
+        assert!(PieceManifest::load_previous(&file, size, Some("\"v2\"")).is_none());
+        assert!(PieceManifest::load_previous(&file, size, None).is_none());
+        assert!(PieceManifest::load_previous(&file, size + 1, Some("\"v1\"")).is_none());
+        std::fs::remove_file(PieceManifest::path(&file)).unwrap();
+    }
+}
@@ -0,0 +1,223 @@
+use super::error::{DownloadError, Result};
+use super::file_save::{IFileSave, SegmentWriter};
+use super::memory_save::MemorySave;
+use super::transport::ByteStream;
+use super::DownloadInner;
+use bytes::{Bytes, BytesMut};
+use futures_util::StreamExt;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::{sleep, timeout};
+
+/// download range data to memory,end is included
+#[inline]
+pub(crate) async fn fetch_range(
+    inner_status: Arc<DownloadInner>,
+    start: u64,
+    end: u64,
+) -> Result<Bytes> {
+    let save_file = Arc::new(MemorySave::new());
+    save_file.init(end - start + 1).await?;
+    RangeFile::new(save_file.clone(), inner_status, start, end)
+        .save_at(0)
+        .run()
+        .await?;
+    save_file.finish().await?;
+    Ok(save_file.take_bytes().unwrap_or_default())
+}
+
+/// download data of 0..=end to memory from probe opened whole file stream,
+/// rest data download by range request if stream end early
+#[inline]
+pub(crate) async fn fetch_head_from_body(
+    inner_status: Arc<DownloadInner>,
+    end: u64,
+    mut body: ByteStream,
+) -> Result<Bytes> {
+    let len = end as usize + 1;
+    let mut data = BytesMut::with_capacity(len);
+    while data.len() < len {
+        match timeout(Duration::from_secs(10), body.next()).await {
+            Ok(Some(Ok(buf))) => {
+                let take = buf.len().min(len - data.len());
+                data.extend_from_slice(&buf[..take]);
+                inner_status.add_down_size(take as u64);
+            }
+            Ok(Some(Err(err))) => {
+                log::warn!("read url:{} probe body error:{}", inner_status.url, err);
+                break;
+            }
+            Ok(None) | Err(_) => break,
+        }
+    }
+    drop(body);
+    if data.len() < len {
+        let rest = fetch_range(inner_status, data.len() as u64, end).await?;
+        data.extend_from_slice(&rest);
+    }
+    Ok(data.freeze())
+}
+
+/// download range of remote file by transport
+pub(crate) struct RangeFile<S> {
+    writer: SegmentWriter<S>,
+    inner_status: Arc<DownloadInner>,
+    start: u64,
+    end: u64,
+    current: u64,
+    save_start: u64,
+    last_error: Option<DownloadError>,
+}
+
+impl<S: IFileSave + 'static> RangeFile<S> {
+    pub fn new(save_file: Arc<S>, inner_status: Arc<DownloadInner>, start: u64, end: u64) -> Self {
+        Self {
+            writer: SegmentWriter::new(save_file),
+            inner_status,
+            start,
+            end,
+            current: start,
+            save_start: start,
+            last_error: None,
+        }
+    }
+
+    /// set save position of start byte,default is same as start
+    #[inline]
+    pub fn save_at(mut self, save_start: u64) -> Self {
+        self.save_start = save_start;
+        self
+    }
+
+    #[inline]
+    pub async fn run(&mut self) -> Result<()> {
+        while !self.inner_status.is_finish() && self.current <= self.end {
+            if self.inner_status.is_cancel() {
+                return Err(DownloadError::Cancelled);
+            }
+            if !self.inner_status.is_start.load(Ordering::Acquire) {
+                sleep(Duration::from_secs(1)).await
+            } else {
+                're: for i in (0..10).rev() {
+                    let open = self.inner_status.transport.open_range(
+                        &self.inner_status,
+                        self.current,
+                        self.end,
+                    );
+                    match timeout(Duration::from_secs(15), open).await {
+                        Ok(Ok(stream)) => {
+                            log::trace!(
+                                "start download url block:{} start:{} end:{}",
+                                self.inner_status.url,
+                                self.current,
+                                self.end
+                            );
+                            if self.read_stream(stream).await? {
+                                break 're;
+                            } else if self.current > self.end {
+                                // all data read but transfer report error after it
+                                if let Some(err) = self.last_error.take() {
+                                    return Err(err);
+                                }
+                                break 're;
+                            } else if i == 0 {
+                                if let Some(err) = self.last_error.take() {
+                                    return Err(err);
+                                }
+                            }
+                        }
+                        Ok(Err(err)) => {
+                            if i > 0 && err.is_retryable() {
+                                log::error!(
+                                    "download url:{} error:{err} retry:{i}",
+                                    self.inner_status.url
+                                );
+                            } else {
+                                return Err(err);
+                            }
+                        }
+                        Err(_) => {
+                            if i > 0 {
+                                log::warn!(
+                                    "get url:{} response time out retry:{i}",
+                                    self.inner_status.url
+                                );
+                            } else {
+                                return Err(DownloadError::ConnectTimeout(
+                                    self.inner_status.url.clone(),
+                                ));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// read probe opened stream first,continue by range request if not finish
+    #[inline]
+    pub async fn run_once(&mut self, stream: ByteStream) -> Result<()> {
+        if !self.read_stream(stream).await? {
+            self.run().await
+        } else {
+            Ok(())
+        }
+    }
+
+    #[inline]
+    async fn read_stream(&mut self, mut stream: ByteStream) -> Result<bool> {
+        let is_finish = loop {
+            match timeout(Duration::from_secs(10), stream.next()).await {
+                Ok(Some(Ok(buf))) => {
+                    let len = buf.len() as u64;
+                    if self.current + len > self.end + 1 {
+                        return Err(DownloadError::SizeMismatch {
+                            expected: self.end + 1 - self.start,
+                            actual: self.current + len - self.start,
+                        });
+                    }
+                    self.writer
+                        .write(&buf, self.current - self.start + self.save_start)
+                        .await?;
+                    self.current += len;
+                    self.inner_status.add_down_size(len);
+                    if self.inner_status.is_cancel() {
+                        return Err(DownloadError::Cancelled);
+                    }
+                    if !self.inner_status.is_start() {
+                        log::debug!("is suspend");
+                        break false;
+                    }
+                }
+                Ok(Some(Err(err))) => {
+                    log::error!(
+                        "download url:{} buff is error:{}",
+                        self.inner_status.url,
+                        err
+                    );
+                    self.last_error = Some(err);
+                    break false;
+                }
+                Ok(None) => {
+                    log::trace!(
+                        "download url:{} block:{}-{} response close",
+                        self.inner_status.url,
+                        self.start,
+                        self.end
+                    );
+                    break true;
+                }
+                Err(_) => {
+                    log::warn!("download url:{} time out", self.inner_status.url);
+                    self.last_error =
+                        Some(DownloadError::ReadTimeout(self.inner_status.url.clone()));
+                    break false;
+                }
+            }
+        };
+        self.writer.flush().await?;
+        Ok(is_finish)
+    }
+}
@@ -0,0 +1,334 @@
+use super::error::Result;
+use super::range_file::fetch_range;
+use super::transport::{transport_for_url, ITransport, IntoDownloadUrl};
+use super::DownloadInner;
+use bytes::Bytes;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::io::SeekFrom;
+use std::pin::Pin;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncSeek, ReadBuf};
+use tokio::task::JoinHandle;
+
+/// random access remote file,read by http range request,
+/// read block cache in memory and read ahead next blocks
+pub struct RemoteFile {
+    inner_status: Arc<DownloadInner>,
+    block: u64,
+    read_ahead: u64,
+    cache_blocks: usize,
+    cache: HashMap<u64, Bytes>,
+    cache_order: VecDeque<u64>,
+    fetching: HashMap<u64, JoinHandle<Result<Bytes>>>,
+    position: u64,
+}
+
+impl RemoteFile {
+    /// open remote file,block is range request size,
+    /// read_ahead is prefetch block count,cache_blocks is max cache block count
+    #[inline]
+    pub async fn open<U: IntoDownloadUrl>(
+        url: U,
+        block: u64,
+        read_ahead: u64,
+        cache_blocks: usize,
+    ) -> Result<Self> {
+        let url = url.into_download_url()?;
+        let transport = transport_for_url(&url);
+        Self::open_with_transport(url, transport, block, read_ahead, cache_blocks).await
+    }
+
+    /// open remote file,remote file read by custom transport
+    #[inline]
+    pub async fn open_with_transport<U: IntoDownloadUrl>(
+        url: U,
+        transport: Arc<dyn ITransport>,
+        block: u64,
+        read_ahead: u64,
+        cache_blocks: usize,
+    ) -> Result<Self> {
+        let url = url.into_download_url()?;
+        // only need size and validator,not open whole file body
+        let info = transport.probe_head(&url).await?;
+        let inner_status = Arc::new(DownloadInner::new(url, transport, info.size, info.size));
+        inner_status.set_validator(info.validator);
+        inner_status.is_start.store(true, Ordering::Release);
+        Ok(Self {
+            inner_status,
+            block: block.max(1),
+            read_ahead,
+            cache_blocks: cache_blocks.max(read_ahead as usize + 1),
+            cache: HashMap::new(),
+            cache_order: VecDeque::new(),
+            fetching: HashMap::new(),
+            position: 0,
+        })
+    }
+
+    /// get url
+    #[inline]
+    pub fn url(&self) -> &str {
+        self.inner_status.url()
+    }
+
+    /// file size
+    #[inline]
+    pub fn size(&self) -> u64 {
+        self.inner_status.get_size()
+    }
+
+    /// current read position
+    #[inline]
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// get status arc
+    #[inline]
+    pub fn get_status(&self) -> Arc<DownloadInner> {
+        self.inner_status.clone()
+    }
+
+    /// start fetch block if not cache and not fetching
+    #[inline]
+    fn fetch_block(&mut self, index: u64) {
+        let start = index * self.block;
+        if start >= self.size() || self.cache.contains_key(&index) {
+            return;
+        }
+        if let Entry::Vacant(entry) = self.fetching.entry(index) {
+            let end = (start + self.block).min(self.inner_status.get_size()) - 1;
+            log::trace!(
+                "remote file:{} fetch block:{}",
+                self.inner_status.url,
+                index
+            );
+            entry.insert(tokio::spawn(fetch_range(
+                self.inner_status.clone(),
+                start,
+                end,
+            )));
+        }
+    }
+
+    /// put block to cache,remove oldest block if cache full
+    #[inline]
+    fn cache_block(&mut self, index: u64, data: Bytes) {
+        while self.cache_order.len() >= self.cache_blocks {
+            if let Some(old) = self.cache_order.pop_front() {
+                self.cache.remove(&old);
+            }
+        }
+        self.cache.insert(index, data);
+        self.cache_order.push_back(index);
+    }
+
+    /// mark block recently used
+    #[inline]
+    fn touch_block(&mut self, index: u64) {
+        if self.cache_order.back() != Some(&index) {
+            if let Some(i) = self.cache_order.iter().position(|x| *x == index) {
+                self.cache_order.remove(i);
+                self.cache_order.push_back(index);
+            }
+        }
+    }
+}
+
+impl AsyncRead for RemoteFile {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = &mut *self;
+        if this.position >= this.size() || buf.remaining() == 0 {
+            return Poll::Ready(Ok(()));
+        }
+        let index = this.position / this.block;
+        if !this.cache.contains_key(&index) {
+            // seek to other place,drop fetching out of read window
+            let read_ahead = this.read_ahead;
+            this.fetching.retain(|i, task| {
+                let keep = *i >= index && *i <= index + read_ahead;
+                if !keep {
+                    task.abort();
+                }
+                keep
+            });
+            this.fetch_block(index);
+            for next in index + 1..=index + this.read_ahead {
+                this.fetch_block(next);
+            }
+            let fetching = this
+                .fetching
+                .get_mut(&index)
+                .expect("block fetching must exist");
+            let data = match Pin::new(fetching).poll(cx) {
+                Poll::Ready(Ok(Ok(data))) => data,
+                Poll::Ready(Ok(Err(err))) => {
+                    this.fetching.remove(&index);
+                    return Poll::Ready(Err(std::io::Error::other(err)));
+                }
+                Poll::Ready(Err(err)) => {
+                    this.fetching.remove(&index);
+                    return Poll::Ready(Err(std::io::Error::other(err)));
+                }
+                Poll::Pending => return Poll::Pending,
+            };
+            this.fetching.remove(&index);
+            this.cache_block(index, data);
+        } else {
+            this.touch_block(index);
+        }
+
+        let data = &this.cache[&index];
+        let offset = (this.position - index * this.block) as usize;
+        let len = (data.len() - offset).min(buf.remaining());
+        buf.put_slice(&data[offset..offset + len]);
+        this.position += len as u64;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncSeek for RemoteFile {
+    fn start_seek(mut self: Pin<&mut Self>, position: SeekFrom) -> std::io::Result<()> {
+        let position = match position {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.size().checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        self.position = position.ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+        Ok(())
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<u64>> {
+        Poll::Ready(Ok(self.position))
+    }
+}
+
+impl Drop for RemoteFile {
+    #[inline]
+    fn drop(&mut self) {
+        for task in self.fetching.values() {
+            task.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RemoteFile;
+    use crate::transport::tests::MemoryTransport;
+    use std::io::SeekFrom;
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+    fn data(size: usize) -> Vec<u8> {
+        (0..size).map(|i| (i % 251) as u8).collect()
+    }
+
+    async fn open(
+        transport: &Arc<MemoryTransport>,
+        read_ahead: u64,
+        cache_blocks: usize,
+    ) -> RemoteFile {
+        RemoteFile::open_with_transport(
+            "http://127.0.0.1/file.bin",
+            transport.clone(),
+            10,
+            read_ahead,
+            cache_blocks,
+        )
+        .await
+        .unwrap()
+    }
+
+    /// read len bytes at position
+    async fn read_at(file: &mut RemoteFile, position: u64, len: usize) -> Vec<u8> {
+        file.seek(SeekFrom::Start(position)).await.unwrap();
+        let mut buf = vec![0; len];
+        file.read_exact(&mut buf).await.unwrap();
+        buf
+    }
+
+    fn requests(transport: &MemoryTransport) -> Vec<(u64, u64)> {
+        transport.requests.lock().unwrap().clone()
+    }
+
+    #[tokio::test]
+    async fn open_by_head() {
+        let transport = Arc::new(MemoryTransport::new(data(95)));
+        let file = open(&transport, 0, 1).await;
+        assert_eq!(file.size(), 95);
+        assert_eq!(transport.head_count.load(Ordering::Acquire), 1);
+        assert_eq!(transport.probe_count.load(Ordering::Acquire), 0);
+        assert!(requests(&transport).is_empty());
+    }
+
+    #[tokio::test]
+    async fn read_across_blocks() {
+        let transport = Arc::new(MemoryTransport::new(data(95)));
+        let mut file = open(&transport, 0, 4).await;
+        assert_eq!(read_at(&mut file, 8, 15).await, data(95)[8..23]);
+        assert_eq!(file.position(), 23);
+        assert_eq!(requests(&transport), [(0, 9), (10, 19), (20, 29)]);
+        // short last block,read to end of file
+        let mut rest = vec![];
+        file.seek(SeekFrom::End(-7)).await.unwrap();
+        file.read_to_end(&mut rest).await.unwrap();
+        assert_eq!(rest, data(95)[88..]);
+        assert_eq!(requests(&transport).last(), Some(&(90, 94)));
+        assert!(file.seek(SeekFrom::Current(-100)).await.is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn read_ahead_blocks() {
+        let transport = Arc::new(MemoryTransport::new(data(95)));
+        let mut file = open(&transport, 2, 4).await;
+        assert_eq!(read_at(&mut file, 0, 5).await, data(95)[..5]);
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        let mut fetched = requests(&transport);
+        fetched.sort();
+        assert_eq!(fetched, [(0, 9), (10, 19), (20, 29)]);
+        // read ahead block is used,not request again
+        assert_eq!(read_at(&mut file, 10, 20).await, data(95)[10..30]);
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        let fetched = requests(&transport);
+        assert_eq!(
+            fetched.iter().filter(|range| **range == (10, 19)).count(),
+            1
+        );
+        assert_eq!(
+            fetched.iter().filter(|range| **range == (20, 29)).count(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn evict_least_recently_used_block() {
+        let transport = Arc::new(MemoryTransport::new(data(95)));
+        let mut file = open(&transport, 0, 2).await;
+        read_at(&mut file, 10, 1).await;
+        read_at(&mut file, 20, 1).await;
+        // touch block 1,block 2 is least recently used
+        read_at(&mut file, 10, 1).await;
+        read_at(&mut file, 30, 1).await;
+        assert_eq!(requests(&transport), [(10, 19), (20, 29), (30, 39)]);
+        assert_eq!(read_at(&mut file, 15, 1).await, [data(95)[15]]);
+        assert_eq!(requests(&transport).len(), 3);
+        assert_eq!(read_at(&mut file, 25, 1).await, [data(95)[25]]);
+        assert_eq!(requests(&transport).last(), Some(&(20, 29)));
+    }
+}
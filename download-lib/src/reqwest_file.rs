@@ -1,153 +1,277 @@
 use super::error::{DownloadError, Result};
-use super::file_save::{FileSave, IFileSave};
+use super::http_cache::CachePolicy;
+use super::transport::{ByteStream, ITransport, RemoteInfo, Revalidation};
 use super::DownloadInner;
-use crate::StatusCode;
-use aqueue::Actor;
-use futures_util::StreamExt;
-use reqwest::Response;
-use std::sync::atomic::Ordering;
-use std::sync::Arc;
-use std::time::Duration;
-use tokio::time::{sleep, timeout};
-
-/// http download file
-pub(crate) struct ReqwestFile {
-    save_file: Arc<Actor<FileSave>>,
-    inner_status: Arc<DownloadInner>,
-    start: u64,
-    end: u64,
-    current: u64,
+use futures_util::TryStreamExt;
+use reqwest::header::HeaderMap;
+use reqwest::StatusCode;
+use reqwest::{Client, Response, Url};
+
+/// get strong etag or last modified for if-range
+#[inline]
+pub(crate) fn parse_validator(headers: &HeaderMap) -> Option<String> {
+    let etag = headers
+        .get(reqwest::header::ETAG)
+        .and_then(|etag| etag.to_str().ok())
+        .filter(|etag| etag.starts_with('"'));
+    etag.or_else(|| {
+        headers
+            .get(reqwest::header::LAST_MODIFIED)
+            .and_then(|last_modified| last_modified.to_str().ok())
+    })
+    .map(|validator| validator.to_string())
 }
 
-impl ReqwestFile {
-    pub fn new(
-        save_file: Arc<Actor<FileSave>>,
-        inner_status: Arc<DownloadInner>,
-        start: u64,
-        end: u64,
-    ) -> Self {
-        Self {
-            save_file,
-            inner_status,
-            start,
-            end,
-            current: start,
-        }
+/// get total size from content-range,like "bytes 0-99/1234"
+#[inline]
+fn parse_content_range_total(headers: &HeaderMap) -> Option<u64> {
+    headers
+        .get(reqwest::header::CONTENT_RANGE)?
+        .to_str()
+        .ok()?
+        .rsplit('/')
+        .next()?
+        .trim()
+        .parse::<u64>()
+        .ok()
+}
+
+#[inline]
+fn parse_content_length(headers: &HeaderMap) -> Option<u64> {
+    headers
+        .get(reqwest::header::CONTENT_LENGTH)?
+        .to_str()
+        .ok()?
+        .parse::<u64>()
+        .ok()
+}
+
+#[inline]
+fn parse_content_filename(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(reqwest::header::CONTENT_DISPOSITION)?
+        .to_str()
+        .ok()?
+        .trim()
+        .split(';')
+        .find_map(|content| {
+            let content = content.trim();
+            if content.find("filename") == Some(0) {
+                content.split('=').next_back()
+            } else {
+                None
+            }
+        })
+        .map(|x| x.to_string())
+}
+
+/// response body to data stream
+#[inline]
+fn body_stream(response: Response) -> ByteStream {
+    Box::pin(response.bytes_stream().map_err(DownloadError::from))
+}
+
+/// http transport by reqwest,it is default transport
+#[derive(Default, Clone)]
+pub struct ReqwestTransport {
+    client: Client,
+}
+
+impl ReqwestTransport {
+    /// create transport use default client
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
     }
 
+    /// create transport use custom client,like proxy or default headers
     #[inline]
-    pub async fn run(&mut self) -> Result<()> {
-        while !self.inner_status.is_finish() && self.current < self.end {
-            if !self.inner_status.is_start.load(Ordering::Acquire) {
-                sleep(Duration::from_secs(1)).await
-            } else {
-                're: for i in (0..10).rev() {
-                    let request_data = {
-                        reqwest::Client::new()
-                            .get(self.inner_status.url.as_str())
-                            .header(
-                                reqwest::header::RANGE,
-                                format!("bytes={}-{}", self.current, self.end),
-                            )
-                            .send()
-                    };
-
-                    match timeout(Duration::from_secs(15), request_data).await {
-                        Ok(Ok(response)) => {
-                            if response.status() == StatusCode::OK
-                                || response.status() == StatusCode::PARTIAL_CONTENT
-                            {
-                                log::trace!(
-                                    "start download url block:{} start:{} end:{} status:{:?}",
-                                    self.inner_status.url,
-                                    self.current,
-                                    self.end,
-                                    response.headers().get(reqwest::header::CONTENT_RANGE)
-                                );
-                                if self.read_stream(response).await? {
-                                    break 're;
-                                }
-                            } else if i > 0 {
-                                log::error!(
-                                    "download url:{}  status error:{} retry:{i}",
-                                    self.inner_status.url,
-                                    response.status()
-                                );
-                            } else {
-                                return Err(DownloadError::HttpStatusError(
-                                    response.status().to_string(),
-                                ));
-                            }
-                        }
-                        Ok(Err(err)) => {
-                            if i > 0 {
-                                log::error!(
-                                    "download url:{} error:{err} retry:{i}",
-                                    self.inner_status.url
-                                );
-                            } else {
-                                return Err(DownloadError::ReqwestError { source: err });
-                            }
-                        }
-                        Err(_) => {
-                            log::warn!("get url:{} response time out", self.inner_status.url);
-                        }
-                    }
-                }
+    pub fn with_client(client: Client) -> Self {
+        Self { client }
+    }
+}
+
+/// check response file size and etag same as first request
+#[inline]
+fn check_remote_not_changed(status: &DownloadInner, response: &Response) -> Result<()> {
+    if !status.is_check_change() {
+        return Ok(());
+    }
+    let headers = response.headers();
+    let total = if response.status() == StatusCode::PARTIAL_CONTENT {
+        parse_content_range_total(headers)
+    } else {
+        response.content_length()
+    };
+    let is_changed = total.is_some_and(|total| total != status.get_remote_size())
+        || match (status.get_validator(), headers.get(reqwest::header::ETAG)) {
+            (Some(validator), Some(etag)) => {
+                validator.starts_with('"') && etag.as_bytes() != validator.as_bytes()
             }
-        }
+            _ => false,
+        };
+    if is_changed {
+        log::error!(
+            "download url:{} remote file changed,total:{:?} etag:{:?}",
+            status.url,
+            total,
+            headers.get(reqwest::header::ETAG)
+        );
+        Err(DownloadError::RemoteChanged(status.url.clone()))
+    } else {
         Ok(())
     }
+}
+
+/// get remote file info from response headers
+#[inline]
+fn remote_info(size: u64, headers: &HeaderMap) -> RemoteInfo {
+    RemoteInfo {
+        size,
+        file_name: parse_content_filename(headers),
+        validator: parse_validator(headers),
+        sha256: None,
+        pieces: None,
+        cache_policy: CachePolicy::from_headers(headers),
+        body: None,
+    }
+}
+
+/// get remote file info from whole file response
+#[inline]
+pub(crate) fn probe_response(url: &Url, response: Response) -> Result<RemoteInfo> {
+    if response.status() == StatusCode::OK {
+        let size = parse_content_length(response.headers())
+            .ok_or_else(|| DownloadError::NotGetFileSize(url.clone()))?;
+        let mut info = remote_info(size, response.headers());
+        info.body = Some(body_stream(response));
+        Ok(info)
+    } else {
+        Err(DownloadError::http_status(
+            url,
+            response.status(),
+            response.headers(),
+            None,
+        ))
+    }
+}
+
+/// check range response status and remote file not changed,return data stream
+#[inline]
+pub(crate) fn range_response(
+    status: &DownloadInner,
+    start: u64,
+    end: u64,
+    response: Response,
+) -> Result<ByteStream> {
+    if response.status() == StatusCode::PARTIAL_CONTENT
+        || (response.status() == StatusCode::OK
+            && start == 0
+            && end + 1 == status.get_remote_size())
+    {
+        check_remote_not_changed(status, &response)?;
+        log::trace!(
+            "open url:{} range:{:?}",
+            status.url,
+            response.headers().get(reqwest::header::CONTENT_RANGE)
+        );
+        Ok(body_stream(response))
+    } else if response.status() == StatusCode::OK {
+        // if-range not match,server send whole new file
+        if status.get_validator().is_some() {
+            Err(DownloadError::RemoteChanged(status.url.clone()))
+        } else {
+            Err(DownloadError::RangeNotSupported(status.url.clone()))
+        }
+    } else if status.is_check_change()
+        && (response.status() == StatusCode::RANGE_NOT_SATISFIABLE
+            || response.status() == StatusCode::PRECONDITION_FAILED)
+    {
+        Err(DownloadError::RemoteChanged(status.url.clone()))
+    } else {
+        Err(DownloadError::http_status(
+            &status.url,
+            response.status(),
+            response.headers(),
+            Some((start, end)),
+        ))
+    }
+}
 
+#[async_trait::async_trait]
+impl ITransport for ReqwestTransport {
     #[inline]
-    pub async fn run_once(&mut self, response: Response) -> Result<()> {
-        if !self.read_stream(response).await? {
-            self.run().await
+    async fn probe(&self, url: &Url) -> Result<RemoteInfo> {
+        let response = self.client.get(url.as_str()).send().await?;
+        probe_response(url, response)
+    }
+
+    /// etag send "If-None-Match",last modified send "If-Modified-Since"
+    #[inline]
+    async fn probe_if_modified(&self, url: &Url, validator: &str) -> Result<Revalidation> {
+        let header = if validator.starts_with('"') || validator.starts_with("W/") {
+            reqwest::header::IF_NONE_MATCH
         } else {
-            Ok(())
+            reqwest::header::IF_MODIFIED_SINCE
+        };
+        let response = self
+            .client
+            .get(url.as_str())
+            .header(header, validator)
+            .send()
+            .await?;
+        if response.status() == StatusCode::NOT_MODIFIED {
+            log::trace!("url:{} not modified of validator:{}", url, validator);
+            return Ok(Revalidation::NotModified(CachePolicy::from_headers(
+                response.headers(),
+            )));
         }
+        probe_response(url, response).map(Revalidation::Modified)
     }
 
+    /// head request,server not support head get first byte by range request
     #[inline]
-    async fn read_stream(&mut self, response: Response) -> Result<bool> {
-        let mut stream = response.bytes_stream();
-        let is_finish = loop {
-            match timeout(Duration::from_secs(10), stream.next()).await {
-                Ok(Some(Ok(buf))) => {
-                    self.save_file
-                        .write_all_by_offset(&buf, self.current)
-                        .await?;
-                    let len = buf.len() as u64;
-                    self.current += len;
-                    self.inner_status.add_down_size(len);
-                    if !self.inner_status.is_start() {
-                        log::debug!("is suspend");
-                        break false;
-                    }
-                }
-                Ok(Some(Err(err))) => {
-                    log::error!(
-                        "download url:{} buff is error:{}",
-                        self.inner_status.url,
-                        err
-                    );
-                    break false;
-                }
-                Ok(None) => {
-                    log::trace!(
-                        "download url:{} block:{}-{} response close",
-                        self.inner_status.url,
-                        self.start,
-                        self.end
-                    );
-                    break true;
-                }
-                Err(_) => {
-                    log::warn!("download url:{} time out", self.inner_status.url);
-                    break false;
-                }
+    async fn probe_head(&self, url: &Url) -> Result<RemoteInfo> {
+        let response = self.client.head(url.as_str()).send().await?;
+        if response.status() == StatusCode::OK {
+            if let Some(size) = parse_content_length(response.headers()) {
+                return Ok(remote_info(size, response.headers()));
             }
-        };
-        Ok(is_finish)
+        }
+        log::trace!(
+            "head url:{} status:{} not get size,request first byte",
+            url,
+            response.status()
+        );
+        let response = self
+            .client
+            .get(url.as_str())
+            .header(reqwest::header::RANGE, "bytes=0-0")
+            .send()
+            .await?;
+        // empty file reply range not satisfiable with "bytes */0"
+        if response.status() == StatusCode::PARTIAL_CONTENT
+            || response.status() == StatusCode::RANGE_NOT_SATISFIABLE
+        {
+            if let Some(size) = parse_content_range_total(response.headers()) {
+                return Ok(remote_info(size, response.headers()));
+            }
+        }
+        let mut info = probe_response(url, response)?;
+        info.body = None;
+        Ok(info)
+    }
+
+    #[inline]
+    async fn open_range(&self, status: &DownloadInner, start: u64, end: u64) -> Result<ByteStream> {
+        let mut request = self
+            .client
+            .get(status.url.as_str())
+            .header(reqwest::header::RANGE, format!("bytes={}-{}", start, end));
+        if let Some(validator) = status.get_validator() {
+            request = request.header(reqwest::header::IF_RANGE, validator);
+        }
+        let response = request.send().await?;
+        range_response(status, start, end, response)
     }
 }
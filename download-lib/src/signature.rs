@@ -0,0 +1,179 @@
+use super::checksum::load_text;
+use super::error::{DownloadError, Result};
+use minisign_verify::{PublicKey, Signature};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+/// verify file read buffer size
+const READ_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// minisign detached signature of download file,
+/// verify before temp file rename to real path
+#[derive(Debug, Clone)]
+pub struct MinisignCheck {
+    /// base64 public key,minisign.pub file content or path
+    pub public_key: String,
+    /// signature url or local path,like "https://example.com/app.tar.gz.minisig"
+    pub signature: String,
+}
+
+/// decoded minisign public key and signature
+#[derive(Clone)]
+pub struct MinisignVerifier {
+    public_key: PublicKey,
+    signature: Signature,
+}
+
+#[inline]
+fn invalid(err: minisign_verify::Error) -> DownloadError {
+    DownloadError::InvalidSignature(err.to_string())
+}
+
+impl MinisignCheck {
+    #[inline]
+    pub fn new(public_key: String, signature: String) -> Self {
+        Self {
+            public_key,
+            signature,
+        }
+    }
+
+    /// load public key and download signature
+    #[inline]
+    pub async fn resolve(&self) -> Result<MinisignVerifier> {
+        let public_key = if !self.public_key.contains('\n') && Path::new(&self.public_key).is_file()
+        {
+            tokio::fs::read_to_string(&self.public_key).await?
+        } else {
+            self.public_key.clone()
+        };
+        let signature = load_text(&self.signature).await?;
+        MinisignVerifier::new(&public_key, &signature)
+    }
+}
+
+impl MinisignVerifier {
+    /// public key is base64 or minisign.pub file content,signature is minisig file content
+    #[inline]
+    pub fn new(public_key: &str, signature: &str) -> Result<Self> {
+        let public_key = public_key.trim();
+        let public_key = if public_key.contains('\n') {
+            PublicKey::decode(public_key)
+        } else {
+            PublicKey::from_base64(public_key)
+        }
+        .map_err(invalid)?;
+        Ok(Self {
+            public_key,
+            signature: Signature::decode(signature).map_err(invalid)?,
+        })
+    }
+
+    /// trusted comment of signature,signed with file
+    #[inline]
+    pub fn trusted_comment(&self) -> &str {
+        self.signature.trusted_comment()
+    }
+
+    /// verify signature of file,legacy signature read whole file to memory
+    #[inline]
+    pub fn verify_file(&self, path: &Path) -> Result<()> {
+        let mut file = File::open(path)?;
+        match self.public_key.verify_stream(&self.signature) {
+            Ok(mut verifier) => {
+                let mut buf = vec![0; READ_BUFFER_SIZE];
+                loop {
+                    let len = file.read(&mut buf)?;
+                    if len == 0 {
+                        break;
+                    }
+                    verifier.update(&buf[..len]);
+                }
+                verifier.finalize().map_err(invalid)?;
+            }
+            Err(minisign_verify::Error::UnsupportedLegacyMode) => {
+                let mut data = vec![];
+                file.read_to_end(&mut data)?;
+                self.public_key
+                    .verify(&data, &self.signature, true)
+                    .map_err(invalid)?;
+            }
+            Err(err) => return Err(invalid(err)),
+        }
+        log::trace!(
+            "verify signature of file:{:?} ok,trusted comment:{}",
+            path,
+            self.trusted_comment()
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::MinisignVerifier;
+    use crate::error::DownloadError;
+    use std::path::PathBuf;
+
+    /// signed file data,key and signatures make by ed25519 of fixed seed
+    pub(crate) const DATA: &[u8] = b"minisign test data\n";
+    pub(crate) const PUBLIC_KEY: &str = "RWQBI0VniavN7wOhB7/zzhC+HXDdGOdLwJln5NYwm6UNXx3chmQSVTG4";
+    const OTHER_PUBLIC_KEY: &str = "RWT+3LqYdlQyEHm1Vi6P5lT5QHixEuipi6eQH4U65pW+1+DjkQutBJZk";
+    /// prehashed signature,blake2b-512 of data is signed
+    pub(crate) const SIGNATURE: &str = "untrusted comment: signature\n\
+        RUQBI0VniavN7zSJTGOJ6dIbxswJ0cG8S/kCnjTh2D8gNiKHbxog8x4C9qoaTB/c4b3OXoF9YIu8w8TwV3551EpKFfqvP71H9gY=\n\
+        trusted comment: timestamp:0\tfile:test.txt\n\
+        6UY+seu7xjHWZ0pOSp1AROkaMsWiOo8QMQiTZ24fqoKHm8VIMewp/rdn0ZlDAlpILCI3+nfQ/H6+Nz9UZrG2Dg==\n";
+    /// legacy signature,data is signed
+    const LEGACY_SIGNATURE: &str = "untrusted comment: signature\n\
+        RWQBI0VniavN73AupZq1Z3OjWobPkPRjAhpM2RN4JnF3Vg6jh9RocSCK0j65hhEFj5dbpp85dAbNWsny+5WkTtIx3/qFUXXtpgU=\n\
+        trusted comment: timestamp:0\tfile:test.txt\n\
+        L+oQZoE34GttMnk8bBy2lOjUMrze7VNT+O9G5Ssub+45nerdoChN0iuqWkw3DXiFRLYXY1bmwjzg9z9h6BN4AQ==\n";
+
+    /// write data to temp file of name
+    fn temp_file(name: &str, data: &[u8]) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("signature-test-{}-{}", std::process::id(), name));
+        std::fs::write(&path, data).unwrap();
+        path
+    }
+
+    fn verify(public_key: &str, signature: &str, name: &str, data: &[u8]) -> crate::Result<()> {
+        let path = temp_file(name, data);
+        let result = MinisignVerifier::new(public_key, signature)?.verify_file(&path);
+        std::fs::remove_file(&path).unwrap();
+        result
+    }
+
+    #[test]
+    fn verify_valid_signature() {
+        verify(PUBLIC_KEY, SIGNATURE, "prehashed", DATA).unwrap();
+        verify(PUBLIC_KEY, LEGACY_SIGNATURE, "legacy", DATA).unwrap();
+        // minisign.pub file content
+        let public_key = format!("untrusted comment: minisign public key\n{}\n", PUBLIC_KEY);
+        verify(&public_key, SIGNATURE, "pub-file", DATA).unwrap();
+        let verifier = MinisignVerifier::new(PUBLIC_KEY, SIGNATURE).unwrap();
+        assert_eq!(verifier.trusted_comment(), "timestamp:0\tfile:test.txt");
+    }
+
+    #[test]
+    fn reject_tampered_file() {
+        let tampered = b"minisign test data!";
+        for (name, signature) in [
+            ("tampered", SIGNATURE),
+            ("tampered-legacy", LEGACY_SIGNATURE),
+        ] {
+            let result = verify(PUBLIC_KEY, signature, name, tampered);
+            assert!(matches!(result, Err(DownloadError::InvalidSignature(_))));
+        }
+    }
+
+    #[test]
+    fn reject_wrong_key() {
+        let result = verify(OTHER_PUBLIC_KEY, SIGNATURE, "wrong-key", DATA);
+        assert!(matches!(result, Err(DownloadError::InvalidSignature(_))));
+        assert!(MinisignVerifier::new("not key", SIGNATURE).is_err());
+        assert!(MinisignVerifier::new(PUBLIC_KEY, "not signature").is_err());
+    }
+}
@@ -0,0 +1,246 @@
+use super::error::{DownloadError, Result};
+use super::ftp_file::FtpTransport;
+use super::http_cache::CachePolicy;
+use super::local_file::{DataTransport, FileTransport};
+use super::oci_file::OciTransport;
+use super::piece_hash::PieceHashes;
+use super::reqwest_file::ReqwestTransport;
+use super::s3_file::S3Transport;
+#[cfg(feature = "sftp")]
+use super::sftp_file::SftpTransport;
+use super::DownloadInner;
+use bytes::Bytes;
+use futures_util::Stream;
+use reqwest::Url;
+use std::pin::Pin;
+use std::sync::Arc;
+
+/// remote data stream
+pub type ByteStream = Pin<Box<dyn Stream<Item = Result<Bytes>> + Send>>;
+
+/// remote file info of probe
+pub struct RemoteInfo {
+    /// remote file size
+    pub size: u64,
+    /// file name from remote,none is use url last path
+    pub file_name: Option<String>,
+    /// etag or last modified,use check remote file not changed
+    pub validator: Option<String>,
+    /// sha256 hex of whole file if remote know it,verify before finish
+    pub sha256: Option<String>,
+    /// piece hashes of whole file,verify every piece before finish
+    pub pieces: Option<PieceHashes>,
+    /// http caching policy of response,fresh cache file reuse without request
+    pub cache_policy: CachePolicy,
+    /// whole file data stream if probe already open it,
+    /// single task download read it without new request
+    pub body: Option<ByteStream>,
+}
+
+/// result of probe with cached validator
+pub enum Revalidation {
+    /// remote not modified,caching policy of not modified response
+    NotModified(CachePolicy),
+    /// remote modified or transport not support conditional request
+    Modified(RemoteInfo),
+}
+
+/// remote file transport,
+/// get file info and open range data stream,
+/// segment,retry,save and progress is same for all transport
+#[async_trait::async_trait]
+pub trait ITransport: Send + Sync {
+    /// get remote file size,file name and validator
+    async fn probe(&self, url: &Url) -> Result<RemoteInfo>;
+
+    /// get remote file info without data,use to poll remote file size,
+    /// default is probe and drop body
+    async fn probe_head(&self, url: &Url) -> Result<RemoteInfo> {
+        let mut info = self.probe(url).await?;
+        info.body = None;
+        Ok(info)
+    }
+
+    /// probe with validator of cached file,
+    /// default is probe without condition
+    async fn probe_if_modified(&self, url: &Url, _validator: &str) -> Result<Revalidation> {
+        self.probe(url).await.map(Revalidation::Modified)
+    }
+
+    /// open data stream of start..=end,
+    /// status have url,remote size and validator,
+    /// return remote changed error if remote file not same as probe
+    async fn open_range(&self, status: &DownloadInner, start: u64, end: u64) -> Result<ByteStream>;
+}
+
+/// value can convert to download url,
+/// not like reqwest into url,url without host is allowed,like file: and data:
+pub trait IntoDownloadUrl {
+    /// parse to url
+    fn into_download_url(self) -> Result<Url>;
+}
+
+impl IntoDownloadUrl for Url {
+    #[inline]
+    fn into_download_url(self) -> Result<Url> {
+        Ok(self)
+    }
+}
+
+impl IntoDownloadUrl for &Url {
+    #[inline]
+    fn into_download_url(self) -> Result<Url> {
+        Ok(self.clone())
+    }
+}
+
+impl IntoDownloadUrl for &str {
+    #[inline]
+    fn into_download_url(self) -> Result<Url> {
+        Url::parse(self).map_err(|err| DownloadError::InvalidUrl(format!("{}:{}", self, err)))
+    }
+}
+
+impl IntoDownloadUrl for String {
+    #[inline]
+    fn into_download_url(self) -> Result<Url> {
+        self.as_str().into_download_url()
+    }
+}
+
+impl IntoDownloadUrl for &String {
+    #[inline]
+    fn into_download_url(self) -> Result<Url> {
+        self.as_str().into_download_url()
+    }
+}
+
+/// get default transport of url scheme
+#[inline]
+pub(crate) fn transport_for_url(url: &Url) -> Arc<dyn ITransport> {
+    match url.scheme() {
+        "ftp" | "ftps" => Arc::new(FtpTransport::new()),
+        "file" => Arc::new(FileTransport),
+        "data" => Arc::new(DataTransport),
+        "s3" => Arc::new(S3Transport::from_env()),
+        "oci" => Arc::new(OciTransport::new()),
+        #[cfg(feature = "sftp")]
+        "sftp" => Arc::new(SftpTransport::new()),
+        _ => Arc::new(ReqwestTransport::new()),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::{ByteStream, ITransport, RemoteInfo};
+    use crate::error::{DownloadError, Result};
+    use crate::DownloadInner;
+    use bytes::Bytes;
+    use reqwest::Url;
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    /// behavior of one open range request
+    pub(crate) enum Open {
+        /// reply data after delay
+        Delay(Duration),
+        /// reply not retryable error after delay
+        Fail(Duration),
+        Panic,
+    }
+
+    /// transport of memory data,
+    /// open range behavior is set by start of range and used once
+    pub(crate) struct MemoryTransport {
+        pub data: Bytes,
+        pub opens: Mutex<HashMap<u64, Vec<Open>>>,
+        /// requested ranges,end is included
+        pub requests: Mutex<Vec<(u64, u64)>>,
+        pub probe_count: AtomicU32,
+        pub head_count: AtomicU32,
+    }
+
+    impl MemoryTransport {
+        pub fn new(data: impl Into<Bytes>) -> Self {
+            Self {
+                data: data.into(),
+                opens: Default::default(),
+                requests: Default::default(),
+                probe_count: Default::default(),
+                head_count: Default::default(),
+            }
+        }
+
+        /// add behavior of next open range of start
+        pub fn on_open(self, start: u64, open: Open) -> Self {
+            self.opens
+                .lock()
+                .unwrap()
+                .entry(start)
+                .or_default()
+                .push(open);
+            self
+        }
+
+        fn info(&self) -> RemoteInfo {
+            RemoteInfo {
+                size: self.data.len() as u64,
+                file_name: None,
+                validator: None,
+                sha256: None,
+                pieces: None,
+                cache_policy: Default::default(),
+                body: None,
+            }
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl ITransport for MemoryTransport {
+        async fn probe(&self, _url: &Url) -> Result<RemoteInfo> {
+            self.probe_count.fetch_add(1, Ordering::AcqRel);
+            Ok(self.info())
+        }
+
+        async fn probe_head(&self, _url: &Url) -> Result<RemoteInfo> {
+            self.head_count.fetch_add(1, Ordering::AcqRel);
+            Ok(self.info())
+        }
+
+        async fn open_range(
+            &self,
+            status: &DownloadInner,
+            start: u64,
+            end: u64,
+        ) -> Result<ByteStream> {
+            self.requests.lock().unwrap().push((start, end));
+            let open = self
+                .opens
+                .lock()
+                .unwrap()
+                .get_mut(&start)
+                .and_then(|opens| {
+                    if opens.is_empty() {
+                        None
+                    } else {
+                        Some(opens.remove(0))
+                    }
+                });
+            match open {
+                Some(Open::Delay(delay)) => tokio::time::sleep(delay).await,
+                Some(Open::Fail(delay)) => {
+                    tokio::time::sleep(delay).await;
+                    return Err(DownloadError::RangeNotSupported(status.url.clone()));
+                }
+                Some(Open::Panic) => panic!("open range start:{}", start),
+                None => {}
+            }
+            let data = self.data.slice(start as usize..end as usize + 1);
+            Ok(Box::pin(futures_util::stream::once(
+                async move { Ok(data) },
+            )))
+        }
+    }
+}
@@ -4,6 +4,35 @@
 #include <ostream>
 #include <new>
 
+/// error code returned by durl_get_state
+enum DurlErrorCode : int32_t {
+  DURL_OK = 0,
+  DURL_REQWEST_ERROR = 1,
+  DURL_IO_ERROR = 2,
+  DURL_NOT_GET_FILE_SIZE = 3,
+  DURL_SAVE_FILE_CLOSED = 4,
+  DURL_NOT_FILE_NAME = 5,
+  DURL_HTTP_STATUS = 6,
+  DURL_JOIN_ERROR = 7,
+  DURL_RANGE_NOT_SUPPORTED = 8,
+  DURL_SIZE_MISMATCH = 9,
+  DURL_CHECKSUM_MISMATCH = 10,
+  DURL_DISK_FULL = 11,
+  DURL_CANCELLED = 12,
+  DURL_CONNECT_TIMEOUT = 13,
+  DURL_READ_TIMEOUT = 14,
+  DURL_SIZE_LIMIT_EXCEEDED = 15,
+  DURL_INVALID_RANGE = 16,
+  DURL_APPEND_MISMATCH = 17,
+  DURL_REMOTE_CHANGED = 18,
+  DURL_FTP_STATUS = 19,
+  DURL_INVALID_URL = 20,
+  DURL_INVALID_SIGNATURE = 21,
+  DURL_CHECKSUM_NOT_FOUND = 22,
+  DURL_UNSAFE_ARCHIVE_ENTRY = 23,
+  DURL_INVALID_ARGUMENT = 24,
+};
+
 /// Download handler context
 struct DownloadHandler;
 
@@ -41,6 +70,9 @@ uint32_t durl_get_state(const DownloadHandler *handler,
                         uint64_t *down_size,
                         int32_t *err_code);
 
+/// get error is transient,retry download may succeed
+bool durl_is_retryable(const DownloadHandler *handler, uint64_t key);
+
 /// # Safety
 /// get error msg string
 void durl_get_error_str(const DownloadHandler *handler, uint64_t key, char *msg);
@@ -166,6 +166,22 @@ pub extern "C" fn durl_get_state(
     }
 }
 
+/// get error is transient,retry download may succeed
+#[no_mangle]
+pub extern "C" fn durl_is_retryable(handler: &DownloadHandler, key: u64) -> bool {
+    if let Some(item) = handler.items.get(key as usize) {
+        if let Some(err) = item.error.get() {
+            err.is_retryable()
+        } else if let Some(download) = item.down_core.get() {
+            download.get_error().is_some_and(|err| err.is_retryable())
+        } else {
+            false
+        }
+    } else {
+        false
+    }
+}
+
 /// # Safety
 /// get error msg string
 #[no_mangle]
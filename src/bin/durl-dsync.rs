@@ -0,0 +1,46 @@
+use anyhow::Result;
+use download_lib::DeltaControl;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+/// generate delta control file of local file,
+/// durl use it to download only changed blocks
+fn main() -> Result<()> {
+    let opt = Opt::from_args();
+    let mut control = DeltaControl::generate(&opt.file, opt.block_size)?;
+    control.url = opt.url;
+    let output = opt.output.unwrap_or_else(|| {
+        let mut path = opt.file.clone().into_os_string();
+        path.push(".dsync");
+        PathBuf::from(path)
+    });
+    control.save(&output)?;
+    println!(
+        "{} blocks:{} sha256:{} save to {:?}",
+        opt.file.display(),
+        control.blocks.len(),
+        control.sha256,
+        output
+    );
+    Ok(())
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "durl-dsync")]
+struct Opt {
+    /// local file
+    #[structopt(parse(from_os_str))]
+    file: PathBuf,
+
+    /// block size of signatures
+    #[structopt(short = "b", long, default_value = "4096")]
+    block_size: u64,
+
+    /// remote url of file write to control file
+    #[structopt(short = "u", long)]
+    url: Option<String>,
+
+    /// control file path,default is "<file>.dsync"
+    #[structopt(short = "o", long, parse(from_os_str))]
+    output: Option<PathBuf>,
+}
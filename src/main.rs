@@ -1,12 +1,23 @@
 use anyhow::Result;
-use download_lib::DownloadFile;
+use download_lib::{
+    decompress_file, download_decompress, download_hls, download_metalink, download_s3_prefix,
+    download_stream, follow_append, ByteRange, ChecksumSource, DecompressStatus, DeltaControl,
+    DownloadCache, DownloadFile, DownloadOptions, ExtractOptions, HlsStatus, MinisignCheck,
+    PieceManifest, S3Transport,
+};
 use log::LevelFilter;
 use std::path::PathBuf;
+use std::sync::Arc;
 use std::time::Duration;
 use structopt::StructOpt;
+use tokio::io::AsyncWriteExt;
+use url::Url;
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    if std::env::args().nth(1).as_deref() == Some("cache") {
+        return run_cache(CacheOpt::from_iter(std::env::args().skip(1)));
+    }
     let opt = Opt::from_args();
 
     env_logger::builder()
@@ -16,7 +27,183 @@ async fn main() -> Result<()> {
         .filter_level(LevelFilter::Trace)
         .init();
 
-    match DownloadFile::start_download(opt.url, opt.save_path, opt.tasks, 1024 * 1024).await {
+    if opt.output.as_deref() == Some("-") {
+        let options = DownloadOptions {
+            task_count: opt.tasks,
+            ..Default::default()
+        };
+        let mut stream = download_stream(opt.url, &options).await?;
+        let mut stdout = tokio::io::stdout();
+        tokio::io::copy(&mut stream, &mut stdout).await?;
+        stdout.flush().await?;
+        return Ok(());
+    }
+
+    let save_path = opt.output.map(PathBuf::from).unwrap_or(opt.save_path);
+    if opt.decompress_stream {
+        let options = DownloadOptions {
+            task_count: opt.tasks,
+            ..Default::default()
+        };
+        // compressed file save to dir of output
+        let keep = opt.keep_compressed.then(|| match save_path.parent() {
+            Some(parent) if !save_path.is_dir() => parent.to_path_buf(),
+            _ => save_path.clone(),
+        });
+        let status = Arc::new(DecompressStatus::default());
+        log_decompress_progress(status.clone());
+        let file = download_decompress(opt.url, save_path, None, keep, &options, status).await?;
+        log::info!("download and decompress finish,save to {:?}", file);
+        return Ok(());
+    }
+
+    if let Some(interval) = opt.follow {
+        let options = DownloadOptions {
+            task_count: opt.tasks,
+            ..Default::default()
+        };
+        follow_append(opt.url, save_path, &options, Duration::from_secs(interval)).await?;
+        return Ok(());
+    }
+
+    if opt.url.starts_with("s3://") && opt.url.ends_with('/') {
+        let options = DownloadOptions {
+            task_count: opt.tasks,
+            ..Default::default()
+        };
+        for file in
+            download_s3_prefix(opt.url, save_path, S3Transport::from_env(), &options).await?
+        {
+            log::info!("download finish,save to {:?}", file);
+        }
+        return Ok(());
+    }
+
+    if opt.url.ends_with(".meta4") || opt.url.ends_with(".metalink") {
+        let options = DownloadOptions {
+            task_count: opt.tasks,
+            location: opt.location,
+            ..Default::default()
+        };
+        // local metalink file path
+        let url = match std::fs::canonicalize(&opt.url) {
+            Ok(path) => Url::from_file_path(path)
+                .map_err(|_| anyhow::anyhow!("invalid metalink path:{}", opt.url))?
+                .to_string(),
+            Err(_) => opt.url,
+        };
+        for file in download_metalink(url, save_path, &options).await? {
+            log::info!("download finish,save to {:?}", file);
+        }
+        return Ok(());
+    }
+
+    if opt
+        .url
+        .split(['?', '#'])
+        .next()
+        .unwrap_or_default()
+        .ends_with(".m3u8")
+    {
+        let options = DownloadOptions {
+            task_count: opt.tasks,
+            ..Default::default()
+        };
+        let status = Arc::new(HlsStatus::default());
+        log_hls_progress(status.clone());
+        let file = download_hls(opt.url, save_path, opt.quality.parse()?, &options, status).await?;
+        log::info!("download finish,save to {:?}", file);
+        return Ok(());
+    }
+
+    if let Some(delta) = opt.delta {
+        let old_file = opt
+            .old
+            .ok_or_else(|| anyhow::anyhow!("delta download need old file"))?;
+        // local control file path or url
+        let control = match std::fs::read(&delta) {
+            Ok(data) => DeltaControl::parse(&data)?,
+            Err(_) => DeltaControl::load(delta.as_str()).await?,
+        };
+        let download = DownloadFile::start_delta(
+            opt.url,
+            &control,
+            old_file,
+            save_path,
+            opt.tasks,
+            1024 * 1024,
+        )
+        .await?;
+        download.wait_finish().await;
+        match download.get_error() {
+            None => log::info!(
+                "url {} delta download finish,reuse {} bytes,save to {}",
+                download.url(),
+                download.get_save().reuse_size(),
+                download.get_save().get_real_file_path()
+            ),
+            Some(err) => log::info!("url {} download is error:{}", download.url(), err),
+        }
+        return Ok(());
+    }
+
+    let download = if opt.append {
+        DownloadFile::start_sync_append(
+            opt.url,
+            save_path,
+            DownloadOptions::default().append_overlap,
+            opt.tasks,
+            1024 * 1024,
+        )
+        .await
+    } else if let Some(range) = opt.range {
+        let ranges = ByteRange::parse_list(&range)?;
+        DownloadFile::start_download_ranges(opt.url, save_path, &ranges, opt.tasks, 1024 * 1024)
+            .await
+    } else {
+        let pieces = match &opt.pieces {
+            Some(path) => Some(PieceManifest::load(path)?.pieces),
+            None => None,
+        };
+        let cache = match opt.cache_dir {
+            Some(dir) => {
+                let cache = DownloadCache::new(dir);
+                Some(match opt.cache_max_size {
+                    Some(max_size) => cache.max_size(max_size),
+                    None => cache,
+                })
+            }
+            None => DownloadCache::from_env(),
+        };
+        // signature default is "<url>.minisig"
+        let signature = opt.minisign_key.map(|key| {
+            let signature = opt
+                .signature
+                .unwrap_or_else(|| format!("{}.minisig", opt.url));
+            MinisignCheck::new(key, signature)
+        });
+        let options = DownloadOptions {
+            task_count: opt.tasks,
+            pieces,
+            piece_manifest: opt.piece_manifest,
+            cache,
+            signature,
+            checksum: opt.checksums.map(|checksums| match checksums.as_str() {
+                "auto" => ChecksumSource::Discover,
+                _ => ChecksumSource::Manifest(checksums),
+            }),
+            extract: opt.extract.clone().map(|dir| ExtractOptions {
+                dir,
+                strip_components: opt.strip_components,
+                include: opt.include,
+                exclude: opt.exclude,
+                format: None,
+            }),
+            ..Default::default()
+        };
+        DownloadFile::start_download_with_options(opt.url, save_path, &options).await
+    };
+    match download {
         Ok(download) => {
             let status = download.get_status();
             //  tokio::spawn(async move{
@@ -51,6 +238,19 @@ async fn main() -> Result<()> {
                     status.url(),
                     download.get_real_file_path()
                 );
+                if let Some(dir) = &opt.extract {
+                    log::info!("extract finish to {:?}", dir);
+                }
+                if opt.decompress {
+                    let file = PathBuf::from(download.get_real_file_path());
+                    let dir = file.parent().map(|x| x.to_path_buf()).unwrap_or_default();
+                    let status = Arc::new(DecompressStatus::default());
+                    log_decompress_progress(status.clone());
+                    match decompress_file(&file, dir, None, opt.keep_compressed, status).await {
+                        Ok(file) => log::info!("decompress finish,save to {:?}", file),
+                        Err(err) => log::error!("decompress fail:{}", err),
+                    }
+                }
             } else {
                 log::info!(
                     "url {} download is error:{}",
@@ -67,11 +267,47 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
+/// log decompress progress every second until finish
+#[inline]
+fn log_decompress_progress(status: Arc<DecompressStatus>) {
+    tokio::spawn(async move {
+        while !status.is_finish() {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            log::info!(
+                "decompress progress:{}% {} K",
+                status.get_percent_complete(),
+                status.get_written_size() / 1024
+            );
+        }
+    });
+}
+
+/// log hls progress every second until finish
+#[inline]
+fn log_hls_progress(status: Arc<HlsStatus>) {
+    tokio::spawn(async move {
+        while !status.is_finish() {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            log::info!(
+                "hls progress:{}% {}/{} segments {} K",
+                status.get_percent_complete(),
+                status.get_downloaded_count(),
+                status.get_segment_count(),
+                status.get_written_size() / 1024
+            );
+        }
+    });
+}
+
 // A basic example
 #[derive(StructOpt, Debug)]
 #[structopt(name = "basic")]
 struct Opt {
-    /// http url,http server need support range
+    /// http,ftp,sftp,s3,oci,file or data url,http server need support range,
+    /// s3 url end with "/" download all objects of prefix,
+    /// oci url like "oci://registry/repository@sha256:digest" download blob,
+    /// url or path end with ".meta4" or ".metalink" download files of metalink,
+    /// url end with ".m3u8" download hls segments to one file
     #[structopt(short = "u", long)]
     url: String,
 
@@ -79,7 +315,161 @@ struct Opt {
     #[structopt(short = "s", long, parse(from_os_str), default_value = "./")]
     save_path: PathBuf,
 
+    /// output file path,"-" is write to stdout in order while downloading
+    #[structopt(short = "o", long)]
+    output: Option<String>,
+
+    /// only download byte ranges,like "0-1023,-500",save to file one by one
+    #[structopt(short = "r", long)]
+    range: Option<String>,
+
+    /// only download new bytes of growing remote file and append to local file
+    #[structopt(long)]
+    append: bool,
+
+    /// keep append new bytes of growing remote file,poll remote file every seconds
+    #[structopt(long)]
+    follow: Option<u64>,
+
+    /// preferred mirror location of metalink,like "de"
+    #[structopt(long)]
+    location: Option<String>,
+
+    /// hls variant of master playlist,"highest","lowest" or max bandwidth
+    #[structopt(long, default_value = "highest")]
+    quality: String,
+
+    /// piece manifest file,verify every piece and download mismatch pieces again
+    #[structopt(long, parse(from_os_str))]
+    pieces: Option<PathBuf>,
+
+    /// save sha256 piece manifest of piece length to "<file>.pieces" after download
+    #[structopt(long)]
+    piece_manifest: Option<u64>,
+
+    /// delta control file path or url,copy same blocks from old file,
+    /// only download missing blocks
+    #[structopt(long)]
+    delta: Option<String>,
+
+    /// old local file of delta download
+    #[structopt(long, parse(from_os_str))]
+    old: Option<PathBuf>,
+
+    /// local download cache dir,use verified cache file instead of download,
+    /// manage it by "durl cache",default is env DURL_CACHE_DIR
+    #[structopt(long, parse(from_os_str))]
+    cache_dir: Option<PathBuf>,
+
+    /// max total size of download cache,evict least recently used files
+    #[structopt(long)]
+    cache_max_size: Option<u64>,
+
+    /// minisign public key,base64 or minisign.pub file path,verify signature before save
+    #[structopt(long)]
+    minisign_key: Option<String>,
+
+    /// minisign signature url or path,default is "<url>.minisig"
+    #[structopt(long)]
+    signature: Option<String>,
+
+    /// sha256 checksum manifest url or path like "SHA256SUMS",
+    /// "auto" is try "<url>.sha256",verify file before save
+    #[structopt(long)]
+    checksums: Option<String>,
+
+    /// decompress .gz,.zst,.xz or .bz2 file after download
+    #[structopt(long)]
+    decompress: bool,
+
+    /// download in order and decompress while streaming
+    #[structopt(long)]
+    decompress_stream: bool,
+
+    /// keep compressed file of decompress
+    #[structopt(long)]
+    keep_compressed: bool,
+
+    /// extract tar,compressed tar or zip archive to dir after download,
+    /// replace same name entries of dir
+    #[structopt(long, parse(from_os_str))]
+    extract: Option<PathBuf>,
+
+    /// remove number of leading path components of extract entries
+    #[structopt(long, default_value = "0")]
+    strip_components: usize,
+
+    /// only extract entries match glob,can repeat
+    #[structopt(long, number_of_values = 1)]
+    include: Vec<String>,
+
+    /// skip extract entries match glob,can repeat
+    #[structopt(long, number_of_values = 1)]
+    exclude: Vec<String>,
+
     /// number of concurrent download
     #[structopt(short = "t", long, default_value = "15")]
     tasks: u64,
 }
+
+/// inspect and prune local download cache
+#[derive(StructOpt, Debug)]
+#[structopt(name = "cache")]
+struct CacheOpt {
+    /// local download cache dir
+    #[structopt(short = "d", long, parse(from_os_str))]
+    cache_dir: PathBuf,
+
+    #[structopt(subcommand)]
+    cmd: CacheCmd,
+}
+
+#[derive(StructOpt, Debug)]
+enum CacheCmd {
+    /// show cache size and hit count
+    Stats,
+    /// list cache url entries
+    List,
+    /// evict least recently used files until total size not over max size
+    Prune {
+        #[structopt(long)]
+        max_size: u64,
+    },
+    /// check sha256 of all cache files,remove broken files
+    Verify,
+    /// remove all cache files
+    Clear,
+}
+
+#[inline]
+fn run_cache(opt: CacheOpt) -> Result<()> {
+    let cache = DownloadCache::new(opt.cache_dir);
+    match opt.cmd {
+        CacheCmd::Stats => {
+            let stats = cache.stats()?;
+            println!(
+                "entries:{} files:{} size:{} hits:{} misses:{} evictions:{}",
+                stats.entries, stats.blobs, stats.size, stats.hits, stats.misses, stats.evictions
+            );
+        }
+        CacheCmd::List => {
+            for entry in cache.entries()? {
+                println!(
+                    "{} {} {} {}",
+                    entry.sha256,
+                    entry.size,
+                    entry.validator.as_deref().unwrap_or("-"),
+                    entry.url
+                );
+            }
+        }
+        CacheCmd::Prune { max_size } => {
+            println!("freed {} bytes", cache.prune(max_size)?);
+        }
+        CacheCmd::Verify => {
+            println!("removed {} broken files", cache.verify()?);
+        }
+        CacheCmd::Clear => cache.clear()?,
+    }
+    Ok(())
+}